@@ -0,0 +1,59 @@
+//! cargo bench --bench job
+use rvn_utils::block_template::BlockTemplate;
+use rvn_utils::job::SharedJob;
+use std::sync::Arc;
+use std::time::Instant;
+
+const TXS: usize = 3_000;
+const TX_BYTES: usize = 1_000;
+const JOBS: u32 = 100;
+const ROLLS: u32 = 100_000;
+
+fn main() {
+    let template = BlockTemplate {
+        header: vec![0; 80],
+        coinbase_tx: vec![0; 200],
+        external_txs: vec!["ab".repeat(TX_BYTES); TXS],
+        ..Default::default()
+    };
+    let target = "00000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffff".to_string();
+
+    let start = Instant::now();
+    let jobs: Vec<_> = (0..JOBS)
+        .map(|_| template.create_job(target.clone(), true))
+        .collect();
+    println!("create_job: {:?} per job", start.elapsed() / JOBS);
+
+    let template = Arc::new(template);
+    let start = Instant::now();
+    let shared: Vec<_> = (0..JOBS)
+        .map(|_| SharedJob::new(template.clone(), target.clone(), true))
+        .collect();
+    println!("SharedJob::new: {:?} per job", start.elapsed() / JOBS);
+
+    assert_eq!(
+        jobs[0].build_block("00", "00"),
+        shared[0].build_block("00", "00")
+    );
+
+    // coinbase 第 60 字节起为 4 字节的 extranonce2
+    let coinbase_tx = vec![0; 200];
+    let template = BlockTemplate {
+        coinbase_prefix: coinbase_tx[..60].to_vec(),
+        coinbase_suffix: coinbase_tx[64..].to_vec(),
+        coinbase_tx,
+        extranonce2_offset: Some(60),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    for i in 0..ROLLS {
+        let coinbase_tx = template
+            .coinbase_tx_with_extranonce2(&i.to_le_bytes())
+            .unwrap();
+        assert_eq!(coinbase_tx.len(), 200);
+    }
+    println!(
+        "coinbase_tx_with_extranonce2: {:?} per roll",
+        start.elapsed() / ROLLS
+    );
+}
@@ -0,0 +1,81 @@
+//! cargo bench --bench merkle
+use rvn_utils::block_template::dsha256;
+use rvn_utils::merkle::{merkel_hash, merkle_root_from_hex};
+use rvn_utils::util::hash_from_hex;
+use std::time::Instant;
+
+const LEAVES: usize = 10_000;
+const ROUNDS: u32 = 20;
+const HEX_LEAVES: usize = 5_000;
+
+/// 每个节点都新建 hasher 并分配 Vec 的实现，作为对照
+fn merkel_hash_alloc(txids: Vec<[u8; 32]>) -> [u8; 32] {
+    let mut level = txids;
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut data = Vec::with_capacity(64);
+                data.extend_from_slice(&pair[0]);
+                data.extend_from_slice(&pair[1]);
+                dsha256(&data)
+            })
+            .collect();
+    }
+    level[0]
+}
+
+fn bench(name: &str, f: impl Fn(Vec<[u8; 32]>) -> [u8; 32], txids: &[[u8; 32]]) -> [u8; 32] {
+    let start = Instant::now();
+    let mut root = [0; 32];
+    for _ in 0..ROUNDS {
+        root = f(txids.to_vec());
+    }
+    println!("{}: {:?} per tree", name, start.elapsed() / ROUNDS);
+    root
+}
+
+fn main() {
+    let txids: Vec<[u8; 32]> = (0..LEAVES as u32)
+        .map(|i| dsha256(&i.to_le_bytes()))
+        .collect();
+    let a = bench("merkel_hash (reused hasher)", merkel_hash, &txids);
+    let b = bench("merkel_hash (alloc per node)", merkel_hash_alloc, &txids);
+    assert_eq!(a, b);
+    // cargo bench --bench merkle --features rayon
+    #[cfg(feature = "rayon")]
+    {
+        let c = bench(
+            "merkle_root_parallel",
+            rvn_utils::merkle::merkle_root_parallel,
+            &txids,
+        );
+        assert_eq!(a, c);
+    }
+
+    let hexes: Vec<String> = txids[..HEX_LEAVES].iter().map(hex::encode).collect();
+    let hexes: Vec<&str> = hexes.iter().map(String::as_str).collect();
+    let start = Instant::now();
+    let mut a = [0; 32];
+    for _ in 0..ROUNDS {
+        let leaves = hexes.iter().map(|s| hash_from_hex(s).unwrap()).collect();
+        a = merkel_hash(leaves);
+    }
+    println!(
+        "hash_from_hex + merkel_hash: {:?} per tree",
+        start.elapsed() / ROUNDS
+    );
+    let start = Instant::now();
+    let mut b = [0; 32];
+    for _ in 0..ROUNDS {
+        b = merkle_root_from_hex(&hexes).unwrap();
+    }
+    println!(
+        "merkle_root_from_hex: {:?} per tree",
+        start.elapsed() / ROUNDS
+    );
+    assert_eq!(a, b);
+}
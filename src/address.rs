@@ -1,36 +1,172 @@
-use anyhow::Error;
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Error, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+use crate::error::RvnError;
+
+/// 地址类型
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum AddrKind {
+    #[default]
+    P2PKH,
+    P2SH,
+}
+
+/// 地址所属网络
+///
+/// regtest 与 testnet 使用相同的版本号，地址中统一视为 Test
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Network {
+    #[default]
+    Main,
+    Test,
+    Regtest,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
 pub struct Address {
     inner: String,
-    testnet: bool,
+    network: Network,
+    kind: AddrKind,
 }
 
 impl FromStr for Address {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let checker = bs58::decode(&s).with_check(None).into_vec()?;
-        let testnet = checker[0] != 60;
+        let checker = bs58::decode(&s)
+            .with_check(None)
+            .into_vec()
+            .map_err(|e| RvnError::InvalidAddress(format!("{}: {}", s, e)))?;
+        let (network, kind) = match checker.first() {
+            Some(60) => (Network::Main, AddrKind::P2PKH),
+            Some(111) => (Network::Test, AddrKind::P2PKH),
+            Some(122) => (Network::Main, AddrKind::P2SH),
+            Some(196) => (Network::Test, AddrKind::P2SH),
+            Some(v) => bail!(RvnError::InvalidAddress(format!("unknown version {}", v))),
+            None => bail!(RvnError::InvalidAddress("empty address".to_string())),
+        };
         Ok(Self {
             inner: s.into(),
-            testnet,
+            network,
+            kind,
         })
     }
 }
 
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.inner)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.inner)
+    }
+}
+
+/// 反序列化时经过 FromStr 校验，避免构造出非法地址
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Address::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 impl Address {
+    fn version_byte(network: Network, kind: AddrKind) -> u8 {
+        match (network, kind) {
+            (Network::Main, AddrKind::P2PKH) => 60,
+            (Network::Main, AddrKind::P2SH) => 122,
+            (_, AddrKind::P2PKH) => 111,
+            (_, AddrKind::P2SH) => 196,
+        }
+    }
+
+    /// 由 20 字节的 hash160 构造地址，Regtest 与 Testnet 前缀相同，按 Test 保存，与 FromStr 的结果一致
+    pub fn from_hash160(hash: [u8; 20], network: Network, kind: AddrKind) -> Address {
+        let network = match network {
+            Network::Regtest => Network::Test,
+            n => n,
+        };
+        let mut data = vec![Self::version_byte(network, kind)];
+        data.extend_from_slice(&hash);
+        Self {
+            inner: bs58::encode(data).with_check().into_string(),
+            network,
+            kind,
+        }
+    }
+
+    /// base58check 中的版本号
+    pub fn version(&self) -> u8 {
+        Self::version_byte(self.network, self.kind)
+    }
+
+    pub fn kind(&self) -> AddrKind {
+        self.kind
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn is_testnet(&self) -> bool {
+        self.network != Network::Main
+    }
+
     pub fn vout_to_miner(&self) -> Vec<u8> {
+        self.try_vout_to_miner().expect("invalid address")
+    }
+
+    /// 地址中的 20 字节 hash160（公钥或脚本的哈希）
+    pub fn hash160(&self) -> Result<[u8; 20]> {
         let checker = bs58::decode(&self.inner)
             .with_check(None)
             .into_vec()
-            .unwrap();
-        // let checker = self.inner.from_base58().unwrap();
-        let mut data = vec![0x76, 0xa9, 0x14];
-        data.extend_from_slice(&checker[1..]);
-        data.extend_from_slice(&[0x88, 0xac]);
-        data
+            .map_err(|e| RvnError::InvalidAddress(format!("{}: {}", self.inner, e)))?;
+        // 1 字节版本号 + 20 字节 hash160
+        match checker
+            .get(1..)
+            .and_then(|hash| <[u8; 20]>::try_from(hash).ok())
+        {
+            Some(hash) => Ok(hash),
+            None => bail!(RvnError::InvalidAddress(format!(
+                "invalid payload length {}",
+                checker.len()
+            ))),
+        }
+    }
+
+    /// 生成支付给该地址的输出脚本，地址解码失败或长度不对时返回错误
+    pub fn try_vout_to_miner(&self) -> Result<Vec<u8>> {
+        let hash = self.hash160()?;
+        let data = match self.kind {
+            // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+            AddrKind::P2PKH => {
+                let mut data = vec![0x76, 0xa9, 0x14];
+                data.extend_from_slice(&hash);
+                data.extend_from_slice(&[0x88, 0xac]);
+                data
+            }
+            // OP_HASH160 <hash> OP_EQUAL
+            AddrKind::P2SH => {
+                let mut data = vec![0xa9, 0x14];
+                data.extend_from_slice(&hash);
+                data.push(0x87);
+                data
+            }
+        };
+        Ok(data)
+    }
+
+    /// 输出脚本是否支付给该地址
+    pub fn matches_script(&self, script: &[u8]) -> bool {
+        match self.try_vout_to_miner() {
+            Ok(expected) => expected == script,
+            Err(_) => false,
+        }
     }
 }
 
@@ -50,4 +186,150 @@ mod test {
         let address = Address::from_str("MF8zHXrZcnARfzpfXW3yBB6xC5QKeyWgkr");
         println!("{:?}", address);
     }
+
+    #[test]
+    fn test_invalid_address_error() {
+        let err = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jj").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RvnError>(),
+            Some(RvnError::InvalidAddress(_))
+        ));
+        let err = Address::from_str("MF8zHXrZcnARfzpfXW3yBB6xC5QKeyWgkr").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RvnError>(),
+            Some(RvnError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_try_vout_to_miner() {
+        let addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        assert_eq!(addr.try_vout_to_miner().unwrap(), addr.vout_to_miner());
+
+        let addr = Address {
+            inner: "not-base58!".to_string(),
+            network: Network::Main,
+            kind: AddrKind::P2PKH,
+        };
+        assert!(addr.try_vout_to_miner().is_err());
+
+        // base58check 合法但长度不是 21 字节
+        let inner = bs58::encode([60_u8, 1, 2, 3]).with_check().into_string();
+        let addr = Address {
+            inner,
+            network: Network::Main,
+            kind: AddrKind::P2PKH,
+        };
+        assert!(addr.try_vout_to_miner().is_err());
+    }
+
+    #[test]
+    fn test_hash160() {
+        let addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let hash = addr.hash160().unwrap();
+        assert_eq!(&addr.vout_to_miner()[3..23], &hash);
+        assert_eq!(
+            Address::from_hash160(hash, addr.network(), addr.kind()),
+            addr
+        );
+
+        let addr = Address::from_str("rN4THQwusGXqqZ5NHB3nfHtBsu1b7ngny4").unwrap();
+        assert_eq!(&addr.vout_to_miner()[2..22], &addr.hash160().unwrap());
+
+        let inner = bs58::encode([60_u8, 1, 2, 3]).with_check().into_string();
+        let addr = Address {
+            inner,
+            network: Network::Main,
+            kind: AddrKind::P2PKH,
+        };
+        assert!(addr.hash160().is_err());
+    }
+
+    #[test]
+    fn test_matches_script() {
+        let addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let other = Address::from_str("rN4THQwusGXqqZ5NHB3nfHtBsu1b7ngny4").unwrap();
+        assert!(addr.matches_script(&addr.vout_to_miner()));
+        assert!(!addr.matches_script(&other.vout_to_miner()));
+        assert!(!addr.matches_script(&addr.vout_to_miner()[1..]));
+    }
+
+    #[test]
+    fn test_p2sh_address() {
+        let addr = Address::from_str("rN4THQwusGXqqZ5NHB3nfHtBsu1b7ngny4").unwrap();
+        assert_eq!(addr.kind(), AddrKind::P2SH);
+        let out = addr.vout_to_miner();
+        assert_eq!(
+            hex::encode(out),
+            "a914ad974e8859312c1afbe828aadfaa2e1e8f8d690087"
+        );
+
+        let addr = Address::from_str("2N9569T1EQcpgMdPn6DhNYa2QTE5PpirUE5").unwrap();
+        assert_eq!(addr.kind(), AddrKind::P2SH);
+        assert!(addr.is_testnet());
+
+        // 非 Ravencoin 的版本号
+        assert!(Address::from_str("MF8zHXrZcnARfzpfXW3yBB6xC5QKeyWgkr").is_err());
+    }
+
+    #[test]
+    fn test_address_serde() {
+        let addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        assert_eq!(addr.to_string(), "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk");
+        let s = serde_json::to_string(&addr).unwrap();
+        assert_eq!(s, r#""RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk""#);
+        let addr2: Address = serde_json::from_str(&s).unwrap();
+        assert_eq!(addr, addr2);
+
+        assert!(
+            serde_json::from_str::<Address>(r#""RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jj""#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_network() {
+        let addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        assert_eq!(addr.network(), Network::Main);
+        assert!(!addr.is_testnet());
+
+        let addr = Address::from_str("mwLpTDfk4HSCqnYR1ZPHKutXxMBDK3TaFF").unwrap();
+        assert_eq!(addr.network(), Network::Test);
+        assert_eq!(addr.kind(), AddrKind::P2PKH);
+        assert!(addr.is_testnet());
+    }
+
+    #[test]
+    fn test_from_hash160() {
+        let hash = [
+            149_u8, 0, 219, 97, 53, 71, 189, 57, 112, 252, 206, 194, 167, 169, 9, 185, 46, 117, 0,
+            89,
+        ];
+        let addr = Address::from_hash160(hash, Network::Main, AddrKind::P2PKH);
+        assert_eq!(addr.to_string(), "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk");
+        assert_eq!(
+            addr,
+            Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap()
+        );
+
+        let hash = hex::decode("ad974e8859312c1afbe828aadfaa2e1e8f8d6900").unwrap();
+        let addr = Address::from_hash160(hash.try_into().unwrap(), Network::Main, AddrKind::P2SH);
+        assert_eq!(addr.to_string(), "rN4THQwusGXqqZ5NHB3nfHtBsu1b7ngny4");
+
+        // 任意网络和类型都能经 FromStr 还原
+        let hash = [7; 20];
+        for network in [Network::Main, Network::Test, Network::Regtest] {
+            for kind in [AddrKind::P2PKH, AddrKind::P2SH] {
+                let addr = Address::from_hash160(hash, network, kind);
+                assert_eq!(Address::from_str(&addr.to_string()).unwrap(), addr);
+                assert_eq!(addr.hash160().unwrap(), hash);
+                assert_eq!(addr.kind(), kind);
+            }
+        }
+        let addr = Address::from_hash160(hash, Network::Regtest, AddrKind::P2PKH);
+        assert_eq!(addr.network(), Network::Test);
+        assert_eq!(
+            addr.to_string(),
+            Address::from_hash160(hash, Network::Test, AddrKind::P2PKH).to_string()
+        );
+    }
 }
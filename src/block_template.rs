@@ -1,15 +1,29 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGVERIFY,
+};
+use bitcoin::blockdata::script::Instruction;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use sha3::Keccak256;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::address::Address;
+use crate::address::{AddrKind, Address};
+use crate::consts::{ChainParams, MAX_MONEY};
+use crate::diff::{
+    bits2target, clamp_share_target, diff2target, format_target_hex, parse_bits, target2bits,
+    uint256_from_hash, Uint256,
+};
+use crate::error::RvnError;
+use crate::header::{Header, HEADER_LEN};
 use crate::job::JobInfo;
-use crate::merkle::merkel_hash;
-use crate::op_data::OpData;
+use crate::kawpow::kawpow_seed_for_epoch;
+use crate::merkle::{merkel_hash, merkle_branches, merkle_root_from_coinbase, MAX_MERKLE_LEAVES};
+use crate::op_data::{OpData, OpReader};
 use crate::script::Script;
+use crate::stratum::Subscription;
+use crate::util::{ct_eq, decode_hash32_reversed, reverse_bytes, reverse_hash};
 
 /// RPC 返回的交易数据
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,7 +34,123 @@ pub struct Transaction {
     pub fee: u64,
     pub sigops: u32,
     pub weight: u32,
-    depends: Vec<u32>,
+    /// 依赖的交易在 transactions 中从 1 开始的下标
+    pub depends: Vec<u32>,
+}
+
+impl Transaction {
+    /// 手续费率（fee / weight），weight 为 0 的交易视为无效，返回 0
+    pub fn fee_rate(&self) -> f64 {
+        if self.weight == 0 {
+            return 0.0;
+        }
+        self.fee as f64 / self.weight as f64
+    }
+}
+
+/// 解码后的交易，txid 和 wtxid 为内部序
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TxView {
+    pub txid: [u8; 32],
+    pub wtxid: [u8; 32],
+    pub data: Vec<u8>,
+    pub fee: u64,
+    pub weight: u32,
+    pub sigops: u32,
+}
+
+impl TryFrom<&Transaction> for TxView {
+    type Error = anyhow::Error;
+
+    fn try_from(tx: &Transaction) -> Result<Self> {
+        Ok(Self {
+            txid: decode_hash32_reversed(&tx.txid)?,
+            wtxid: decode_hash32_reversed(&tx.hash)?,
+            data: hex::decode(&tx.data)?,
+            fee: tx.fee,
+            weight: tx.weight,
+            sigops: tx.sigops,
+        })
+    }
+}
+
+/// 解析 reader 当前位置的一笔交易，返回去掉 witness 后的序列化数据、witness 部分（含 marker/flag）的长度
+/// 以及交易的完整长度
+fn read_tx(reader: &mut OpReader) -> Result<(Vec<u8>, usize, usize)> {
+    let start = reader.pos();
+    let version = reader.take(4)?;
+    let mut peek = reader.clone();
+    // 输入数不可能为 0，此处的 0 是 marker
+    let segwit = peek.read_u8().ok() == Some(0) && peek.read_u8().is_ok_and(|flag| flag != 0);
+    if segwit {
+        reader.take(2)?;
+    }
+    let body = reader.clone();
+    let inputs = reader.read_var_len()?;
+    for _ in 0..inputs {
+        reader.take(36)?;
+        let len = reader.read_var_len()?;
+        reader.take(len.saturating_add(4))?;
+    }
+    let outputs = reader.read_var_len()?;
+    for _ in 0..outputs {
+        reader.take(8)?;
+        let len = reader.read_var_len()?;
+        reader.take(len)?;
+    }
+    let body = body.clone().take(reader.pos() - body.pos())?;
+    if segwit {
+        for _ in 0..inputs {
+            let items = reader.read_var_len()?;
+            for _ in 0..items {
+                let len = reader.read_var_len()?;
+                reader.take(len)?;
+            }
+        }
+    }
+    let locktime = reader.take(4)?;
+
+    let mut stripped = version.to_vec();
+    stripped.extend_from_slice(body);
+    stripped.extend_from_slice(locktime);
+    let tx_len = reader.pos() - start;
+    let witness_len = tx_len - stripped.len();
+    Ok((stripped, witness_len, tx_len))
+}
+
+/// 原始交易中 witness 之外的部分，以及 witness 部分（含 marker/flag）的长度
+fn split_witness(raw: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut reader = OpReader::new(raw);
+    let (stripped, witness_len, _) = read_tx(&mut reader)?;
+    if reader.remaining() != 0 {
+        bail!("{} trailing bytes after transaction", reader.remaining());
+    }
+    Ok((stripped, witness_len))
+}
+
+/// 由原始交易计算 txid（内部序），segwit 交易会先去掉 witness 数据
+pub fn txid_from_raw(raw: &[u8]) -> Result<[u8; 32]> {
+    let (stripped, _) = split_witness(raw)?;
+    Ok(dsha256(&stripped))
+}
+
+/// 同 txid_from_raw，但要求交易带有 witness（version 之后为 0x00 0x01 的 marker/flag），
+/// 去掉 marker/flag 和 witness 后计算 txid；没有 witness 时报错，用于确认拿到的是完整的 segwit 序列化
+pub fn txid_from_witness_tx(raw: &[u8]) -> Result<[u8; 32]> {
+    if raw.get(4..6) != Some(&[0x00, 0x01][..]) {
+        bail!("transaction has no segwit marker");
+    }
+    let (stripped, witness_len) = split_witness(raw)?;
+    if witness_len == 0 {
+        bail!("transaction has no witness data");
+    }
+    Ok(dsha256(&stripped))
+}
+
+/// 原始交易的 weight：不含 witness 的部分计 4 倍，witness 部分计 1 倍
+pub fn tx_weight(raw: &[u8]) -> Result<u32> {
+    let (stripped, witness_len) = split_witness(raw)?;
+    Ok(u32::try_from(stripped.len() * 4 + witness_len)?)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,215 +185,3863 @@ pub struct BlockTemplateInfo {
     pub default_witness_commitment: String,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+impl BlockTemplateInfo {
+    pub fn builder() -> BlockTemplateInfoBuilder {
+        BlockTemplateInfoBuilder::default()
+    }
+
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    /// 节点是否支持 proposal 模式
+    pub fn supports_proposal(&self) -> bool {
+        self.capabilities.iter().any(|c| c == "proposal")
+    }
+
+    pub fn rules(&self) -> &[String] {
+        &self.rules
+    }
+
+    pub fn vbavailable(&self) -> &HashMap<String, u32> {
+        &self.vbavailable
+    }
+
+    pub fn vbrequired(&self) -> u32 {
+        self.vbrequired
+    }
+
+    /// 在节点给出的 version 上置位 enabled 中各规则在 vbavailable 里的 bit，
+    /// vbrequired 中的位总是置位，不认识的规则忽略
+    pub fn signalling_version(&self, enabled: &[&str]) -> u32 {
+        enabled
+            .iter()
+            .filter_map(|rule| self.vbavailable.get(*rule))
+            .filter(|bit| **bit < 32)
+            .fold(self.version | self.vbrequired, |version, bit| {
+                version | (1 << bit)
+            })
+    }
+
+    pub fn coinbase_aux(&self) -> &HashMap<String, String> {
+        &self.coinbase_aux
+    }
+
+    /// 解码 coinbaseaux.flags，没有该字段时为空
+    pub fn coinbase_aux_flags(&self) -> Result<Vec<u8>> {
+        match self.coinbase_aux.get("flags") {
+            Some(flags) => Ok(hex::decode(flags)
+                .map_err(|e| RvnError::TemplateDecode(format!("coinbaseaux.flags: {}", e)))?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn long_poll_id(&self) -> &str {
+        &self.long_poll_id
+    }
+
+    pub fn mutable(&self) -> &[String] {
+        &self.mutable
+    }
+
+    /// 节点是否允许修改 field，如 "time"、"transactions"、"prevblock"
+    pub fn may_mutate(&self, field: &str) -> bool {
+        self.mutable.iter().any(|m| m == field)
+    }
+
+    pub fn noncerange(&self) -> &str {
+        &self.noncerange
+    }
+
+    /// noncerange 解码为 (起始, 结束)，两端均包含
+    pub fn nonce_range(&self) -> Result<(u32, u32)> {
+        let range = hex::decode(&self.noncerange)
+            .map_err(|e| RvnError::TemplateDecode(format!("noncerange: {}", e)))?;
+        if range.len() != 8 {
+            bail!(RvnError::TemplateDecode(format!(
+                "noncerange: {}",
+                self.noncerange
+            )));
+        }
+        let start = u32::from_be_bytes(range[0..4].try_into()?);
+        let end = u32::from_be_bytes(range[4..8].try_into()?);
+        Ok((start, end))
+    }
+
+    /// 检查矿池分配的 nonce 前缀（8 字节 nonce 的高 4 字节）在 noncerange 内
+    pub fn validate_nonce(&self, nonce: u64) -> Result<()> {
+        if !nonce_in_range(nonce, self.nonce_range()?) {
+            bail!("nonce {:016x} out of range {}", nonce, self.noncerange);
+        }
+        Ok(())
+    }
+
+    pub fn sigop_limit(&self) -> u32 {
+        self.sigop_limit
+    }
+
+    pub fn size_limit(&self) -> u64 {
+        self.size_limit
+    }
+
+    pub fn weight_limit(&self) -> u64 {
+        self.weight_limit
+    }
+
+    pub fn mintime(&self) -> u64 {
+        self.mintime
+    }
+
+    pub fn curtime(&self) -> u64 {
+        self.cur_time
+    }
+
+    /// 检查 bits 与 target 一致，target 精度高于 bits 时只要求压缩后相同
+    pub fn validate_target_bits(&self) -> Result<()> {
+        check_target_bits(&self.bits, &self.target)
+    }
+
+    pub fn iter_txs(&self) -> impl Iterator<Item = Result<TxView>> + '_ {
+        self.transactions.iter().map(TxView::try_from)
+    }
+
+    /// 与 other 比较，区分新区块和仅交易变化
+    pub fn diff(&self, other: &BlockTemplateInfo) -> TemplateDiff {
+        let txids = |info: &BlockTemplateInfo| -> BTreeSet<String> {
+            info.transactions
+                .iter()
+                .map(|tx| tx.txid.to_lowercase())
+                .collect()
+        };
+        TemplateDiff {
+            height_changed: self.height != other.height,
+            prevblock_changed: !self
+                .previousblockhash
+                .eq_ignore_ascii_case(&other.previousblockhash),
+            tx_set_changed: txids(self) != txids(other),
+            witness_changed: self.default_witness_commitment != other.default_witness_commitment,
+        }
+    }
+
+    /// 检查 coinbasevalue 不超过区块奖励与手续费之和
+    pub fn validate_coinbase_value(&self) -> Result<()> {
+        self.validate_coinbase_value_with(&ChainParams::ravencoin_mainnet())
+    }
+
+    /// 同 validate_coinbase_value，区块奖励按 params 计算
+    pub fn validate_coinbase_value_with(&self, params: &ChainParams) -> Result<()> {
+        let max_value = params
+            .subsidy(self.height)
+            .saturating_add(self.total_fees());
+        if self.coinbasevalue > max_value {
+            bail!(
+                "coinbasevalue {} exceeds subsidy plus fees {}",
+                self.coinbasevalue,
+                max_value
+            );
+        }
+        Ok(())
+    }
+
+    /// 所有交易的手续费之和
+    pub fn total_fees(&self) -> u64 {
+        self.transactions.iter().map(|tx| tx.fee).sum()
+    }
+
+    /// 区块奖励，即 coinbasevalue 扣除手续费的部分
+    pub fn subsidy(&self) -> Result<u64> {
+        let total_fees = self.total_fees();
+        match self.coinbasevalue.checked_sub(total_fees) {
+            Some(subsidy) => Ok(subsidy),
+            None => bail!(
+                "coinbasevalue {} less than total fees {}",
+                self.coinbasevalue,
+                total_fees
+            ),
+        }
+    }
+}
+
+/// 两次 getblocktemplate 结果之间的差异
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TemplateDiff {
+    pub height_changed: bool,
+    pub prevblock_changed: bool,
+    /// 交易集合（按 txid，不计顺序）变化
+    pub tx_set_changed: bool,
+    pub witness_changed: bool,
+}
+
+impl TemplateDiff {
+    /// 高度或前一区块变化，旧任务作废，需要矿机立即切换
+    pub fn is_new_block(&self) -> bool {
+        self.height_changed || self.prevblock_changed
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        *self == TemplateDiff::default()
+    }
+}
+
+/// 解析 getblocktemplate 的 result，并检查字段之间的基本约束
+impl TryFrom<&str> for BlockTemplateInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        // serde 的错误信息中包含缺失或类型不对的字段名
+        let info: BlockTemplateInfo = serde_json::from_str(s)
+            .map_err(|e| RvnError::TemplateDecode(format!("getblocktemplate: {}", e)))?;
+        if info.previousblockhash.is_empty() {
+            bail!(RvnError::TemplateDecode(
+                "previousblockhash: empty".to_string()
+            ));
+        }
+        if info.bits.len() != 8 {
+            bail!(RvnError::TemplateDecode(format!(
+                "bits: invalid length {}, expect 8",
+                info.bits.len()
+            )));
+        }
+        if info.target.len() != 64 {
+            bail!(RvnError::TemplateDecode(format!(
+                "target: invalid length {}, expect 64",
+                info.target.len()
+            )));
+        }
+        Ok(info)
+    }
+}
+
+/// 不经过 RPC 构造 BlockTemplateInfo，主要用于测试
+///
+/// 未设置的 target 由 bits 计算，coinbasevalue 为区块奖励加手续费，
+/// default_witness_commitment 由交易的 wtxid 计算
+#[derive(Debug, Clone)]
+pub struct BlockTemplateInfoBuilder {
+    height: u32,
+    previousblockhash: String,
+    bits: String,
+    target: Option<String>,
+    coinbasevalue: Option<u64>,
+    transactions: Vec<Transaction>,
+}
+
+impl Default for BlockTemplateInfoBuilder {
+    fn default() -> Self {
+        Self {
+            height: 1,
+            previousblockhash: hex::encode([0; 32]),
+            bits: "207fffff".to_string(),
+            target: None,
+            coinbasevalue: None,
+            transactions: Vec::new(),
+        }
+    }
+}
+
+impl BlockTemplateInfoBuilder {
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn previousblockhash(mut self, hash: &str) -> Self {
+        self.previousblockhash = hash.to_string();
+        self
+    }
+
+    pub fn bits(mut self, bits: &str) -> Self {
+        self.bits = bits.to_string();
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn coinbasevalue(mut self, value: u64) -> Self {
+        self.coinbasevalue = Some(value);
+        self
+    }
+
+    pub fn transactions(mut self, transactions: Vec<Transaction>) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    pub fn build(self) -> Result<BlockTemplateInfo> {
+        let target = match self.target {
+            Some(target) => target,
+            None => format_target_hex(&bits2target(parse_bits(&self.bits)?)),
+        };
+        let fees: u64 = self.transactions.iter().map(|tx| tx.fee).sum();
+        let coinbasevalue = self
+            .coinbasevalue
+            .unwrap_or_else(|| expected_subsidy(self.height) + fees);
+        let transactions: Vec<_> = self.transactions.iter().collect();
+        let default_witness_commitment = witness_commitment(&transactions, &[0; 32])?;
+        Ok(BlockTemplateInfo {
+            capabilities: vec!["proposal".to_string()],
+            version: 0x30000000,
+            rules: Vec::new(),
+            vbavailable: HashMap::new(),
+            vbrequired: 0,
+            long_poll_id: format!("{}0", self.previousblockhash),
+            previousblockhash: self.previousblockhash,
+            transactions: self.transactions,
+            coinbase_aux: HashMap::new(),
+            coinbasevalue,
+            target,
+            mintime: 0,
+            mutable: vec![
+                "time".to_string(),
+                "transactions".to_string(),
+                "prevblock".to_string(),
+            ],
+            noncerange: "00000000ffffffff".to_string(),
+            sigop_limit: 80000,
+            size_limit: 8000000,
+            weight_limit: 8000000,
+            cur_time: now() as u64,
+            bits: self.bits,
+            height: self.height,
+            default_witness_commitment,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+/// 哈希字段的字节序：
+/// - 内部序：dsha256 的原始输出，即写入区块头/merkle 计算时使用的顺序
+/// - 显示序：内部序反转，即 RPC 和区块浏览器中显示的顺序
 pub struct BlockTemplate {
     pub pool_addr: Address,
     pub pool_info: String,
     pub coinbase_tx: Vec<u8>,
+    /// 内部序
     pub coinbase_txid: [u8; 32],
     pub seed_hash: [u8; 32],
     pub header: Vec<u8>,
+    /// 显示序，即下发给矿机的 header_hash
     pub header_hash: [u8; 32],
+    /// 内部序，previousblockhash 反转后的结果
     pub prev_hash: Vec<u8>,
+    /// 显示序的小写 hex，即 previousblockhash，用于快速比较
+    #[serde(default)]
+    pub prev_hash_hex: String,
     pub timestamp: u32,
+    /// 生成模板时的时钟，与区块头时间无关，NtimePolicy 为 MinTime/Fixed 时二者可能相差很远
+    #[serde(default)]
+    pub built_at: u32,
     pub external_txs: Vec<String>,
+    /// 内部序，模板中声明的 txid，与 external_txs 一一对应
+    #[serde(default)]
+    pub external_txids: Vec<[u8; 32]>,
     pub target_hex: String,
     pub bits_hex: String,
+    /// coinbase 中实际写入的 witness commitment 输出脚本
     pub witness_hex: String,
+    /// 节点给出的 default_witness_commitment，只用于判断模板是否变化
+    #[serde(default)]
+    pub default_witness_hex: String,
     pub version: u32,
     pub height: u32,
+    /// extranonce2 在 coinbase_tx 中的偏移，只有指定了 subscription 时才有
+    #[serde(default)]
+    pub extranonce2_offset: Option<usize>,
+    /// coinbase_tx 中 extranonce2 之前和之后的部分，滚动 extranonce2 时直接拼接，只有指定了 subscription 时才有
+    #[serde(default)]
+    pub coinbase_prefix: Vec<u8>,
+    #[serde(default)]
+    pub coinbase_suffix: Vec<u8>,
+    #[serde(default)]
+    pub long_poll_id: String,
+    /// external_txs 的 weight 之和，取自模板
+    #[serde(default)]
+    pub external_weight: u64,
+    /// external_txs 的 sigops 之和，取自模板
+    #[serde(default)]
+    pub external_sigops: u32,
+    /// external_txs 的手续费之和，已计入矿池输出
+    #[serde(default)]
+    pub external_fees: u64,
+    /// coinbase 到 merkle 根的兄弟节点，coinbase 变化时用于快速重算根
+    #[serde(default)]
+    pub merkle_branches: Vec<[u8; 32]>,
+    /// 模板的 mutable 中没有 time，不能滚动 ntime
+    #[serde(default)]
+    pub fixed_time: bool,
 }
 
-const KAWPOW_EPOCH_LENGTH: usize = 7500;
+/// BlockTemplate 的元数据部分，不含 coinbase_tx 和 external_txs，用于日志和监控
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct BlockTemplateSummary {
+    pub height: u32,
+    pub version: u32,
+    pub timestamp: u32,
+    /// 显示序
+    #[serde(with = "hex::serde")]
+    pub header_hash: [u8; 32],
+    #[serde(with = "hex::serde")]
+    pub seed_hash: [u8; 32],
+    pub prev_hash_hex: String,
+    pub target_hex: String,
+    pub bits_hex: String,
+    /// 含 coinbase
+    pub tx_count: usize,
+    pub external_weight: u64,
+    pub external_fees: u64,
+    pub long_poll_id: String,
+}
 
-fn now() -> u32 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32
+/// 32 字节哈希的字节序，各字段保存的字节序见 BlockTemplate 的文档
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HashOrientation {
+    /// dsha256 的原始输出，写入区块头和计算 merkle 时使用
+    Internal,
+    /// 内部序反转，RPC、区块浏览器和 mining.notify 中使用
+    Display,
 }
 
-impl BlockTemplate {
-    pub fn new(
-        template_info: &BlockTemplateInfo,
-        pool_addr: Address,
-        pool_info: String,
-    ) -> Result<Self> {
-        let seed_hash = Self::seed_hash(template_info.height);
-        let script = Script::coinbase_script(template_info.height, &pool_info)?;
-        let coinbase_txin = Self::coinbase_txin(&script);
-        let vout_to_miner = pool_addr.vout_to_miner();
-        let witness_vout = hex::decode(&template_info.default_witness_commitment)?;
+impl HashOrientation {
+    /// 将 self 字节序的 hash 转为 to 字节序
+    pub fn convert(self, hash: [u8; 32], to: HashOrientation) -> [u8; 32] {
+        if self == to {
+            hash
+        } else {
+            reverse_hash(hash)
+        }
+    }
+}
 
-        // generate coinbase tx
-        let coinbase_tx = OpData::default()
-            .push_u32(1)
-            .push_slice(&[0x00, 0x01, 0x01])
-            .push_slice(&coinbase_txin)
-            .push_u8(0x02)
-            .push_u64(template_info.coinbasevalue)
-            .op_push_slice(&vout_to_miner)
-            .push_slice(&[0; 8])
-            .op_push_slice(&witness_vout)
-            .push_slice(&[0x01, 0x20])
-            .push_slice(&[0; 32])
-            .push_slice(&[0; 4]);
+/// coinbase 中附加的资产输出
+///
+/// script 为完整的输出脚本，原样写入交易：通常是 P2PKH/P2SH 脚本后接
+/// OP_RVN_ASSET(0xc0)、一次 push 的资产数据（以 "rvn" 开头，后跟类型字节和序列化的资产）以及 OP_DROP(0x75)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AssetOutput {
+    pub script: Vec<u8>,
+    pub value: u64,
+}
 
-        // generate coinbase txid
-        let coinbase_no_wit = OpData::default()
-            .push_u32(1)
-            .push_u8(0x01)
-            .push_slice(&coinbase_txin)
-            .push_u8(0x02)
-            .push_u64(template_info.coinbasevalue)
-            .op_push_slice(&vout_to_miner)
-            .push_slice(&[0; 8])
-            .op_push_slice(&witness_vout)
-            .push_slice(&[0; 4]);
+/// 由同一组输入生成 coinbase 的两种序列化：带 witness 的完整交易，以及计算 txid 用的不含 witness 的交易
+#[derive(Debug, Clone)]
+pub struct CoinbaseBuilder {
+    pub version: u32,
+    /// 唯一的输入，含空 prevout、脚本和 sequence
+    pub txin: Vec<u8>,
+    /// 输出数及全部输出
+    pub outputs: Vec<u8>,
+    /// witness 栈中唯一的元素
+    pub witness_reserved_value: [u8; 32],
+    pub locktime: u32,
+}
 
-        let coinbase_txid = dsha256(coinbase_no_wit.as_slice());
-        let mut txids = vec![coinbase_txid];
-        let txids2: Vec<_> = template_info
-            .transactions
-            .iter()
-            .map(|s| {
-                let mut h = hex::decode(&s.txid).expect("invalid txid");
-                h.reverse();
-                h.try_into().unwrap()
-            })
-            .collect();
-        txids.extend_from_slice(&txids2);
-        let incoming_txs: Vec<_> = template_info
-            .transactions
-            .iter()
-            .map(|s| s.data.clone())
-            .collect();
-        let merkle = merkel_hash(txids);
+impl CoinbaseBuilder {
+    fn serialize(&self, witness: bool) -> Vec<u8> {
+        let mut data = OpData::default().push_u32(self.version);
+        if witness {
+            // marker 和 flag
+            data = data.push_slice(&[0x00, 0x01]);
+        }
+        data = data
+            .var_push_num(1)
+            .push_slice(&self.txin)
+            .push_slice(&self.outputs);
+        if witness {
+            data = data
+                .var_push_num(1)
+                .op_push_slice(&self.witness_reserved_value);
+        }
+        data.push_u32(self.locktime).as_slice().to_vec()
+    }
 
-        // calculate header
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as u32;
-        let mut prev_hash = hex::decode(&template_info.previousblockhash).unwrap();
-        prev_hash.reverse();
-        let mut bits_hex = hex::decode(&template_info.bits).unwrap();
-        bits_hex.reverse();
-        let op_data_header = OpData::default()
-            .push_u32(template_info.version)
-            .push_slice(&prev_hash)
-            .push_slice(&merkle)
-            .push_u32(ts)
-            .push_slice(&bits_hex)
-            .push_u32(template_info.height);
-        let header = op_data_header.as_slice().to_vec();
-        let mut header_hash = dsha256(&header);
-        header_hash.reverse();
+    /// 带 witness 的完整序列化，即写入区块的 coinbase_tx
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.serialize(true)
+    }
 
-        let obj = Self {
-            pool_addr,
-            pool_info,
-            coinbase_tx: coinbase_tx.as_slice().to_vec(),
-            witness_hex: template_info.default_witness_commitment.clone(),
-            coinbase_txid,
-            seed_hash,
-            header,
-            header_hash,
-            prev_hash,
-            timestamp: ts,
-            external_txs: incoming_txs,
-            target_hex: template_info.target.clone(),
-            bits_hex: template_info.bits.clone(),
-            version: template_info.version,
-            height: template_info.height,
-        };
-        Ok(obj)
+    /// 不含 witness 的序列化，用于计算 txid
+    pub fn to_stripped_bytes(&self) -> Vec<u8> {
+        self.serialize(false)
     }
 
-    /// target_hex: like "00000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
-    pub fn create_job(&self, target_hex: String, refresh: bool) -> JobInfo {
-        JobInfo {
-            header_hash: self.header_hash,
-            seed_hash: hex::encode(self.seed_hash),
-            share_target_hex: target_hex,
-            block_target_hex: self.target_hex.clone(),
-            height: self.height,
-            block_bits_hex: self.bits_hex.clone(),
-            refresh,
-            header: self.header.clone(),
-            external_txs: self.external_txs.clone(),
-            coinbase_tx: self.coinbase_tx.clone(),
-            timestamp: self.timestamp,
-        }
+    /// 内部序的 txid
+    pub fn txid(&self) -> [u8; 32] {
+        dsha256(&self.to_stripped_bytes())
     }
+}
+
+/// 构造 BlockTemplate 的可选项
+#[derive(Debug, Clone)]
+pub struct BlockTemplateOptions {
+    /// 按手续费率重新挑选交易，使其满足节点给出的 weight/sigop 限制；
+    /// 被丢弃交易的手续费会从 coinbasevalue 中扣除，witness commitment 按挑选后的交易重新计算
+    pub select_transactions: bool,
+    /// 在 coinbase 脚本中预留 extranonce1 和 extranonce2 区域
+    pub subscription: Option<Subscription>,
+    pub coinbase_version: u32,
+    /// coinbase 输入的 sequence，默认 0xffffffff
+    pub coinbase_sequence: u32,
+    pub locktime: u32,
+    /// 追加在矿池输出之后、witness commitment 之前，其 value 从矿池输出中扣除
+    pub asset_outputs: Vec<AssetOutput>,
+    /// coinbase witness 栈中的 reserved value，非 0 时按其重新计算 witness commitment
+    pub witness_reserved_value: [u8; 32],
+    /// 无 extranonce 时，pool_info 过长是否截断，否则报错
+    pub truncate_pool_info: bool,
+    /// 生成 coinbase 后检查矿池输出确实支付给 pool_addr
+    pub verify_payout: bool,
+    /// 覆盖区块头的 version，通常来自 BlockTemplateInfo::signalling_version，vbrequired 中的位仍会置位
+    pub version: Option<u32>,
+    /// 链参数，默认为 Ravencoin 主网
+    pub chain_params: ChainParams,
+    /// 将节点的 coinbaseaux.flags 写入 coinbase 脚本，位于 BIP34 高度之后、pool_info 之前
+    pub include_coinbase_aux: bool,
+    /// 已知的 epoch seed，跳过 keccak 计算，需由调用方保证与模板高度对应
+    pub seed_hash: Option<[u8; 32]>,
+    /// 区块头 time 的取值方式
+    pub ntime_policy: NtimePolicy,
+}
+
+/// 区块头 time 的取值方式
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum NtimePolicy {
+    /// 当前时间，不小于 mintime，见 header_time
+    #[default]
+    Now,
+    /// 模板的 mintime
+    MinTime,
+    /// 固定值，用于测试或复现
+    Fixed(u32),
+}
 
-    fn coinbase_txin(script: &Script) -> Vec<u8> {
-        let mut data = vec![0; 32];
-        data.extend_from_slice(&[0xff; 4]);
-        data.push(script.as_slice().len() as u8);
-        data.extend_from_slice(script.as_slice());
-        data.extend_from_slice(&[0xff; 4]);
-        data
+impl NtimePolicy {
+    fn header_time(&self, mintime: u64) -> u32 {
+        match self {
+            NtimePolicy::Now => header_time(now(), mintime),
+            NtimePolicy::MinTime => mintime.min(u32::MAX as u64) as u32,
+            NtimePolicy::Fixed(time) => *time,
+        }
     }
+}
 
-    fn seed_hash(height: u32) -> [u8; 32] {
-        let mut seed: [u8; 32] = Default::default();
-        if height < KAWPOW_EPOCH_LENGTH as u32 {
-            return seed;
+impl Default for BlockTemplateOptions {
+    fn default() -> Self {
+        Self {
+            select_transactions: false,
+            subscription: None,
+            coinbase_version: 1,
+            coinbase_sequence: 0xffff_ffff,
+            locktime: 0,
+            asset_outputs: Vec::new(),
+            truncate_pool_info: false,
+            witness_reserved_value: [0; 32],
+            verify_payout: false,
+            version: None,
+            chain_params: ChainParams::ravencoin_mainnet(),
+            include_coinbase_aux: false,
+            seed_hash: None,
+            ntime_policy: NtimePolicy::Now,
         }
-        for _ in 0..height / KAWPOW_EPOCH_LENGTH as u32 {
-            let mut hasher = Keccak256::default();
-            hasher.update(&mut seed);
-            seed = hasher.finalize().to_vec().try_into().unwrap();
+    }
+}
+
+/// 链式构造 BlockTemplate，未设置的项使用 BlockTemplateOptions 的默认值，
+/// 同一个 builder 可以对每个新模板重复 build
+#[derive(Debug, Clone)]
+pub struct BlockTemplateBuilder {
+    pool_addr: Address,
+    pool_info: String,
+    options: BlockTemplateOptions,
+}
+
+impl BlockTemplateBuilder {
+    pub fn new(pool_addr: Address) -> Self {
+        Self {
+            pool_addr,
+            pool_info: String::new(),
+            options: BlockTemplateOptions::default(),
         }
-        seed
     }
 
-    pub fn is_new_template(&self, template_info: &BlockTemplateInfo) -> bool {
-        self.height != template_info.height
-            || now() - self.timestamp > 60
-            || self.witness_hex != template_info.default_witness_commitment
+    /// 矿池输出之后的额外输出，金额从矿池输出中扣除
+    pub fn payouts(mut self, outputs: Vec<AssetOutput>) -> Self {
+        self.options.asset_outputs = outputs;
+        self
+    }
+
+    /// 写入 coinbase 脚本的信息，即 new 的 pool_info
+    pub fn coinbase_message(mut self, message: &str) -> Self {
+        self.pool_info = message.to_string();
+        self
+    }
+
+    pub fn coinbase_version(mut self, version: u32) -> Self {
+        self.options.coinbase_version = version;
+        self
+    }
+
+    /// 在 coinbase 脚本中预留 extranonce1 和 extranonce2 区域
+    pub fn extranonce1(mut self, extranonce1: Vec<u8>, extranonce2_size: usize) -> Self {
+        self.options.subscription = Some(Subscription::new(extranonce1, extranonce2_size));
+        self
+    }
+
+    pub fn witness_reserved(mut self, value: [u8; 32]) -> Self {
+        self.options.witness_reserved_value = value;
+        self
+    }
+
+    pub fn ntime_policy(mut self, policy: NtimePolicy) -> Self {
+        self.options.ntime_policy = policy;
+        self
+    }
+
+    pub fn chain_params(mut self, params: ChainParams) -> Self {
+        self.options.chain_params = params;
+        self
+    }
+
+    pub fn build(&self, template_info: &BlockTemplateInfo) -> Result<BlockTemplate> {
+        BlockTemplate::new_with_options(
+            template_info,
+            self.pool_addr.clone(),
+            self.pool_info.clone(),
+            &self.options,
+        )
     }
 }
 
-pub fn dsha256(data: &[u8]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    let result = hasher.finalize();
-    let mut hasher = Sha256::new();
-    hasher.update(&result);
-    hasher.finalize().as_slice().try_into().unwrap()
+/// 解码后的 BlockTemplateInfo，用同一模板生成多个 BlockTemplate（如不同的收款配置）时避免重复解码 hex
+#[derive(Debug, Clone)]
+pub struct NormalizedTemplate {
+    pub info: BlockTemplateInfo,
+    /// 内部序
+    pub prev_hash: [u8; 32],
+    pub bits: u32,
+    pub target: Uint256,
+    /// 内部序，与 info.transactions 一一对应
+    pub txids: Vec<[u8; 32]>,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::str::FromStr;
+impl TryFrom<BlockTemplateInfo> for NormalizedTemplate {
+    type Error = anyhow::Error;
 
-    #[test]
-    fn test_dsha_256() {
-        let data = b"hello world";
-        let r = dsha256(&data.as_slice());
-        let result_exp = [
-            188_u8, 98, 212, 184, 13, 158, 54, 218, 41, 193, 108, 93, 77, 159, 17, 115, 31, 54, 5,
-            44, 114, 64, 26, 118, 194, 60, 15, 181, 169, 183, 68, 35,
-        ];
-        assert_eq!(r, result_exp)
+    fn try_from(info: BlockTemplateInfo) -> Result<Self> {
+        info.validate_target_bits()?;
+        Ok(Self {
+            prev_hash: decode_prev_hash(&info)?,
+            bits: parse_bits(&info.bits)?,
+            target: uint256_from_hash(&info.target)?,
+            txids: decode_txids(&info.transactions)?,
+            info,
+        })
+    }
+}
+
+/// 切出 extranonce2 区域前后的部分，offset 和 size 由构造 coinbase 时确定，不会越界
+fn split_coinbase(coinbase_tx: &[u8], offset: usize, size: usize) -> (Vec<u8>, Vec<u8>) {
+    (
+        coinbase_tx[..offset].to_vec(),
+        coinbase_tx[offset + size..].to_vec(),
+    )
+}
+
+/// 必须是 32 字节，否则区块头错位
+fn decode_prev_hash(info: &BlockTemplateInfo) -> Result<[u8; 32]> {
+    Ok(decode_hash32_reversed(&info.previousblockhash)
+        .map_err(|e| RvnError::TemplateDecode(format!("previousblockhash: {}", e)))?)
+}
+
+fn decode_txids(txs: &[Transaction]) -> Result<Vec<[u8; 32]>> {
+    txs.iter()
+        .map(|tx| {
+            Ok(decode_hash32_reversed(&tx.txid)
+                .map_err(|e| RvnError::TemplateDecode(format!("txid: {}", e)))?)
+        })
+        .collect()
+}
+
+/// 指定高度的区块奖励（不含手续费）
+pub fn expected_subsidy(height: u32) -> u64 {
+    ChainParams::ravencoin_mainnet().subsidy(height)
+}
+
+/// 只计入实际打包交易的手续费，避免丢弃交易后 coinbase 金额过高（bad-cb-amount）
+pub fn recompute_coinbase_value(base_subsidy: u64, included: &[&Transaction]) -> u64 {
+    included
+        .iter()
+        .fold(base_subsidy, |acc, tx| acc.saturating_add(tx.fee))
+}
+
+/// coinbase_tx 中脚本的起始偏移：version(4) + marker/flag(2) + 输入数(1) + prevout(36) + 脚本长度(1)
+const COINBASE_SCRIPT_OFFSET: usize = 44;
+
+/// witness commitment 输出脚本的前缀：OP_RETURN、push 36 字节和 0xaa21a9ed
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// coinbase_tx 中只计入 witness 的部分：marker/flag(2) + 栈元素数(1) + 长度(1) + 32 字节
+const COINBASE_WITNESS_LEN: usize = 36;
+
+/// 区块头 + nonce + mix_hash
+const BLOCK_HEADER_LEN: usize = 120;
+
+/// 为 coinbase 和区块头预留的 weight 与 sigops
+const COINBASE_RESERVED_WEIGHT: u64 = 4000;
+const COINBASE_RESERVED_SIGOPS: u32 = 400;
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+/// 区块时间最多比当前时间超前的秒数
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// 区块头时间：不早于模板的 mintime，且不超过 now + MAX_FUTURE_BLOCK_TIME
+pub fn header_time(now: u32, mintime: u64) -> u32 {
+    let mintime = mintime.min(u32::MAX as u64) as u32;
+    now.max(mintime)
+        .min(now.saturating_add(MAX_FUTURE_BLOCK_TIME))
+}
+
+/// 按手续费率（fee / weight）从高到低贪心挑选交易，返回按加入顺序排列的下标
+///
+/// 交易的 depends 为 GBT 中从 1 开始的下标，父交易总是先于子交易被选中，
+/// 依赖越界或 weight 为 0 的交易及其子交易不会被选中
+pub fn select_transactions(txs: &[Transaction], weight_limit: u64, sigop_limit: u32) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..txs.len()).filter(|i| txs[*i].weight > 0).collect();
+    candidates.sort_by(|a, b| {
+        let rate_a = txs[*a].fee as u128 * txs[*b].weight as u128;
+        let rate_b = txs[*b].fee as u128 * txs[*a].weight as u128;
+        rate_b.cmp(&rate_a)
+    });
+
+    let mut selected = Vec::new();
+    let mut included = vec![false; txs.len()];
+    let mut weight = 0u64;
+    let mut sigops = 0u32;
+    // 子交易可能排在父交易之前，需要多轮扫描直到没有新的交易加入
+    loop {
+        let mut progress = false;
+        for i in candidates.iter().copied() {
+            if included[i] {
+                continue;
+            }
+            let tx = &txs[i];
+            let parents_included = tx
+                .depends
+                .iter()
+                .all(|d| *d >= 1 && included.get(*d as usize - 1) == Some(&true));
+            if !parents_included {
+                continue;
+            }
+            let next_weight = weight + tx.weight as u64;
+            let next_sigops = sigops.saturating_add(tx.sigops);
+            if next_weight > weight_limit || next_sigops > sigop_limit {
+                continue;
+            }
+            weight = next_weight;
+            sigops = next_sigops;
+            included[i] = true;
+            selected.push(i);
+            progress = true;
+        }
+        if !progress {
+            break;
+        }
+    }
+    selected
+}
+
+/// 按 depends 对交易做拓扑排序，保证父交易在前，依赖关系允许时保持原有顺序
+///
+/// depends 越界、依赖自身或存在环时返回错误
+pub fn order_by_depends(txs: &[Transaction]) -> Result<Vec<usize>> {
+    let mut pending = vec![0usize; txs.len()];
+    let mut children = vec![Vec::new(); txs.len()];
+    for (i, tx) in txs.iter().enumerate() {
+        for d in &tx.depends {
+            let parent = match (*d as usize).checked_sub(1) {
+                Some(parent) if parent < txs.len() && parent != i => parent,
+                _ => bail!(RvnError::TemplateDecode(format!(
+                    "tx {} has invalid depends {}",
+                    i, d
+                ))),
+            };
+            pending[i] += 1;
+            children[parent].push(i);
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = (0..txs.len()).filter(|i| pending[*i] == 0).collect();
+    let mut order = Vec::with_capacity(txs.len());
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for child in &children[i] {
+            pending[*child] -= 1;
+            if pending[*child] == 0 {
+                ready.insert(*child);
+            }
+        }
+    }
+    if order.len() != txs.len() {
+        bail!(RvnError::TemplateDecode(
+            "cycle in transaction depends".to_string()
+        ));
+    }
+    Ok(order)
+}
+
+impl BlockTemplate {
+    pub fn builder(pool_addr: Address) -> BlockTemplateBuilder {
+        BlockTemplateBuilder::new(pool_addr)
+    }
+
+    pub fn new(
+        template_info: &BlockTemplateInfo,
+        pool_addr: Address,
+        pool_info: String,
+    ) -> Result<Self> {
+        Self::new_with_options(
+            template_info,
+            pool_addr,
+            pool_info,
+            &BlockTemplateOptions::default(),
+        )
+    }
+
+    /// 同 new，但使用调用方已有的 seed，同一高度反复生成模板时避免重复计算
+    pub fn new_with_seed(
+        template_info: &BlockTemplateInfo,
+        pool_addr: Address,
+        pool_info: String,
+        seed: [u8; 32],
+    ) -> Result<Self> {
+        let options = BlockTemplateOptions {
+            seed_hash: Some(seed),
+            ..Default::default()
+        };
+        Self::new_with_options(template_info, pool_addr, pool_info, &options)
+    }
+
+    pub fn new_with_options(
+        template_info: &BlockTemplateInfo,
+        pool_addr: Address,
+        pool_info: String,
+        options: &BlockTemplateOptions,
+    ) -> Result<Self> {
+        template_info.validate_target_bits()?;
+        let prev_hash = decode_prev_hash(template_info)?;
+        let bits = parse_bits(&template_info.bits)?;
+        let txids = decode_txids(&template_info.transactions)?;
+        Self::build(
+            template_info,
+            prev_hash,
+            bits,
+            &txids,
+            pool_addr,
+            pool_info,
+            options,
+        )
+    }
+
+    /// 同 new_with_options，但使用已解码的模板，同一模板按不同配置生成多个 BlockTemplate 时不再重复解码
+    pub fn from_normalized(
+        normalized: &NormalizedTemplate,
+        pool_addr: Address,
+        pool_info: String,
+        options: &BlockTemplateOptions,
+    ) -> Result<Self> {
+        Self::build(
+            &normalized.info,
+            normalized.prev_hash,
+            normalized.bits,
+            &normalized.txids,
+            pool_addr,
+            pool_info,
+            options,
+        )
+    }
+
+    /// txids 与 template_info.transactions 一一对应
+    fn build(
+        template_info: &BlockTemplateInfo,
+        prev_hash: [u8; 32],
+        bits: u32,
+        txids: &[[u8; 32]],
+        pool_addr: Address,
+        pool_info: String,
+        options: &BlockTemplateOptions,
+    ) -> Result<Self> {
+        let params = &options.chain_params;
+        if !params.is_kawpow(template_info.height) {
+            bail!(
+                "height {} is below kawpow activation height {}",
+                template_info.height,
+                params.kawpow_activation_height
+            );
+        }
+        template_info.validate_coinbase_value_with(params)?;
+        let expected_version = match pool_addr.kind() {
+            AddrKind::P2PKH => params.p2pkh_version,
+            AddrKind::P2SH => params.p2sh_version,
+        };
+        if pool_addr.version() != expected_version {
+            bail!(RvnError::InvalidAddress(format!(
+                "{} has version {}, expect {}",
+                pool_addr,
+                pool_addr.version(),
+                expected_version
+            )));
+        }
+        let pool_info = if pool_info.is_empty() {
+            params.coinbase_message.clone()
+        } else {
+            pool_info
+        };
+        let selected = if options.select_transactions {
+            select_transactions(
+                &template_info.transactions,
+                template_info
+                    .weight_limit
+                    .saturating_sub(COINBASE_RESERVED_WEIGHT),
+                template_info
+                    .sigop_limit
+                    .saturating_sub(COINBASE_RESERVED_SIGOPS),
+            )
+        } else {
+            order_by_depends(&template_info.transactions)?
+        };
+        let transactions: Vec<&Transaction> = selected
+            .iter()
+            .map(|&i| &template_info.transactions[i])
+            .collect();
+        let txids: Vec<[u8; 32]> = selected.iter().map(|&i| txids[i]).collect();
+        let coinbasevalue = recompute_coinbase_value(template_info.subsidy()?, &transactions);
+        if coinbasevalue > MAX_MONEY {
+            bail!(
+                "coinbasevalue {} exceeds max money {}",
+                coinbasevalue,
+                MAX_MONEY
+            );
+        }
+
+        let seed_hash = match options.seed_hash {
+            Some(seed) => seed,
+            None => kawpow_seed_for_epoch(params.epoch(template_info.height)),
+        };
+        let aux_flags = if options.include_coinbase_aux {
+            template_info.coinbase_aux_flags()?
+        } else {
+            Vec::new()
+        };
+        let (script, extranonce2_offset) = match &options.subscription {
+            Some(sub) => {
+                let (script, offset) = Script::coinbase_script_with_extranonce_and_aux(
+                    template_info.height,
+                    &aux_flags,
+                    &sub.extranonce1,
+                    sub.extranonce2_size,
+                    &pool_info,
+                )?;
+                (script, Some(COINBASE_SCRIPT_OFFSET + offset))
+            }
+            None => (
+                Script::coinbase_script_with_aux(
+                    template_info.height,
+                    &aux_flags,
+                    &pool_info,
+                    options.truncate_pool_info,
+                )?,
+                None,
+            ),
+        };
+        let coinbase_txin = Self::coinbase_txin(&script, options.coinbase_sequence);
+        let vout_to_miner = pool_addr.try_vout_to_miner()?;
+        // 交易或顺序与模板不同时，节点给出的 default_witness_commitment 不再适用
+        let reordered = !selected
+            .iter()
+            .copied()
+            .eq(0..template_info.transactions.len());
+        let witness_vout = if !reordered && ct_eq(&options.witness_reserved_value, &[0; 32]) {
+            let commitment = parse_witness_commitment(&template_info.default_witness_commitment)
+                .map_err(|e| {
+                    RvnError::TemplateDecode(format!("default_witness_commitment: {}", e))
+                })?;
+            witness_commitment_script(&commitment)
+        } else {
+            hex::decode(witness_commitment(
+                &transactions,
+                &options.witness_reserved_value,
+            )?)?
+        };
+
+        let asset_value = options
+            .asset_outputs
+            .iter()
+            .try_fold(0u64, |acc, output| acc.checked_add(output.value));
+        let pool_value = match asset_value.and_then(|v| coinbasevalue.checked_sub(v)) {
+            Some(value) => value,
+            None => bail!("asset outputs exceed coinbasevalue {}", coinbasevalue),
+        };
+        let omit_payout = pool_value == 0;
+        if omit_payout && !params.omit_zero_payout {
+            bail!("pool payout is zero, set ChainParams::omit_zero_payout to drop the output");
+        }
+        // 输出顺序固定：矿池输出、资产输出，witness commitment 总在最后；省略矿池输出时只剩后两者
+        let output_count = options.asset_outputs.len() as u64 + 2 - omit_payout as u64;
+        let mut outputs = OpData::default().var_push_num(output_count);
+        if !omit_payout {
+            outputs = outputs.push_u64(pool_value).op_push_slice(&vout_to_miner);
+        }
+        for output in &options.asset_outputs {
+            outputs = outputs
+                .push_u64(output.value)
+                .var_push_num(output.script.len() as u64)
+                .push_slice(&output.script);
+        }
+        let outputs = outputs.push_slice(&[0; 8]).op_push_slice(&witness_vout);
+
+        let coinbase = CoinbaseBuilder {
+            version: options.coinbase_version,
+            txin: coinbase_txin,
+            outputs: outputs.as_slice().to_vec(),
+            witness_reserved_value: options.witness_reserved_value,
+            locktime: options.locktime,
+        };
+        let coinbase_tx = coinbase.to_bytes();
+        let (coinbase_prefix, coinbase_suffix) = match (&options.subscription, extranonce2_offset) {
+            (Some(sub), Some(offset)) => split_coinbase(&coinbase_tx, offset, sub.extranonce2_size),
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        if options.verify_payout && !omit_payout {
+            // 输出数之后是 8 字节 value 和 1 字节脚本长度
+            let start = OpData::default()
+                .var_push_num(output_count)
+                .as_slice()
+                .len()
+                + 9;
+            let script = &outputs.as_slice()[start..start + vout_to_miner.len()];
+            if outputs.as_slice()[start - 1] as usize != vout_to_miner.len()
+                || !pool_addr.matches_script(script)
+            {
+                bail!("coinbase output does not pay to {}", pool_addr);
+            }
+        }
+
+        let coinbase_txid = coinbase.txid();
+        let incoming_txs: Vec<_> = transactions.iter().map(|s| s.data.clone()).collect();
+        let external_weight = transactions.iter().map(|tx| tx.weight as u64).sum();
+        let external_sigops = transactions
+            .iter()
+            .fold(0u32, |acc, tx| acc.saturating_add(tx.sigops));
+        let external_fees = recompute_coinbase_value(0, &transactions);
+        if txids.len() + 1 > MAX_MERKLE_LEAVES {
+            bail!(
+                "too many merkle leaves: {} > {}",
+                txids.len() + 1,
+                MAX_MERKLE_LEAVES
+            );
+        }
+        let merkle_branches = merkle_branches(&txids);
+        let merkle = merkle_root_from_coinbase(coinbase_txid, &merkle_branches);
+        // merkle 的叶子总是包含 coinbase，没有其余交易时根就是 coinbase 的 txid
+        debug_assert_eq!(
+            merkle,
+            merkel_hash(
+                std::iter::once(coinbase_txid)
+                    .chain(txids.iter().copied())
+                    .collect()
+            )
+        );
+
+        // calculate header
+        let ts = options.ntime_policy.header_time(template_info.mintime);
+        let version = options.version.unwrap_or(template_info.version) | template_info.vbrequired;
+        let header = Header {
+            version,
+            prev_hash,
+            merkle_root: merkle,
+            time: ts,
+            bits,
+            height: template_info.height,
+        };
+        let header_hash = header.hash();
+        let header = header.to_bytes();
+
+        let obj = Self {
+            pool_addr,
+            pool_info,
+            coinbase_tx,
+            witness_hex: hex::encode(&witness_vout),
+            default_witness_hex: template_info.default_witness_commitment.clone(),
+            coinbase_txid,
+            seed_hash,
+            header,
+            header_hash,
+            prev_hash: prev_hash.to_vec(),
+            prev_hash_hex: template_info.previousblockhash.to_lowercase(),
+            timestamp: ts,
+            built_at: now(),
+            external_txs: incoming_txs,
+            external_txids: txids,
+            target_hex: template_info.target.clone(),
+            bits_hex: template_info.bits.clone(),
+            version,
+            height: template_info.height,
+            extranonce2_offset,
+            coinbase_prefix,
+            coinbase_suffix,
+            long_poll_id: template_info.long_poll_id.clone(),
+            external_weight,
+            external_sigops,
+            external_fees,
+            merkle_branches,
+            fixed_time: !template_info.may_mutate("time"),
+        };
+        Ok(obj)
+    }
+
+    /// 将矿机的 extranonce2 写入 coinbase 预留区域，即 coinbase_prefix、extranonce2、coinbase_suffix 的拼接
+    pub fn coinbase_tx_with_extranonce2(&self, extranonce2: &[u8]) -> Result<Vec<u8>> {
+        let size = self.extranonce2_size()?;
+        if extranonce2.len() != size {
+            bail!(
+                "invalid extranonce2 len {}, expect {}",
+                extranonce2.len(),
+                size
+            );
+        }
+        let mut coinbase_tx = Vec::with_capacity(self.coinbase_tx.len());
+        coinbase_tx.extend_from_slice(&self.coinbase_prefix);
+        coinbase_tx.extend_from_slice(extranonce2);
+        coinbase_tx.extend_from_slice(&self.coinbase_suffix);
+        Ok(coinbase_tx)
+    }
+
+    /// 由缓存的 coinbase_prefix 和 coinbase_suffix 推出 extranonce2 的长度
+    fn extranonce2_size(&self) -> Result<usize> {
+        match self.extranonce2_offset {
+            Some(offset) if offset == self.coinbase_prefix.len() => Ok(self
+                .coinbase_tx
+                .len()
+                .saturating_sub(offset + self.coinbase_suffix.len())),
+            Some(_) => bail!("coinbase prefix does not match extranonce2 offset"),
+            None => bail!("coinbase has no extranonce region"),
+        }
+    }
+
+    /// 写入 extranonce2 后的 coinbase 及其 txid（内部序），txid 配合 merkle_branches 可算出新的 merkle 根
+    pub fn coinbase_with_extranonce2(&self, extranonce2: &[u8]) -> Result<(Vec<u8>, [u8; 32])> {
+        let coinbase_tx = self.coinbase_tx_with_extranonce2(extranonce2)?;
+        let txid = txid_from_raw(&coinbase_tx)?;
+        Ok((coinbase_tx, txid))
+    }
+
+    /// 去掉交易数据的元数据投影，见 BlockTemplateSummary
+    pub fn summary(&self) -> BlockTemplateSummary {
+        BlockTemplateSummary {
+            height: self.height,
+            version: self.version,
+            timestamp: self.timestamp,
+            header_hash: self.header_hash,
+            seed_hash: self.seed_hash,
+            prev_hash_hex: self.prev_hash_hex.clone(),
+            target_hex: self.target_hex.clone(),
+            bits_hex: self.bits_hex.clone(),
+            tx_count: self.external_txs.len() + 1,
+            external_weight: self.external_weight,
+            external_fees: self.external_fees,
+            long_poll_id: self.long_poll_id.clone(),
+        }
+    }
+
+    /// proposal 模式的区块 hex，nonce 和 mix_hash 填 0
+    pub fn to_proposal_hex(&self) -> String {
+        serialize_block(
+            &self.header,
+            &hex::encode([0; 8]),
+            &hex::encode([0; 32]),
+            &self.coinbase_tx,
+            &self.external_txs,
+        )
+    }
+
+    /// 用矿机的解组装 submitblock 的区块 hex，nonce 和 mix_hash 需已是区块中的字节序
+    pub fn assemble_block(&self, ntime: u32, nonce: &str, mix_hash: &str) -> Result<String> {
+        let header = Header::from_bytes(&self.header)?.with_time(ntime, self.fixed_time)?;
+        Ok(serialize_block(
+            &header.to_bytes(),
+            nonce,
+            mix_hash,
+            &self.coinbase_tx,
+            &self.external_txs,
+        ))
+    }
+
+    /// 区块头和交易数的序列化长度
+    fn header_len(&self) -> u64 {
+        let tx_count = OpData::default().var_push_num(self.external_txs.len() as u64 + 1);
+        (BLOCK_HEADER_LEN + tx_count.as_slice().len()) as u64
+    }
+
+    /// 完整区块序列化后的字节数
+    pub fn size(&self) -> u64 {
+        let external_size: usize = self.external_txs.iter().map(|tx| tx.len() / 2).sum();
+        self.header_len() + (self.coinbase_tx.len() + external_size) as u64
+    }
+
+    /// coinbase 的 weight：base_size * 3 + total_size，base_size 为去掉 witness 后的长度
+    pub fn coinbase_weight(&self) -> u64 {
+        let base_size = match split_witness(&self.coinbase_tx) {
+            Ok((stripped, _)) => stripped.len(),
+            // 无法解析时按 coinbase_tx 固定的 witness 长度估算
+            Err(_) => self.coinbase_tx.len().saturating_sub(COINBASE_WITNESS_LEN),
+        };
+        base_size as u64 * 3 + self.coinbase_tx.len() as u64
+    }
+
+    /// 完整区块的 weight，非 witness 数据计 4 倍
+    pub fn weight(&self) -> u64 {
+        self.header_len() * 4 + self.coinbase_weight() + self.external_weight
+    }
+
+    /// 检查区块不超过节点给出的 weight 和 size 限制
+    pub fn fits_limits(&self, template_info: &BlockTemplateInfo) -> Result<()> {
+        let weight = self.weight();
+        if weight > template_info.weight_limit {
+            bail!(
+                "block weight {} exceeds limit {}",
+                weight,
+                template_info.weight_limit
+            );
+        }
+        let size = self.size();
+        if size > template_info.size_limit {
+            bail!(
+                "block size {} exceeds limit {}",
+                size,
+                template_info.size_limit
+            );
+        }
+        self.fits_sigop_limit(template_info)
+    }
+
+    /// coinbase 的 sigop cost：输入和输出脚本中的 legacy sigops 计 4 倍，与模板中交易的 sigops 单位一致
+    pub fn coinbase_sigops(&self) -> Result<u32> {
+        let tx = self.coinbase()?;
+        let scripts = tx
+            .input
+            .iter()
+            .map(|txin| &txin.script_sig)
+            .chain(tx.output.iter().map(|out| &out.script_pubkey));
+        let mut sigops = 0u32;
+        for script in scripts {
+            for ins in script.instructions() {
+                sigops += match ins {
+                    Ok(Instruction::Op(op)) if op == OP_CHECKSIG || op == OP_CHECKSIGVERIFY => 1,
+                    Ok(Instruction::Op(op))
+                        if op == OP_CHECKMULTISIG || op == OP_CHECKMULTISIGVERIFY =>
+                    {
+                        20
+                    }
+                    // 与节点一致，遇到无法解析的 push 后停止计数
+                    Err(_) => break,
+                    _ => 0,
+                };
+            }
+        }
+        Ok(sigops * 4)
+    }
+
+    /// coinbase 与其余交易的 sigop cost 之和
+    pub fn total_sigops(&self) -> Result<u32> {
+        Ok(self.coinbase_sigops()?.saturating_add(self.external_sigops))
+    }
+
+    /// 检查 sigop cost 不超过节点给出的 sigoplimit
+    pub fn fits_sigop_limit(&self, template_info: &BlockTemplateInfo) -> Result<()> {
+        let sigops = self.total_sigops()?;
+        if sigops > template_info.sigop_limit {
+            bail!(
+                "block sigops {} exceeds limit {}",
+                sigops,
+                template_info.sigop_limit
+            );
+        }
+        Ok(())
+    }
+
+    fn coinbase(&self) -> Result<bitcoin::Transaction> {
+        Ok(bitcoin::consensus::deserialize(&self.coinbase_tx)?)
+    }
+
+    fn coinbase_outputs(&self) -> Result<Vec<bitcoin::TxOut>> {
+        Ok(self.coinbase()?.output)
+    }
+
+    /// coinbase 的输出数
+    pub fn output_count(&self) -> Result<usize> {
+        Ok(self.coinbase_outputs()?.len())
+    }
+
+    /// witness commitment 输出在 coinbase 中的下标，总是最后一个输出
+    pub fn witness_output_index(&self) -> Result<usize> {
+        let outputs = self.coinbase_outputs()?;
+        match outputs.iter().rposition(|out| {
+            out.script_pubkey
+                .as_bytes()
+                .starts_with(&WITNESS_COMMITMENT_HEADER)
+        }) {
+            Some(index) if index + 1 == outputs.len() => Ok(index),
+            Some(index) => bail!(
+                "witness commitment at output {} is not the last of {}",
+                index,
+                outputs.len()
+            ),
+            None => bail!("coinbase has no witness commitment output"),
+        }
+    }
+
+    /// coinbase witness 中的 reserved value，紧接在 locktime 之前
+    fn witness_reserved_value(&self) -> Result<[u8; 32]> {
+        let len = self.coinbase_tx.len();
+        match self
+            .coinbase_tx
+            .get(len.saturating_sub(36)..len.saturating_sub(4))
+        {
+            Some(reserved) if len >= 36 => Ok(reserved.try_into()?),
+            _ => bail!("coinbase too short: {}", len),
+        }
+    }
+
+    /// 替换 coinbase 之外的交易，txs 需已按依赖排序。
+    ///
+    /// 同时更新 coinbase 中的 witness commitment 和矿池输出的金额（按手续费的变化增减），
+    /// 以及 coinbase txid、merkle 根和区块头，避免 external_txs 与 commitment 不一致
+    pub fn set_transactions(&mut self, txs: &[&Transaction]) -> Result<()> {
+        if txs.len() + 1 > MAX_MERKLE_LEAVES {
+            bail!(
+                "too many merkle leaves: {} > {}",
+                txs.len() + 1,
+                MAX_MERKLE_LEAVES
+            );
+        }
+        let mut txids = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let txid = decode_hash32_reversed(&tx.txid)
+                .map_err(|e| RvnError::TemplateDecode(format!("txid: {}", e)))?;
+            txids.push(txid);
+        }
+        let reserved = self.witness_reserved_value()?;
+        let witness_hex = witness_commitment(txs, &reserved)?;
+        let commitment = hex::decode(&witness_hex)?;
+        let fees = recompute_coinbase_value(0, txs);
+
+        let mut coinbase = self.coinbase()?;
+        let index = self.witness_output_index()?;
+        coinbase.output[index].script_pubkey = bitcoin::Script::from(commitment);
+        // 矿池输出总在第一个，金额为 0 时可能已被省略
+        if self
+            .pool_addr
+            .matches_script(coinbase.output[0].script_pubkey.as_bytes())
+        {
+            let pool_value = coinbase.output[0]
+                .value
+                .checked_sub(self.external_fees)
+                .and_then(|v| v.checked_add(fees));
+            coinbase.output[0].value = match pool_value {
+                Some(value) => value,
+                None => bail!(
+                    "pool output {} can not cover fee change {} -> {}",
+                    coinbase.output[0].value,
+                    self.external_fees,
+                    fees
+                ),
+            };
+        } else if fees != self.external_fees {
+            bail!(
+                "coinbase has no pool output to take fee change {} -> {}",
+                self.external_fees,
+                fees
+            );
+        }
+        // 输出长度不变，extranonce2_offset 仍然有效，但输出所在的 coinbase_suffix 需要重新切分
+        let coinbase_tx = bitcoin::consensus::serialize(&coinbase);
+        if let Some(offset) = self.extranonce2_offset {
+            let size = self.extranonce2_size()?;
+            (self.coinbase_prefix, self.coinbase_suffix) =
+                split_coinbase(&coinbase_tx, offset, size);
+        }
+        let coinbase_txid = txid_from_raw(&coinbase_tx)?;
+        let merkle_branches = merkle_branches(&txids);
+        let mut header = Header::from_bytes(&self.header)?;
+        header.merkle_root = merkle_root_from_coinbase(coinbase_txid, &merkle_branches);
+
+        self.coinbase_tx = coinbase_tx;
+        self.coinbase_txid = coinbase_txid;
+        self.merkle_branches = merkle_branches;
+        self.header_hash = header.hash();
+        self.header = header.to_bytes();
+        self.witness_hex = witness_hex;
+        self.external_txs = txs.iter().map(|tx| tx.data.clone()).collect();
+        self.external_txids = txids;
+        self.external_weight = txs.iter().map(|tx| tx.weight as u64).sum();
+        self.external_sigops = txs
+            .iter()
+            .fold(0u32, |acc, tx| acc.saturating_add(tx.sigops));
+        self.external_fees = fees;
+        self.built_at = now();
+        Ok(())
+    }
+
+    /// 检查区块头中的 bits、bits_hex 和 target_hex 一致，避免按一个目标接受份额、却按另一个目标构造区块头。
+    /// 优先级见 check_target_bits：bits 决定区块头，target_hex 只能有压缩带来的精度差异
+    pub fn validate_consistency(&self) -> Result<()> {
+        let header = Header::from_bytes(&self.header)?;
+        let bits = parse_bits(&self.bits_hex)?;
+        if header.bits != bits {
+            bail!(RvnError::InvalidBits(format!(
+                "header bits {:08x} mismatch bits_hex {}",
+                header.bits, self.bits_hex
+            )));
+        }
+        check_target_bits(&self.bits_hex, &self.target_hex)
+    }
+
+    /// 检查每笔 external_txs 都能解码，且由去掉 witness 的序列化算出的 txid 与模板声明的一致，
+    /// 在提交前发现节点或序列化的问题；出错时报告交易的序号
+    pub fn validate_external_txs(&self) -> Result<()> {
+        if self.external_txids.len() != self.external_txs.len() {
+            bail!(
+                "{} external txs but {} declared txids",
+                self.external_txs.len(),
+                self.external_txids.len()
+            );
+        }
+        for (i, (tx, declared)) in self
+            .external_txs
+            .iter()
+            .zip(&self.external_txids)
+            .enumerate()
+        {
+            let raw = match hex::decode(tx) {
+                Ok(raw) => raw,
+                Err(e) => bail!("external tx {} is not valid hex: {}", i, e),
+            };
+            let txid = match txid_from_raw(&raw) {
+                Ok(txid) => txid,
+                Err(e) => bail!("invalid external tx {}: {}", i, e),
+            };
+            if txid != *declared {
+                bail!(
+                    "external tx {} txid mismatch: {} != {}",
+                    i,
+                    hex::encode(reverse_bytes(&txid)),
+                    hex::encode(reverse_bytes(declared))
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// 提交前的自检：由 coinbase 和其余交易重算 merkle 根和 witness commitment，
+    /// 与区块头及 coinbase 中的值比较
+    pub fn self_check(&self) -> Result<()> {
+        let header = Header::from_bytes(&self.header)?;
+        let coinbase_txid = txid_from_raw(&self.coinbase_tx)?;
+        if !ct_eq(&coinbase_txid, &self.coinbase_txid) {
+            bail!(
+                "coinbase txid mismatch: {} != {}",
+                hex::encode(reverse_bytes(&coinbase_txid)),
+                hex::encode(reverse_bytes(&self.coinbase_txid))
+            );
+        }
+
+        let mut txids = vec![coinbase_txid];
+        // coinbase 的 wtxid 为 0
+        let mut wtxids = vec![[0; 32]];
+        for tx in &self.external_txs {
+            let raw = hex::decode(tx)?;
+            txids.push(txid_from_raw(&raw)?);
+            wtxids.push(dsha256(&raw));
+        }
+        let merkle = merkel_hash(txids);
+        if !ct_eq(&merkle, &header.merkle_root) {
+            bail!(
+                "merkle root mismatch: {} != {}",
+                hex::encode(reverse_bytes(&merkle)),
+                hex::encode(reverse_bytes(&header.merkle_root))
+            );
+        }
+
+        let reserved = self.witness_reserved_value()?;
+        let mut data = merkel_hash(wtxids).to_vec();
+        data.extend_from_slice(&reserved);
+        let commitment = witness_commitment_script(&dsha256(&data));
+        if !self
+            .coinbase_tx
+            .windows(commitment.len())
+            .any(|w| w == commitment.as_slice())
+        {
+            bail!(
+                "witness commitment {} not found in coinbase",
+                hex::encode(&commitment)
+            );
+        }
+        self.witness_output_index()?;
+        Ok(())
+    }
+
+    /// target_hex: like "00000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+    pub fn create_job(&self, target_hex: String, refresh: bool) -> JobInfo {
+        JobInfo {
+            header_hash: self.header_hash_display(),
+            seed_hash: self.seed_hash_hex(),
+            share_target_hex: target_hex,
+            block_target_hex: self.target_hex.clone(),
+            height: self.height,
+            block_bits_hex: self.bits_hex.clone(),
+            refresh,
+            header: self.header.clone(),
+            external_txs: self.external_txs.clone(),
+            coinbase_tx: self.coinbase_tx.clone(),
+            timestamp: self.timestamp,
+            fixed_time: self.fixed_time,
+        }
+    }
+
+    /// 同 create_job，但会检查生成的任务格式，target_hex 不是 64 位 hex 时报错
+    pub fn try_create_job(&self, target_hex: String, refresh: bool) -> Result<JobInfo> {
+        let job = self.create_job(target_hex, refresh);
+        job.validate()?;
+        Ok(job)
+    }
+
+    /// 按矿池难度生成任务，份额目标不会比区块目标更难，找到区块的解总能作为份额提交
+    pub fn create_job_for_difficulty(&self, diff: u64, refresh: bool) -> Result<JobInfo> {
+        let block_target = uint256_from_hash(&self.target_hex)?;
+        let share_target = clamp_share_target(diff2target(diff), block_target);
+        self.try_create_job(format_target_hex(&share_target), refresh)
+    }
+
+    /// 为每个难度生成一个任务，顺序与 diffs 一致，份额目标同样不会比区块目标更难；
+    /// 需要避免复制交易数据时使用 SharedJob
+    pub fn jobs_for_difficulties(&self, diffs: &[u64], refresh: bool) -> Result<Vec<JobInfo>> {
+        let block_target = uint256_from_hash(&self.target_hex)?;
+        diffs
+            .iter()
+            .map(|diff| {
+                let share_target = clamp_share_target(diff2target(*diff), block_target);
+                self.try_create_job(format_target_hex(&share_target), refresh)
+            })
+            .collect()
+    }
+
+    /// coinbase 的输入：空的 prevout（32 字节 0 + 0xffffffff）、脚本和 sequence
+    fn coinbase_txin(script: &Script, sequence: u32) -> Vec<u8> {
+        OpData::default()
+            .push_slice(&[0; 32])
+            .push_slice(&[0xff; 4])
+            .var_push_num(script.as_slice().len() as u64)
+            .push_slice(script.as_slice())
+            .push_u32(sequence)
+            .as_slice()
+            .to_vec()
+    }
+
+    /// 显示序即下发给矿机的 header_hash，内部序即区块头 dsha256 的原始输出
+    pub fn header_hash_in(&self, orientation: HashOrientation) -> [u8; 32] {
+        HashOrientation::Display.convert(self.header_hash, orientation)
+    }
+
+    pub fn header_hash_display(&self) -> [u8; 32] {
+        self.header_hash_in(HashOrientation::Display)
+    }
+
+    pub fn header_hash_internal(&self) -> [u8; 32] {
+        self.header_hash_in(HashOrientation::Internal)
+    }
+
+    /// 内部序用于 merkle 计算，显示序即区块浏览器中的 txid
+    pub fn coinbase_txid_in(&self, orientation: HashOrientation) -> [u8; 32] {
+        HashOrientation::Internal.convert(self.coinbase_txid, orientation)
+    }
+
+    /// 内部序即区块头中的 4..36 字节，显示序即 previousblockhash
+    pub fn prev_hash_in(&self, orientation: HashOrientation) -> Result<[u8; 32]> {
+        let prev_hash: [u8; 32] = match self.prev_hash.as_slice().try_into() {
+            Ok(hash) => hash,
+            Err(_) => bail!("invalid prev_hash length {}", self.prev_hash.len()),
+        };
+        Ok(HashOrientation::Internal.convert(prev_hash, orientation))
+    }
+
+    /// 下发给矿机的 seed_hash：keccak256 的原始输出直接编码，不反转。
+    /// seed 不参与区块头和 merkle 计算，没有显示序之分，kawpowminer 等矿机按 ethash 的约定使用原始字节
+    pub fn seed_hash_hex(&self) -> String {
+        hex::encode(self.seed_hash)
+    }
+
+    /// 显示序的 prev_hash，即 previousblockhash，用于日志
+    pub fn prev_hash_display(&self) -> String {
+        if !self.prev_hash_hex.is_empty() {
+            return self.prev_hash_hex.clone();
+        }
+        hex::encode(reverse_bytes(&self.prev_hash))
+    }
+
+    /// 可读的派生字段，哈希均为显示序，便于与节点的 getblock 对比
+    pub fn debug_report(&self) -> String {
+        let merkle_root = self
+            .header
+            .get(36..68)
+            .map(reverse_bytes)
+            .unwrap_or_default();
+        format!(
+            "height:        {}\n\
+             prev hash:     {}\n\
+             coinbase:      {}\n\
+             coinbase txid: {}\n\
+             merkle root:   {}\n\
+             header:        {}\n\
+             header hash:   {}\n\
+             target:        {}\n\
+             bits:          {}\n",
+            self.height,
+            self.prev_hash_display(),
+            hex::encode(&self.coinbase_tx),
+            hex::encode(reverse_bytes(&self.coinbase_txid)),
+            hex::encode(merkle_root),
+            hex::encode(&self.header),
+            hex::encode(self.header_hash),
+            self.target_hex,
+            self.bits_hex,
+        )
+    }
+
+    /// 节点的交易池更新时 longpollid 会变化，即使高度没变
+    pub fn longpollid_changed(&self, template_info: &BlockTemplateInfo) -> bool {
+        self.long_poll_id != template_info.long_poll_id
+    }
+
+    /// 同一高度下 previousblockhash 变化说明发生了重组，旧任务挖的是被淘汰的链
+    pub fn prev_hash_changed(&self, template_info: &BlockTemplateInfo) -> bool {
+        // 旧版本序列化的模板没有 prev_hash_hex，退回到由 prev_hash 计算
+        if self.prev_hash_hex.is_empty() {
+            return !self
+                .prev_hash_display()
+                .eq_ignore_ascii_case(&template_info.previousblockhash);
+        }
+        !self
+            .prev_hash_hex
+            .eq_ignore_ascii_case(&template_info.previousblockhash)
+    }
+
+    pub fn is_new_template(&self, template_info: &BlockTemplateInfo) -> bool {
+        self.height != template_info.height
+            || self.prev_hash_changed(template_info)
+            || now().saturating_sub(self.built_at) > 60
+            || self.default_witness_hex != template_info.default_witness_commitment
+            || self.longpollid_changed(template_info)
+    }
+}
+
+/// 8 字节 KawPow nonce 的高 4 字节是否在 noncerange 内，两端均包含
+pub fn nonce_in_range(nonce: u64, (start, end): (u32, u32)) -> bool {
+    let prefix = (nonce >> 32) as u32;
+    start <= prefix && prefix <= end
+}
+
+/// bits 与 target 的优先级：区块头使用 bits，节点也按 bits 还原的目标检查区块；
+/// target 只用于比较份额和区块，二者只允许有 bits 压缩带来的精度差异，否则报错
+fn check_target_bits(bits_hex: &str, target_hex: &str) -> Result<()> {
+    let bits = parse_bits(bits_hex)?;
+    let target = uint256_from_hash(target_hex)?;
+    if bits2target(bits) != target && target2bits(target) != bits {
+        bail!(RvnError::InvalidBits(format!(
+            "{} mismatch target {}",
+            bits_hex, target_hex
+        )));
+    }
+    Ok(())
+}
+
+/// 由交易的 wtxid 和 coinbase witness 中的 reserved value 计算 witness commitment 输出脚本的 hex，
+/// reserved value 为 0 时与节点的 default_witness_commitment 一致
+pub fn witness_commitment(transactions: &[&Transaction], reserved: &[u8; 32]) -> Result<String> {
+    // coinbase 的 wtxid 为 0
+    let mut wtxids = vec![[0; 32]];
+    for tx in transactions {
+        let wtxid = decode_hash32_reversed(&tx.hash)
+            .map_err(|e| RvnError::TemplateDecode(format!("hash: {}", e)))?;
+        wtxids.push(wtxid);
+    }
+    let mut data = merkel_hash(wtxids).to_vec();
+    data.extend_from_slice(reserved);
+    Ok(hex::encode(witness_commitment_script(&dsha256(&data))))
+}
+
+/// witness commitment 输出的完整脚本：OP_RETURN、push 36 字节、0xaa21a9ed 和 32 字节的 commitment
+pub fn witness_commitment_script(commitment: &[u8; 32]) -> Vec<u8> {
+    let mut script = WITNESS_COMMITMENT_HEADER.to_vec();
+    script.extend_from_slice(commitment);
+    script
+}
+
+/// witness_commitment_script 的逆运算，从输出脚本的 hex 中取出 32 字节的 commitment
+pub fn parse_witness_commitment(script_hex: &str) -> Result<[u8; 32]> {
+    let script = hex::decode(script_hex)?;
+    match script.strip_prefix(&WITNESS_COMMITMENT_HEADER[..]) {
+        Some(commitment) if commitment.len() == 32 => Ok(commitment.try_into()?),
+        _ => bail!("invalid witness commitment script: {}", script_hex),
+    }
+}
+
+/// 将字节按 hex 写入 w，每次编码一小段，不分配完整的字符串
+fn write_hex<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut buf = [0; 1024];
+    for chunk in data.chunks(buf.len() / 2) {
+        let out = &mut buf[..chunk.len() * 2];
+        hex::encode_to_slice(chunk, out).expect("hex buffer length");
+        w.write_all(out)?;
+    }
+    Ok(())
+}
+
+/// 与 serialize_block 输出相同的 hex，但直接写入 w，适合大区块直接写 socket 或文件
+pub fn write_block<W: Write>(
+    w: &mut W,
+    header: &[u8],
+    nonce: &str,
+    mix_hash: &str,
+    coinbase_tx: &[u8],
+    external_txs: &[String],
+) -> io::Result<()> {
+    let op_data = OpData::default().var_push_num(external_txs.len() as u64 + 1);
+    write_hex(w, header)?;
+    w.write_all(nonce.as_bytes())?;
+    w.write_all(mix_hash.as_bytes())?;
+    write_hex(w, op_data.as_slice())?;
+    write_hex(w, coinbase_tx)?;
+    for tx in external_txs {
+        w.write_all(tx.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// serialize_block 的逆运算，返回区块头（不含 nonce 和 mix_hash）以及包括 coinbase 在内的全部交易的 hex
+pub fn parse_block(block_hex: &str) -> Result<(Header, Vec<String>)> {
+    let block = parse_full_block(block_hex)?;
+    let mut txs = Vec::with_capacity(block.txs.len() + 1);
+    txs.push(hex::encode(&block.coinbase_tx));
+    txs.extend(block.txs);
+    Ok((block.header, txs))
+}
+
+/// 解析后的完整区块，如 submitblock 的参数或 getblock 返回的原始区块
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParsedBlock {
+    pub header: Header,
+    /// 区块中的字节序
+    pub nonce: [u8; 8],
+    /// 区块中的字节序
+    pub mix_hash: [u8; 32],
+    pub coinbase_tx: Vec<u8>,
+    /// coinbase 之外的交易
+    pub txs: Vec<String>,
+}
+
+/// ParsedBlock 与本地模板不一致的部分
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BlockDiff {
+    Version,
+    PrevHash,
+    MerkleRoot,
+    Time,
+    Bits,
+    Height,
+    Coinbase,
+    TxSet,
+}
+
+impl ParsedBlock {
+    /// 与本地模板比较，区块被拒绝时用于定位问题；
+    /// 矿机滚动的 ntime 和 extranonce2 也会表现为 Time 和 Coinbase 的差异
+    pub fn diff_against(&self, template: &BlockTemplate) -> Result<Vec<BlockDiff>> {
+        let local = Header::from_bytes(&template.header)?;
+        let mut diffs = Vec::new();
+        let fields = [
+            (self.header.version == local.version, BlockDiff::Version),
+            (
+                self.header.prev_hash == local.prev_hash,
+                BlockDiff::PrevHash,
+            ),
+            (
+                self.header.merkle_root == local.merkle_root,
+                BlockDiff::MerkleRoot,
+            ),
+            (self.header.time == local.time, BlockDiff::Time),
+            (self.header.bits == local.bits, BlockDiff::Bits),
+            (self.header.height == local.height, BlockDiff::Height),
+            (
+                self.coinbase_tx == template.coinbase_tx,
+                BlockDiff::Coinbase,
+            ),
+            (self.txs == template.external_txs, BlockDiff::TxSet),
+        ];
+        for (same, field) in fields {
+            if !same {
+                diffs.push(field);
+            }
+        }
+        Ok(diffs)
+    }
+}
+
+/// 解析完整区块，交易数为 0（没有 coinbase）或有多余字节时报错
+pub fn parse_full_block(block_hex: &str) -> Result<ParsedBlock> {
+    let raw = hex::decode(block_hex)?;
+    let mut reader = OpReader::new(&raw);
+    let header = Header::from_bytes(reader.take(HEADER_LEN)?)?;
+    let nonce = reader.take(8)?.try_into()?;
+    let mix_hash = reader.take(32)?.try_into()?;
+    let count = reader.read_var_len()?;
+    if count == 0 {
+        bail!("block has no coinbase");
+    }
+    let start = reader.pos();
+    let (_, _, tx_len) = read_tx(&mut reader)?;
+    let coinbase_tx = raw[start..start + tx_len].to_vec();
+    let mut txs = Vec::new();
+    for _ in 1..count {
+        let start = reader.pos();
+        let (_, _, tx_len) = read_tx(&mut reader)?;
+        txs.push(hex::encode(&raw[start..start + tx_len]));
+    }
+    if reader.remaining() != 0 {
+        bail!("{} trailing bytes after block", reader.remaining());
+    }
+    Ok(ParsedBlock {
+        header,
+        nonce,
+        mix_hash,
+        coinbase_tx,
+        txs,
+    })
+}
+
+/// 检查 coinbase 和 external_txs 的每一项都恰好是一笔完整的交易，
+/// 否则 serialize_block 写入的交易数与实际交易不符，节点无法解析区块
+pub fn check_block_txs(coinbase_tx: &[u8], external_txs: &[String]) -> Result<()> {
+    let check = |raw: &[u8]| -> Result<()> {
+        let mut reader = OpReader::new(raw);
+        read_tx(&mut reader)?;
+        if reader.remaining() != 0 {
+            bail!("{} trailing bytes after tx", reader.remaining());
+        }
+        Ok(())
+    };
+    if let Err(e) = check(coinbase_tx) {
+        bail!("invalid coinbase tx: {}", e);
+    }
+    for (i, tx) in external_txs.iter().enumerate() {
+        let raw = match hex::decode(tx) {
+            Ok(raw) => raw,
+            Err(e) => bail!("external tx {} is not valid hex: {}", i, e),
+        };
+        if let Err(e) = check(&raw) {
+            bail!("invalid external tx {}: {}", i, e);
+        }
+    }
+    Ok(())
+}
+
+/// 区块的序列化：区块头、nonce、mix_hash、交易数、coinbase 以及其余交易
+pub fn serialize_block(
+    header: &[u8],
+    nonce: &str,
+    mix_hash: &str,
+    coinbase_tx: &[u8],
+    external_txs: &[String],
+) -> String {
+    let op_data = OpData::default().var_push_num(external_txs.len() as u64 + 1);
+    format!(
+        "{}{}{}{}{}{}",
+        hex::encode(header),
+        nonce,
+        mix_hash,
+        hex::encode(op_data.as_slice()),
+        hex::encode(coinbase_tx),
+        external_txs.concat()
+    )
+}
+
+/// 区块头（不含 nonce 和 mix_hash）的 dsha256，反转为显示序，即 KawPow 的 header_hash，
+/// 不是区块哈希，区块哈希见 JobInfo::block_hash
+pub fn header_hash_display(header: &[u8]) -> [u8; 32] {
+    reverse_hash(dsha256(header))
+}
+
+/// 双重 sha256，返回内部序
+pub fn dsha256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0; 32];
+    dsha256_into(data, &mut out);
+    out
+}
+
+pub fn dsha256_into(data: &[u8], out: &mut [u8; 32]) {
+    Dsha256::default().hash_into(data, out)
+}
+
+/// merkle 内部节点的 dsha256，两个子节点直接写入 hasher，不拼接到中间缓冲区
+pub fn dsha256_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Dsha256::default().hash_pair(left, right)
+}
+
+/// 可复用的 dsha256，避免大量计算时反复创建 hasher
+#[derive(Debug, Clone, Default)]
+pub struct Dsha256 {
+    hasher: Sha256,
+}
+
+impl Dsha256 {
+    pub fn hash_into(&mut self, data: &[u8], out: &mut [u8; 32]) {
+        self.hasher.update(data);
+        let first = self.hasher.finalize_reset();
+        self.hasher.update(first);
+        out.copy_from_slice(&self.hasher.finalize_reset());
+    }
+
+    pub fn hash(&mut self, data: &[u8]) -> [u8; 32] {
+        let mut out = [0; 32];
+        self.hash_into(data, &mut out);
+        out
+    }
+
+    /// 同 dsha256_pair，复用 hasher
+    pub fn hash_pair(&mut self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        self.hasher.update(left);
+        self.hasher.update(right);
+        let first = self.hasher.finalize_reset();
+        self.hasher.update(first);
+        self.hasher.finalize_reset().into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consts::{COIN, KAWPOW_EPOCH_LENGTH};
+    use crate::diff::pool_diff_to_target_hex;
+    use crate::header::{MERKLE_OFFSET, PREV_OFFSET, TIME_OFFSET};
+    use crate::job::KawpowNonce;
+    use crate::kawpow::kawpow_epoch;
+    use crate::util::decode_hash32;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_dsha_256() {
+        let data = b"hello world";
+        let r = dsha256(data.as_slice());
+        let result_exp = [
+            188_u8, 98, 212, 184, 13, 158, 54, 218, 41, 193, 108, 93, 77, 159, 17, 115, 31, 54, 5,
+            44, 114, 64, 26, 118, 194, 60, 15, 181, 169, 183, 68, 35,
+        ];
+        assert_eq!(r, result_exp)
+    }
+
+    #[test]
+    fn test_dsha256_into() {
+        let data = b"hello world";
+        let result_exp = dsha256(data.as_slice());
+        let mut out = [0; 32];
+        dsha256_into(data.as_slice(), &mut out);
+        assert_eq!(out, result_exp);
+        let mut hasher = Dsha256::default();
+        assert_eq!(hasher.hash(data.as_slice()), result_exp);
+        // 复用后结果不变
+        assert_eq!(hasher.hash(data.as_slice()), result_exp);
+    }
+
+    fn sample_template_info() -> BlockTemplateInfo {
+        let s = include_str!("testdata/block_template.json");
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn sample_template() -> BlockTemplate {
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        BlockTemplate::new(
+            &sample_template_info(),
+            pool_addr,
+            "with a little help from http://github.com/kralverde/ravencoin-stratum-proxy"
+                .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_block_template() {
+        let s = r#"{"capabilities": ["proposal"], "version": 805306368, "rules": ["assets", "messaging_restricted", "transfer_script", "enforce_value", "coinbase"], "vbavailable": {}, "vbrequired": 0, "previousblockhash": "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f14", "transactions": [{"data": "02000000016c38ea64a528c14165bb70c1acf81c301a2d0ed7dcdef4aec58baafb78a29eee090000006a47304402206fbf6b12649facb89440a6870ffa4bfb81ee7ce274c5b4464b050d3b0333ffcf022070e5cc2f7d6b4a87c84c07546d767c3f080cc56a6b290212960eac0dc3333379012102761a92416b225d00f5ae5aec9e192ef1a8fd5c9887f75191b96f4d9e52d9cb95feffffff1db70bb83b000000001976a91406c6517f33ebf4518fecbdef2f011bcc74d5bdce88ac75b3ba3b000000001976a91406d298591456244b1747a2b15a8943c2b671181388acd92ecb3b000000001976a91409791086f0944a0314426252dca10081193ebffa88ac662dca3b000000001976a9140db2ac258e490441ad2f4405969cc33a908f0b7d88ac5dcbc33b000000001976a9141996438233f461a58f63468063c25a2c4f01233188ac1195d73b000000001976a9141c3a341245e08fc5283476b95bf0af7494b9821a88ac2bd3493c000000001976a9141d5d0f766bba2916f6dc174a224b6503b8a010b488ace5b5c33b000000001976a91424ec399e1f8311ade603e79896a37ed11a3cde6388ac7004e53c000000001976a9142839c671e5a0e83df3c930181ddfe9b0d178cd7a88ace1fc023c000000001976a9142d6a61657f16e9de1baed3eef730484ccd0853a788ac40013e3c000000001976a91431062a27fb63bdc7a788da233f2914425ca70b2c88ac8c64e43b000000001976a914587b5af7272f890c01e1477346f5a94ce88294c488ac5534ce3b000000001976a9146f144df83b2b6260fd540e13b00e1a003541ef7488ac2b87d053000000001976a91480e7aac2065f7527778a584e895f33609f96c11888ac3fc8c13b000000001976a914a1c918588f2ef2e3577d9b9aa4a3b1f610957c3888ac179cd23b000000001976a914a4a696074b20c80c4a3bf12230cd3d6c12f0c93c88aced1c334a000000001976a914a4ef18ccf68154b8f287a9aac84f553e505ad09888acaca4063c000000001976a914a6da027d5f3b92759eaaceb97dde0b4c83b7c95688acbe20f94a000000001976a914aa330dbd34ad84a301dd09406ad89351b151c19988ac5eac7258000000001976a914d12e236654887b67cf4a3c9282df4bf9af7be24388acd44bac3b000000001976a914da92550faa339ef299df238654634b4ccb698a8a88ac0cd0a13b000000001976a914e027e1dc3a412eec7ce177bfa61304dcf10168e888ac8a9eaa3b000000001976a914e148329f72abb0e6acca0b135cd296cbd012a74c88ac87ddc93b000000001976a914e2a3e4a71a191f7841d6ec162f05e4a77432994088ac37f693740b0000001976a914496fe43cb89975bd9f23e7b2d0aac39f9fd3b8fd88ac817c053c000000001976a914e8bbb6e50ffe887b36ba71aca2099c2589cfad4a88ac526f3a2a010000001976a914fc74e7b98d38e183ec1daa88043f52b611ce689a88acff96d43b000000001976a914fd4f0bcc822679e48b651cbf7c8dec6470d6fccc88ac6766f03b0000000017a914ad974e8859312c1afbe828aadfaa2e1e8f8d690087ca042600", "txid": "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde", "hash": "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde", "depends": [], "fee": 1158686, "sigops": 112, "weight": 4564}, {"data": "0200000006055872f60b25de3d30f6490d4509e339cf91899cc5b7f0b33a61fbf5f4563562270400006b4830450221008127dbdab649acec14a2f925d7130723171394d66f2048f1f2d41974c5f05e7202201fd302d6a95343449a397640bc24f8164979432112357cace32bd2de5db8c5950121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff17507bb936a3c6e674438139c20106ff9c452e742f0ad2297706a4642cc2b0990e0500006b483045022100d62134a0aa4f57d53ab63fda6fbe36e39ff5992a9fdc034e812e940ba7ebc2ad02207d57cf5deef5a1f44ea683d672a5f6a9a72bcf1db34a4a090dcb81d0c38e67530121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff3a664aecc01ac73fae20304b41bde95213f26c285b3d69a6bd7cd12840d269c6010000006b483045022100ffcd44525273d4c26db881ff514a10b0f77690c9bb6f941d71015f13a857ee7102203c23c9a815e90470e1ad8c6cbb86aab47828a57f89a6a8cda100a3d14df23aee012102cac321783fe7f568a41536ca82ce2347cc5881b787c3bb4aad545da56e52d23cfeffffff73b6de01ef58e8d530850defdf1c1425aa667fd11f0ef85b46f73b368ebe4374000000006b4830450221009355c15204e4be3f92894d4cf186370a6b55bb107f9e029e25951bb2d991e37902201ff8f6f1744602d6a1752887672c4577952ca7a2f3cd9ff546e5e44fa375120c012102cac321783fe7f568a41536ca82ce2347cc5881b787c3bb4aad545da56e52d23cfeffffffaf6be309e92daba9f650cb31c85d7c526ed95a804a9557d7409dfba04bb28456120400006b483045022100e26b98a78b89180d2d4b1e62fe8346eae7e5006538538beaf984816452f805b902207e28c4aa3019510ffd664fb1f47ec31f271e8793cee8200f966eb8521c3cfa3c0121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35fefffffff4e5daf0949f53de19d8a7f94af2e61757e826960b3450dac46d92acadeed321b20400006b483045022100bac393e39f96f3e46c47d9d3a333fe25fcf6294f779974fbcf13cd9b6a105a27022032fc4fbef176d0ea6d2f0fbd64b712bbc33986d8fb62f05e13f762e27e9bcbee0121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff01225e132a4a00000017a9149349af08ae9c4ee5559bb664104f5e0cc0f102a987d2042600", "txid": "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d", "hash": "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d", "depends": [], "fee": 942997, "sigops": 0, "weight": 3720}, {"data": "0100000001cb75abe448ad02acf24a70964d210708cbe42646186a2c0c864adc621884ecbf190000006a47304402201dc56ec27da38ac807d67888767611f9e69894068240d53647b5c5f9c6532d7d02201dbd252c296e43ee595525e05e461f1e9e7e26482e52d3468ab7ca5ba03d67d6012102dade2431fc06b2ba964147283ba44fc5073a216a11ec0688c5580602cea20ba9ffffffff012d32a93b000000001976a91484173dfafb6fc629de0f372e8ea20a0b89cf31b088ac00000000", "txid": "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17", "hash": "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17", "depends": [], "fee": 193325, "sigops": 4, "weight": 764}, {"data": "0100000001cb75abe448ad02acf24a70964d210708cbe42646186a2c0c864adc621884ecbf390000006b483045022100c6e763d10a998b0b51acb3e8d3340d0c0751da711cbb93e16c63a4b8a55d808c022019516d6650553dc51f838873f48689eda94f83fa6c3a4822ec03ceaca79f251b0121025751f9b5946413e83be6b8b61778712dc160ff8af350fe31fc505b757c4627cbffffffff0121d8e13b000000001976a91484173dfafb6fc629de0f372e8ea20a0b89cf31b088ac00000000", "txid": "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5", "hash": "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5", "depends": [], "fee": 193325, "sigops": 4, "weight": 768}], "coinbaseaux": {"flags": ""}, "coinbasevalue": 250002488333, "longpollid": "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f142904428", "target": "0000000000005ab50d0000000000000000000000000000000000000000000000", "mintime": 1665555669, "mutable": ["time", "transactions", "prevblock"], "noncerange": "00000000ffffffff", "sigoplimit": 80000, "sizelimit": 8000000, "weightlimit": 8000000, "curtime": 1665556235, "bits": "1a5ab50d", "height": 2491604, "default_witness_commitment": "6a24aa21a9edb7efcd0c5c29e3890f1e06bee21568fcbeda8ae211a48c1fb336358729edbb47"}"#;
+        let template_info: BlockTemplateInfo = serde_json::from_str(s).unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(
+            &template_info,
+            pool_addr,
+            "with a little help from http://github.com/kralverde/ravencoin-stratum-proxy"
+                .to_string(),
+        )
+        .unwrap();
+
+        println!("{:?}", template)
+    }
+
+    #[test]
+    fn test_template_info_accessors() {
+        let info = sample_template_info();
+        assert_eq!(
+            info.long_poll_id(),
+            "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f142904428"
+        );
+        assert!(info.rules().iter().any(|r| r == "assets"));
+        assert!(info.mutable().iter().any(|m| m == "time"));
+        assert_eq!(info.capabilities(), &["proposal".to_string()]);
+        assert_eq!(info.sigop_limit(), 80000);
+        assert_eq!(info.size_limit(), 8000000);
+        assert_eq!(info.weight_limit(), 8000000);
+        assert_eq!(info.noncerange(), "00000000ffffffff");
+        assert_eq!(info.coinbase_aux().get("flags").unwrap(), "");
+    }
+
+    fn tx(fee: u64, weight: u32, sigops: u32, depends: Vec<u32>) -> Transaction {
+        Transaction {
+            data: String::new(),
+            txid: String::new(),
+            hash: String::new(),
+            fee,
+            sigops,
+            weight,
+            depends,
+        }
+    }
+
+    #[test]
+    fn test_select_transactions() {
+        let txs = vec![
+            tx(100, 1000, 1, vec![]),
+            // 手续费率最高，但依赖第 1 笔交易
+            tx(1000, 1000, 1, vec![1]),
+            tx(500, 1000, 1, vec![]),
+            tx(300, 1000, 1, vec![]),
+            tx(10_000, 1000, 1, vec![9]),
+        ];
+        let selected = select_transactions(&txs, 3000, 100);
+        assert_eq!(selected, vec![2, 3, 0]);
+
+        let selected = select_transactions(&txs, 4500, 100);
+        assert_eq!(selected, vec![2, 3, 0, 1]);
+        let weight: u64 = selected.iter().map(|i| txs[*i].weight as u64).sum();
+        assert!(weight <= 4500);
+        for (pos, i) in selected.iter().enumerate() {
+            for d in &txs[*i].depends {
+                assert!(selected[..pos].contains(&(*d as usize - 1)));
+            }
+        }
+
+        let selected = select_transactions(&txs, 10_000, 2);
+        assert_eq!(selected, vec![2, 3]);
+
+        // weight 为 0 的交易及依赖它的交易被跳过
+        let txs = vec![
+            tx(5000, 0, 1, vec![]),
+            tx(100, 1000, 1, vec![]),
+            tx(100, 1000, 1, vec![1]),
+        ];
+        assert_eq!(txs[0].fee_rate(), 0.0);
+        assert_eq!(txs[1].fee_rate(), 0.1);
+        assert_eq!(select_transactions(&txs, 10_000, 100), vec![1]);
+        assert_eq!(select_transactions(&txs, 999, 100), Vec::<usize>::new());
+
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            select_transactions: true,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        assert_eq!(template.external_txs.len(), info.transactions.len());
+        template.self_check().unwrap();
+    }
+
+    #[test]
+    fn test_coinbase_aux_flags() {
+        let mut info = sample_template_info();
+        assert!(info.coinbase_aux_flags().unwrap().is_empty());
+        info.coinbase_aux
+            .insert("flags".to_string(), "062f503253482f".to_string());
+        assert_eq!(
+            info.coinbase_aux_flags().unwrap(),
+            vec![0x06, 0x2f, 0x50, 0x32, 0x53, 0x48, 0x2f]
+        );
+
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            include_coinbase_aux: true,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr.clone(), "pool".to_string(), &options)
+                .unwrap();
+        // 脚本长度之后依次为 4 字节高度 push、aux flags、OP_0 和 pool_info
+        let script = &template.coinbase_tx[COINBASE_SCRIPT_OFFSET..];
+        assert_eq!(script[4..11], [0x06, 0x2f, 0x50, 0x32, 0x53, 0x48, 0x2f]);
+        assert_eq!(script[11..17], [0, 4, b'p', b'o', b'o', b'l']);
+        template.self_check().unwrap();
+
+        // 默认不写入
+        let template = BlockTemplate::new(&info, pool_addr, "pool".to_string()).unwrap();
+        let script = &template.coinbase_tx[COINBASE_SCRIPT_OFFSET..];
+        assert_eq!(script[4..10], [0, 4, b'p', b'o', b'o', b'l']);
+
+        info.coinbase_aux
+            .insert("flags".to_string(), "zz".to_string());
+        assert!(info.coinbase_aux_flags().is_err());
+    }
+
+    #[test]
+    fn test_order_by_depends() {
+        let txs = vec![
+            tx(0, 0, 0, vec![]),
+            tx(0, 0, 0, vec![]),
+            tx(0, 0, 0, vec![1]),
+        ];
+        assert_eq!(order_by_depends(&txs).unwrap(), vec![0, 1, 2]);
+
+        // 第 1 笔交易依赖第 3 笔
+        let txs = vec![
+            tx(0, 0, 0, vec![3]),
+            tx(0, 0, 0, vec![]),
+            tx(0, 0, 0, vec![]),
+        ];
+        assert_eq!(order_by_depends(&txs).unwrap(), vec![1, 2, 0]);
+
+        let txs = vec![tx(0, 0, 0, vec![2]), tx(0, 0, 0, vec![1])];
+        assert!(order_by_depends(&txs).is_err());
+        let txs = vec![tx(0, 0, 0, vec![0])];
+        assert!(order_by_depends(&txs).is_err());
+        let txs = vec![tx(0, 0, 0, vec![2])];
+        assert!(order_by_depends(&txs).is_err());
+    }
+
+    #[test]
+    fn test_total_fees() {
+        let mut info = sample_template_info();
+        assert_eq!(info.total_fees(), 1158686 + 942997 + 193325 + 193325);
+        assert_eq!(info.subsidy().unwrap(), 250000000000);
+        info.coinbasevalue = 1;
+        assert!(info.subsidy().is_err());
+    }
+
+    #[test]
+    fn test_job_serde() {
+        let template = sample_template();
+        let job = template.create_job(template.target_hex.clone(), true);
+        let s = serde_json::to_string(&job).unwrap();
+        assert!(s.contains(&format!("\"header\":\"{}\"", hex::encode(&job.header))));
+        let job2: JobInfo = serde_json::from_str(&s).unwrap();
+        assert_eq!(job, job2);
+    }
+
+    #[test]
+    fn test_subscription_coinbase() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let sub = Subscription::new(vec![0x11, 0x22, 0x33, 0x44], 4);
+        let options = BlockTemplateOptions {
+            subscription: Some(sub.clone()),
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, "pool".to_string(), &options)
+                .unwrap();
+        let offset = template.extranonce2_offset.unwrap();
+        assert_eq!(&template.coinbase_tx[offset - 4..offset], &sub.extranonce1);
+        assert_eq!(&template.coinbase_tx[offset..offset + 4], &[0; 4]);
+
+        let extranonce2 = [0xde, 0xad, 0xbe, 0xef];
+        let coinbase_tx = template.coinbase_tx_with_extranonce2(&extranonce2).unwrap();
+        let nonce = sub.full_nonce(&extranonce2).unwrap();
+        assert_eq!(&coinbase_tx[offset - 4..offset + 4], nonce.as_slice());
+
+        assert!(sample_template()
+            .coinbase_tx_with_extranonce2(&extranonce2)
+            .is_err());
+
+        let (coinbase_a, txid_a) = template.coinbase_with_extranonce2(&extranonce2).unwrap();
+        assert_eq!(coinbase_a, coinbase_tx);
+        assert_eq!(txid_a, txid_from_raw(&coinbase_tx).unwrap());
+        let (coinbase_b, txid_b) = template
+            .coinbase_with_extranonce2(&[0xca, 0xfe, 0xba, 0xbe])
+            .unwrap();
+        assert_ne!(coinbase_a, coinbase_b);
+        assert_ne!(txid_a, txid_b);
+        assert_ne!(txid_a, template.coinbase_txid);
+        // 全 0 的 extranonce2 与模板中的 coinbase 相同
+        let (_, txid) = template.coinbase_with_extranonce2(&[0; 4]).unwrap();
+        assert_eq!(txid, template.coinbase_txid);
+        assert!(sample_template()
+            .coinbase_with_extranonce2(&extranonce2)
+            .is_err());
+    }
+
+    #[test]
+    fn test_coinbase_prefix_suffix() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            subscription: Some(Subscription::new(vec![0x11, 0x22, 0x33, 0x44], 4)),
+            ..Default::default()
+        };
+        let mut template =
+            BlockTemplate::new_with_options(&info, pool_addr, "pool".to_string(), &options)
+                .unwrap();
+        let offset = template.extranonce2_offset.unwrap();
+        assert_eq!(template.coinbase_prefix.len(), offset);
+        assert_eq!(
+            template.coinbase_prefix.len() + 4 + template.coinbase_suffix.len(),
+            template.coinbase_tx.len()
+        );
+        let rebuild = |template: &BlockTemplate, extranonce2: &[u8]| {
+            let mut coinbase_tx = template.coinbase_tx.clone();
+            coinbase_tx[offset..offset + extranonce2.len()].copy_from_slice(extranonce2);
+            coinbase_tx
+        };
+        let extranonce2s = [[0; 4], [0xde, 0xad, 0xbe, 0xef], [0xff; 4], [1, 2, 3, 4]];
+        for extranonce2 in &extranonce2s {
+            assert_eq!(
+                template.coinbase_tx_with_extranonce2(extranonce2).unwrap(),
+                rebuild(&template, extranonce2)
+            );
+        }
+        assert!(template.coinbase_tx_with_extranonce2(&[0; 3]).is_err());
+        assert!(template.coinbase_tx_with_extranonce2(&[0; 5]).is_err());
+
+        // 替换交易后矿池输出变化，suffix 随之更新
+        let txs: Vec<_> = info.transactions.iter().skip(1).collect();
+        template.set_transactions(&txs).unwrap();
+        for extranonce2 in &extranonce2s {
+            assert_eq!(
+                template.coinbase_tx_with_extranonce2(extranonce2).unwrap(),
+                rebuild(&template, extranonce2)
+            );
+        }
+
+        let template = sample_template();
+        assert!(template.coinbase_prefix.is_empty());
+        assert!(template.coinbase_suffix.is_empty());
+    }
+
+    #[test]
+    fn test_hash_orientation() {
+        let info = sample_template_info();
+        let template = sample_template();
+
+        let mut header_hash = dsha256(&template.header);
+        assert_eq!(header_hash_display(&template.header), template.header_hash);
+        header_hash.reverse();
+        assert_eq!(header_hash, template.header_hash);
+
+        // prev_hash 为内部序，与区块头中的 4..36 字节一致
+        let mut prev_hash = hex::decode(&info.previousblockhash).unwrap();
+        assert_eq!(
+            &template.header[PREV_OFFSET..MERKLE_OFFSET],
+            template.prev_hash.as_slice()
+        );
+        prev_hash.reverse();
+        assert_eq!(prev_hash, template.prev_hash);
+        assert_eq!(template.prev_hash[31], 0);
+        // 显示序的 prev_hash_hex 与 previousblockhash 一致，无需再反转
+        assert_eq!(template.prev_hash_hex, info.previousblockhash);
+        assert_eq!(
+            template.prev_hash_hex,
+            hex::encode(reverse_bytes(&template.prev_hash))
+        );
+        assert_eq!(template.prev_hash_display(), info.previousblockhash);
+
+        // coinbase_txid 为内部序，与 merkle 计算使用的顺序一致
+        let mut txids = vec![template.coinbase_txid];
+        for tx in &info.transactions {
+            txids.push(reverse_hash(decode_hash32(&tx.txid).unwrap()));
+        }
+        assert_eq!(
+            &template.header[MERKLE_OFFSET..TIME_OFFSET],
+            &merkel_hash(txids)
+        );
+    }
+
+    #[test]
+    fn test_longpollid_changed() {
+        let mut info = sample_template_info();
+        let template = sample_template();
+        assert!(!template.longpollid_changed(&info));
+        assert!(!template.is_new_template(&info));
+
+        info.long_poll_id =
+            "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f142904429".to_string();
+        assert_eq!(info.height, template.height);
+        assert!(template.longpollid_changed(&info));
+        assert!(template.is_new_template(&info));
+    }
+
+    #[test]
+    fn test_is_new_template_built_at() {
+        let info = sample_template_info();
+        // 区块头时间固定在很久以前，不影响是否刷新
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::builder(pool_addr)
+            .ntime_policy(NtimePolicy::Fixed(1_000_000))
+            .build(&info)
+            .unwrap();
+        assert_eq!(template.timestamp, 1_000_000);
+        assert!(!template.is_new_template(&info));
+
+        let stale = BlockTemplate {
+            built_at: super::now() - 61,
+            ..template.clone()
+        };
+        assert!(stale.is_new_template(&info));
+    }
+
+    #[test]
+    fn test_template_info_try_from() {
+        let s = include_str!("testdata/block_template.json");
+        let info = BlockTemplateInfo::try_from(s).unwrap();
+        assert_eq!(info.bits, "1a5ab50d");
+
+        let mut value: serde_json::Value = serde_json::from_str(s).unwrap();
+        value.as_object_mut().unwrap().remove("bits");
+        let err = BlockTemplateInfo::try_from(value.to_string().as_str()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RvnError>(),
+            Some(RvnError::TemplateDecode(_))
+        ));
+        assert!(err.to_string().contains("`bits`"), "{}", err);
+
+        let mut value: serde_json::Value = serde_json::from_str(s).unwrap();
+        value["target"] = "5ab50d".into();
+        let err = BlockTemplateInfo::try_from(value.to_string().as_str()).unwrap_err();
+        assert!(err.to_string().contains("target"), "{}", err);
+
+        let mut value: serde_json::Value = serde_json::from_str(s).unwrap();
+        value["previousblockhash"] = "".into();
+        assert!(BlockTemplateInfo::try_from(value.to_string().as_str()).is_err());
+    }
+
+    #[test]
+    fn test_template_diff() {
+        let info = sample_template_info();
+        assert!(info.diff(&info.clone()).is_unchanged());
+
+        // 交易顺序变化不算集合变化
+        let mut other = info.clone();
+        other.transactions.reverse();
+        assert!(!info.diff(&other).tx_set_changed);
+
+        let mut other = info.clone();
+        other.transactions.pop();
+        let diff = info.diff(&other);
+        assert_eq!(
+            diff,
+            TemplateDiff {
+                tx_set_changed: true,
+                ..Default::default()
+            }
+        );
+        assert!(!diff.is_new_block());
+
+        let mut other = info.clone();
+        other.height += 1;
+        other.previousblockhash = hex::encode([1; 32]);
+        let diff = info.diff(&other);
+        assert!(diff.height_changed && diff.prevblock_changed && diff.is_new_block());
+        assert!(!diff.tx_set_changed && !diff.witness_changed);
+    }
+
+    #[test]
+    fn test_prev_hash_changed() {
+        let mut info = sample_template_info();
+        let template = sample_template();
+        assert!(!template.prev_hash_changed(&info));
+        let upper = info.previousblockhash.to_uppercase();
+        let original = std::mem::replace(&mut info.previousblockhash, upper);
+        assert!(!template.prev_hash_changed(&info));
+        info.previousblockhash = original;
+
+        // 没有 prev_hash_hex 时由 prev_hash 计算
+        let legacy = BlockTemplate {
+            prev_hash_hex: String::new(),
+            ..template.clone()
+        };
+        assert!(!legacy.prev_hash_changed(&info));
+
+        info.previousblockhash =
+            "00000000000006ca7f1d5e43d1f7bba4bf1a3f3e3a4a4a6b12b1c2d3e4f5a6b7".to_string();
+        assert_eq!(info.height, template.height);
+        assert!(template.prev_hash_changed(&info));
+        assert!(legacy.prev_hash_changed(&info));
+        assert!(template.is_new_template(&info));
+    }
+
+    #[test]
+    fn test_validate_target_bits() {
+        let mut info = sample_template_info();
+        assert!(info.validate_target_bits().is_ok());
+
+        info.bits = "1a5ab50e".to_string();
+        assert!(info.validate_target_bits().is_err());
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        assert!(BlockTemplate::new(&info, pool_addr, String::new()).is_err());
+    }
+
+    #[test]
+    fn test_witness_commitment_script() {
+        let info = sample_template_info();
+        let template = sample_template();
+        let commitment = parse_witness_commitment(&info.default_witness_commitment).unwrap();
+        let script = witness_commitment_script(&commitment);
+        assert_eq!(script.len(), 38);
+        assert_eq!(hex::encode(&script), info.default_witness_commitment);
+        assert!(hex::encode(&script).starts_with("6a24aa21a9ed"));
+
+        let coinbase = template.coinbase().unwrap();
+        let index = template.witness_output_index().unwrap();
+        assert_eq!(coinbase.output[index].script_pubkey.as_bytes(), script);
+        assert_eq!(coinbase.output[index].value, 0);
+
+        assert!(parse_witness_commitment("6a24aa21a9ed").is_err());
+        assert!(parse_witness_commitment(&format!("6a24aa21a9ee{}", "00".repeat(32))).is_err());
+        assert!(
+            parse_witness_commitment(&format!("{}00", info.default_witness_commitment)).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_consistency() {
+        let template = sample_template();
+        template.validate_consistency().unwrap();
+
+        // target 比 bits 精度高，压缩后相同即可
+        let mut precise = template.clone();
+        let mut target = decode_hash32(&precise.target_hex).unwrap();
+        target[31] = 1;
+        precise.target_hex = hex::encode(target);
+        precise.validate_consistency().unwrap();
+
+        let mut inconsistent = template.clone();
+        inconsistent.target_hex =
+            "0000000000005ab60d0000000000000000000000000000000000000000000000".to_string();
+        assert!(inconsistent.validate_consistency().is_err());
+
+        // bits_hex 与 target_hex 一致，但与区块头中的 bits 不同
+        let mut inconsistent = template;
+        inconsistent.bits_hex = "1a5ab60d".to_string();
+        inconsistent.target_hex =
+            "0000000000005ab60d0000000000000000000000000000000000000000000000".to_string();
+        assert!(inconsistent.validate_consistency().is_err());
+    }
+
+    #[test]
+    fn test_proposal_hex() {
+        use bitcoin::consensus::encode::{deserialize_partial, VarInt};
+
+        assert!(sample_template_info().supports_proposal());
+        let template = sample_template();
+        let block = hex::decode(template.to_proposal_hex()).unwrap();
+        assert_eq!(&block[..80], template.header.as_slice());
+        assert_eq!(&block[80..120], &[0; 40]);
+        let (count, mut offset) = deserialize_partial::<VarInt>(&block[120..]).unwrap();
+        assert_eq!(count.0, 5);
+        offset += 120;
+        for _ in 0..count.0 {
+            let (_, size) = deserialize_partial::<bitcoin::Transaction>(&block[offset..]).unwrap();
+            offset += size;
+        }
+        assert_eq!(offset, block.len());
+    }
+
+    #[test]
+    fn test_block_weight() {
+        let mut info = sample_template_info();
+        let template = sample_template();
+        assert_eq!(
+            template.size() as usize,
+            template.to_proposal_hex().len() / 2
+        );
+        let tx_weight: u64 = info.transactions.iter().map(|tx| tx.weight as u64).sum();
+        assert_eq!(template.external_weight, tx_weight);
+        let coinbase: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(
+            template.weight(),
+            template.header_len() * 4 + coinbase.weight() as u64 + tx_weight
+        );
+        assert!(template.weight() < 8000000);
+        assert!(template.fits_limits(&info).is_ok());
+
+        let coinbase_weight = template.coinbase_weight();
+        assert!(coinbase_weight > 0);
+        assert_eq!(coinbase_weight, coinbase.weight() as u64);
+        assert_eq!(
+            coinbase_weight,
+            super::tx_weight(&template.coinbase_tx).unwrap() as u64
+        );
+        let total = template.coinbase_tx.len() as u64;
+        assert!(coinbase_weight > total * 3 && coinbase_weight <= total * 4);
+        assert_eq!(
+            coinbase_weight,
+            (total - COINBASE_WITNESS_LEN as u64) * 3 + total
+        );
+
+        info.weight_limit = template.weight() - 1;
+        assert!(template.fits_limits(&info).is_err());
+    }
+
+    #[test]
+    fn test_total_sigops() {
+        let mut info = sample_template_info();
+        let template = sample_template();
+        let tx_sigops: u32 = info.transactions.iter().map(|tx| tx.sigops).sum();
+        assert_eq!(template.external_sigops, tx_sigops);
+        // P2PKH 输出中的 OP_CHECKSIG
+        assert_eq!(template.coinbase_sigops().unwrap(), 4);
+        assert_eq!(template.total_sigops().unwrap(), tx_sigops + 4);
+        assert_eq!(info.sigop_limit(), 80000);
+        assert!(template.total_sigops().unwrap() < info.sigop_limit());
+        assert!(template.fits_sigop_limit(&info).is_ok());
+
+        info.sigop_limit = tx_sigops;
+        assert!(template.fits_sigop_limit(&info).is_err());
+        assert!(template.fits_limits(&info).is_err());
+    }
+
+    #[test]
+    fn test_assemble_block() {
+        let template = sample_template();
+        let job = template.create_job(template.target_hex.clone(), true);
+        let nonce = "0123456789abcdef";
+        let mix_hash = hex::encode([7; 32]);
+        let block = template
+            .assemble_block(template.timestamp, nonce, &mix_hash)
+            .unwrap();
+        assert_eq!(block, job.build_block(nonce, &mix_hash));
+        assert_eq!(block.len(), template.to_proposal_hex().len());
+
+        let block = template
+            .assemble_block(template.timestamp + 1, nonce, &mix_hash)
+            .unwrap();
+        let header = Header::from_bytes(&hex::decode(&block[..160]).unwrap()).unwrap();
+        assert_eq!(header.time, template.timestamp + 1);
+    }
+
+    #[test]
+    fn test_parse_block() {
+        let template = sample_template();
+        let job = template.create_job(template.target_hex.clone(), true);
+        let block = job.build_block("0123456789abcdef", &hex::encode([7; 32]));
+        let (header, txs) = parse_block(&block).unwrap();
+        assert_eq!(header, Header::from_bytes(&template.header).unwrap());
+        assert_eq!(txs.len(), template.external_txs.len() + 1);
+        assert_eq!(txs[0], hex::encode(&template.coinbase_tx));
+        assert_eq!(txs[1..], template.external_txs[..]);
+
+        // 只有 coinbase
+        let block = serialize_block(
+            &template.header,
+            &"00".repeat(8),
+            &"00".repeat(32),
+            &template.coinbase_tx,
+            &[],
+        );
+        assert_eq!(parse_block(&block).unwrap().1.len(), 1);
+
+        assert!(parse_block(&block[..block.len() - 2]).is_err());
+        assert!(parse_block(&format!("{}00", block)).is_err());
+    }
+
+    #[test]
+    fn test_parse_full_block() {
+        let template = sample_template();
+        let nonce = KawpowNonce(0x0123_4567_89ab_cdef);
+        let nonce_hex = hex::encode(nonce.to_header_bytes());
+        let block = template.assemble_block(template.timestamp, &nonce_hex, &hex::encode([7; 32]));
+        let parsed = parse_full_block(&block.unwrap()).unwrap();
+        assert_eq!(parsed.header, Header::from_bytes(&template.header).unwrap());
+        assert_eq!(KawpowNonce::from_header_bytes(parsed.nonce), nonce);
+        assert_eq!(parsed.mix_hash, [7; 32]);
+        assert_eq!(parsed.coinbase_tx, template.coinbase_tx);
+        assert_eq!(parsed.txs, template.external_txs);
+        assert!(parsed.diff_against(&template).unwrap().is_empty());
+
+        let rolled = template
+            .assemble_block(template.timestamp + 1, &nonce_hex, &hex::encode([7; 32]))
+            .unwrap();
+        let parsed = parse_full_block(&rolled).unwrap();
+        assert_eq!(
+            parsed.diff_against(&template).unwrap(),
+            vec![BlockDiff::Time]
+        );
+
+        let mut other = template.clone();
+        other.set_transactions(&[]).unwrap();
+        assert_eq!(
+            parsed.diff_against(&other).unwrap(),
+            vec![
+                BlockDiff::MerkleRoot,
+                BlockDiff::Time,
+                BlockDiff::Coinbase,
+                BlockDiff::TxSet
+            ]
+        );
+
+        let empty = format!("{}00", &rolled[..BLOCK_HEADER_LEN * 2]);
+        assert!(parse_full_block(&empty).is_err());
+    }
+
+    #[test]
+    fn test_no_transactions() {
+        let mut info = sample_template_info();
+        info.coinbasevalue = info.subsidy().unwrap();
+        info.transactions.clear();
+        info.default_witness_commitment = witness_commitment(&[], &[0; 32]).unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(&info, pool_addr, "pool".to_string()).unwrap();
+        assert!(template.external_txs.is_empty());
+        assert!(template.merkle_branches.is_empty());
+        // 只有 coinbase 时 merkle 根就是 coinbase 的 txid，而不是空输入的 dsha256
+        let header = Header::from_bytes(&template.header).unwrap();
+        assert_eq!(header.merkle_root, template.coinbase_txid);
+        assert_ne!(header.merkle_root, dsha256(b""));
+        template.self_check().unwrap();
+
+        let job = template.create_job(template.target_hex.clone(), true);
+        let block = job
+            .try_build_block("0123456789abcdef", &hex::encode([7; 32]))
+            .unwrap();
+        let (_, txs) = parse_block(&block).unwrap();
+        assert_eq!(txs, vec![hex::encode(&template.coinbase_tx)]);
+    }
+
+    #[test]
+    fn test_job_parse_header() {
+        let info = sample_template_info();
+        let template = sample_template();
+        let job = template.create_job(template.target_hex.clone(), true);
+        let header = job.parse_header().unwrap();
+        assert_eq!(header.height, info.height);
+        assert_eq!(header.bits, parse_bits(&info.bits).unwrap());
+        assert_eq!(format!("{:08x}", header.bits), job.block_bits_hex);
+        assert_eq!(header.version, template.version);
+        assert_eq!(header.prev_hash.as_slice(), template.prev_hash.as_slice());
+        assert_eq!(header.hash(), job.header_hash);
+
+        let mut job = job;
+        job.header.truncate(40);
+        assert!(job.parse_header().is_err());
+    }
+
+    #[test]
+    fn test_try_build_block() {
+        let template = sample_template();
+        let mut job = template.create_job(template.target_hex.clone(), true);
+        let nonce = "0123456789abcdef";
+        let mix_hash = hex::encode([7; 32]);
+        assert!(check_block_txs(&job.coinbase_tx, &job.external_txs).is_ok());
+        assert_eq!(
+            job.try_build_block(nonce, &mix_hash).unwrap(),
+            job.build_block(nonce, &mix_hash)
+        );
+
+        // 两笔交易被拼成一项，列表少了一项，交易数与实际交易不符
+        let last = job.external_txs.pop().unwrap();
+        job.external_txs.last_mut().unwrap().push_str(&last);
+        assert!(job.try_build_block(nonce, &mix_hash).is_err());
+        assert!(parse_block(&job.build_block(nonce, &mix_hash)).is_err());
+
+        let mut job = template.create_job(template.target_hex.clone(), true);
+        job.external_txs[0].push('z');
+        assert!(job.try_build_block(nonce, &mix_hash).is_err());
+        let mut job = template.create_job(template.target_hex.clone(), true);
+        job.external_txs[0].truncate(20);
+        assert!(job.try_build_block(nonce, &mix_hash).is_err());
+    }
+
+    #[test]
+    fn test_fixed_time() {
+        let mut info = sample_template_info();
+        assert!(info.may_mutate("time"));
+        assert!(!info.may_mutate("version"));
+        let template = sample_template();
+        assert!(!template.fixed_time);
+
+        info.mutable.retain(|m| m != "time");
+        assert!(!info.may_mutate("time"));
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(&info, pool_addr, String::new()).unwrap();
+        assert!(template.fixed_time);
+        let nonce = "0123456789abcdef";
+        let mix_hash = hex::encode([7; 32]);
+        assert!(template
+            .assemble_block(template.timestamp, nonce, &mix_hash)
+            .is_ok());
+        assert!(template
+            .assemble_block(template.timestamp + 1, nonce, &mix_hash)
+            .is_err());
+        let job = template.create_job(template.target_hex.clone(), true);
+        assert!(job.header_with_time(template.timestamp).is_ok());
+        assert!(job.header_with_time(template.timestamp + 1).is_err());
     }
 
     #[test]
-    fn test_block_template() {
-        let s = r#"{"capabilities": ["proposal"], "version": 805306368, "rules": ["assets", "messaging_restricted", "transfer_script", "enforce_value", "coinbase"], "vbavailable": {}, "vbrequired": 0, "previousblockhash": "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f14", "transactions": [{"data": "02000000016c38ea64a528c14165bb70c1acf81c301a2d0ed7dcdef4aec58baafb78a29eee090000006a47304402206fbf6b12649facb89440a6870ffa4bfb81ee7ce274c5b4464b050d3b0333ffcf022070e5cc2f7d6b4a87c84c07546d767c3f080cc56a6b290212960eac0dc3333379012102761a92416b225d00f5ae5aec9e192ef1a8fd5c9887f75191b96f4d9e52d9cb95feffffff1db70bb83b000000001976a91406c6517f33ebf4518fecbdef2f011bcc74d5bdce88ac75b3ba3b000000001976a91406d298591456244b1747a2b15a8943c2b671181388acd92ecb3b000000001976a91409791086f0944a0314426252dca10081193ebffa88ac662dca3b000000001976a9140db2ac258e490441ad2f4405969cc33a908f0b7d88ac5dcbc33b000000001976a9141996438233f461a58f63468063c25a2c4f01233188ac1195d73b000000001976a9141c3a341245e08fc5283476b95bf0af7494b9821a88ac2bd3493c000000001976a9141d5d0f766bba2916f6dc174a224b6503b8a010b488ace5b5c33b000000001976a91424ec399e1f8311ade603e79896a37ed11a3cde6388ac7004e53c000000001976a9142839c671e5a0e83df3c930181ddfe9b0d178cd7a88ace1fc023c000000001976a9142d6a61657f16e9de1baed3eef730484ccd0853a788ac40013e3c000000001976a91431062a27fb63bdc7a788da233f2914425ca70b2c88ac8c64e43b000000001976a914587b5af7272f890c01e1477346f5a94ce88294c488ac5534ce3b000000001976a9146f144df83b2b6260fd540e13b00e1a003541ef7488ac2b87d053000000001976a91480e7aac2065f7527778a584e895f33609f96c11888ac3fc8c13b000000001976a914a1c918588f2ef2e3577d9b9aa4a3b1f610957c3888ac179cd23b000000001976a914a4a696074b20c80c4a3bf12230cd3d6c12f0c93c88aced1c334a000000001976a914a4ef18ccf68154b8f287a9aac84f553e505ad09888acaca4063c000000001976a914a6da027d5f3b92759eaaceb97dde0b4c83b7c95688acbe20f94a000000001976a914aa330dbd34ad84a301dd09406ad89351b151c19988ac5eac7258000000001976a914d12e236654887b67cf4a3c9282df4bf9af7be24388acd44bac3b000000001976a914da92550faa339ef299df238654634b4ccb698a8a88ac0cd0a13b000000001976a914e027e1dc3a412eec7ce177bfa61304dcf10168e888ac8a9eaa3b000000001976a914e148329f72abb0e6acca0b135cd296cbd012a74c88ac87ddc93b000000001976a914e2a3e4a71a191f7841d6ec162f05e4a77432994088ac37f693740b0000001976a914496fe43cb89975bd9f23e7b2d0aac39f9fd3b8fd88ac817c053c000000001976a914e8bbb6e50ffe887b36ba71aca2099c2589cfad4a88ac526f3a2a010000001976a914fc74e7b98d38e183ec1daa88043f52b611ce689a88acff96d43b000000001976a914fd4f0bcc822679e48b651cbf7c8dec6470d6fccc88ac6766f03b0000000017a914ad974e8859312c1afbe828aadfaa2e1e8f8d690087ca042600", "txid": "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde", "hash": "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde", "depends": [], "fee": 1158686, "sigops": 112, "weight": 4564}, {"data": "0200000006055872f60b25de3d30f6490d4509e339cf91899cc5b7f0b33a61fbf5f4563562270400006b4830450221008127dbdab649acec14a2f925d7130723171394d66f2048f1f2d41974c5f05e7202201fd302d6a95343449a397640bc24f8164979432112357cace32bd2de5db8c5950121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff17507bb936a3c6e674438139c20106ff9c452e742f0ad2297706a4642cc2b0990e0500006b483045022100d62134a0aa4f57d53ab63fda6fbe36e39ff5992a9fdc034e812e940ba7ebc2ad02207d57cf5deef5a1f44ea683d672a5f6a9a72bcf1db34a4a090dcb81d0c38e67530121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff3a664aecc01ac73fae20304b41bde95213f26c285b3d69a6bd7cd12840d269c6010000006b483045022100ffcd44525273d4c26db881ff514a10b0f77690c9bb6f941d71015f13a857ee7102203c23c9a815e90470e1ad8c6cbb86aab47828a57f89a6a8cda100a3d14df23aee012102cac321783fe7f568a41536ca82ce2347cc5881b787c3bb4aad545da56e52d23cfeffffff73b6de01ef58e8d530850defdf1c1425aa667fd11f0ef85b46f73b368ebe4374000000006b4830450221009355c15204e4be3f92894d4cf186370a6b55bb107f9e029e25951bb2d991e37902201ff8f6f1744602d6a1752887672c4577952ca7a2f3cd9ff546e5e44fa375120c012102cac321783fe7f568a41536ca82ce2347cc5881b787c3bb4aad545da56e52d23cfeffffffaf6be309e92daba9f650cb31c85d7c526ed95a804a9557d7409dfba04bb28456120400006b483045022100e26b98a78b89180d2d4b1e62fe8346eae7e5006538538beaf984816452f805b902207e28c4aa3019510ffd664fb1f47ec31f271e8793cee8200f966eb8521c3cfa3c0121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35fefffffff4e5daf0949f53de19d8a7f94af2e61757e826960b3450dac46d92acadeed321b20400006b483045022100bac393e39f96f3e46c47d9d3a333fe25fcf6294f779974fbcf13cd9b6a105a27022032fc4fbef176d0ea6d2f0fbd64b712bbc33986d8fb62f05e13f762e27e9bcbee0121038be5cc928bf73d3895d28f61ae4fe70863e4ba16f7f1d255edde5908d2c82d35feffffff01225e132a4a00000017a9149349af08ae9c4ee5559bb664104f5e0cc0f102a987d2042600", "txid": "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d", "hash": "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d", "depends": [], "fee": 942997, "sigops": 0, "weight": 3720}, {"data": "0100000001cb75abe448ad02acf24a70964d210708cbe42646186a2c0c864adc621884ecbf190000006a47304402201dc56ec27da38ac807d67888767611f9e69894068240d53647b5c5f9c6532d7d02201dbd252c296e43ee595525e05e461f1e9e7e26482e52d3468ab7ca5ba03d67d6012102dade2431fc06b2ba964147283ba44fc5073a216a11ec0688c5580602cea20ba9ffffffff012d32a93b000000001976a91484173dfafb6fc629de0f372e8ea20a0b89cf31b088ac00000000", "txid": "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17", "hash": "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17", "depends": [], "fee": 193325, "sigops": 4, "weight": 764}, {"data": "0100000001cb75abe448ad02acf24a70964d210708cbe42646186a2c0c864adc621884ecbf390000006b483045022100c6e763d10a998b0b51acb3e8d3340d0c0751da711cbb93e16c63a4b8a55d808c022019516d6650553dc51f838873f48689eda94f83fa6c3a4822ec03ceaca79f251b0121025751f9b5946413e83be6b8b61778712dc160ff8af350fe31fc505b757c4627cbffffffff0121d8e13b000000001976a91484173dfafb6fc629de0f372e8ea20a0b89cf31b088ac00000000", "txid": "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5", "hash": "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5", "depends": [], "fee": 193325, "sigops": 4, "weight": 768}], "coinbaseaux": {"flags": ""}, "coinbasevalue": 250002488333, "longpollid": "0000000000003d02fdcce5f8e62741b431eb8677d878b96b41033ce436551f142904428", "target": "0000000000005ab50d0000000000000000000000000000000000000000000000", "mintime": 1665555669, "mutable": ["time", "transactions", "prevblock"], "noncerange": "00000000ffffffff", "sigoplimit": 80000, "sizelimit": 8000000, "weightlimit": 8000000, "curtime": 1665556235, "bits": "1a5ab50d", "height": 2491604, "default_witness_commitment": "6a24aa21a9edb7efcd0c5c29e3890f1e06bee21568fcbeda8ae211a48c1fb336358729edbb47"}"#;
-        let template_info: BlockTemplateInfo = serde_json::from_str(s).unwrap();
+    fn test_recompute_coinbase_value() {
+        let mut info = sample_template_info();
+        let subsidy = info.subsidy().unwrap();
+        let all: Vec<&Transaction> = info.transactions.iter().collect();
+        assert_eq!(recompute_coinbase_value(subsidy, &all), info.coinbasevalue);
+        assert_eq!(
+            recompute_coinbase_value(subsidy, &all[1..]),
+            info.coinbasevalue - info.transactions[0].fee
+        );
+
+        // 最后一笔交易的手续费率最低，weight 限制少 1 时被丢弃
+        let tx_weight: u64 = info.transactions.iter().map(|tx| tx.weight as u64).sum();
+        info.weight_limit = COINBASE_RESERVED_WEIGHT + tx_weight - 1;
         let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
-        let template = BlockTemplate::new(
-            &template_info,
+        let options = BlockTemplateOptions {
+            select_transactions: true,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        assert_eq!(template.external_txs.len(), 3);
+        template.self_check().unwrap();
+        let coinbase: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(
+            coinbase.output[0].value,
+            info.coinbasevalue - info.transactions[3].fee
+        );
+    }
+
+    #[test]
+    fn test_iter_txs() {
+        let info = sample_template_info();
+        let views: Vec<TxView> = info.iter_txs().collect::<Result<_>>().unwrap();
+        let weight: u32 = views.iter().map(|v| v.weight).sum();
+        assert_eq!(weight, 4564 + 3720 + 764 + 768);
+        assert_eq!(
+            views[0].txid,
+            decode_hash32_reversed(
+                "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde"
+            )
+            .unwrap()
+        );
+        assert_eq!(views[0].txid[0], 0xde);
+        assert_eq!(
+            views[0].data,
+            hex::decode(&info.transactions[0].data).unwrap()
+        );
+
+        let mut info = info;
+        info.transactions[1].txid = "zz".to_string();
+        assert!(info.iter_txs().nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_transaction_depends() {
+        let s = r#"{"data": "00", "txid": "00", "hash": "00", "depends": [1, 3], "fee": 1, "sigops": 0, "weight": 4}"#;
+        let tx: Transaction = serde_json::from_str(s).unwrap();
+        assert_eq!(tx.depends, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_expected_subsidy() {
+        assert_eq!(expected_subsidy(1), 5000 * COIN);
+        assert_eq!(expected_subsidy(2_099_999), 5000 * COIN);
+        assert_eq!(expected_subsidy(2_100_000), 2500 * COIN);
+        assert_eq!(expected_subsidy(2491604), 2500 * COIN);
+        assert_eq!(expected_subsidy(4_200_000), 1250 * COIN);
+        assert_eq!(expected_subsidy(u32::MAX), 0);
+
+        let mut info = sample_template_info();
+        assert!(info.validate_coinbase_value().is_ok());
+        info.coinbasevalue += 1;
+        assert!(info.validate_coinbase_value().is_err());
+    }
+
+    #[test]
+    fn test_max_money() {
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let mut info = sample_template_info();
+        assert!(info.coinbasevalue <= MAX_MONEY);
+        assert!(BlockTemplate::new(&info, pool_addr.clone(), String::new()).is_ok());
+
+        info.transactions[0].fee += MAX_MONEY;
+        info.coinbasevalue += MAX_MONEY;
+        assert!(info.validate_coinbase_value().is_ok());
+        let err = BlockTemplate::new(&info, pool_addr, String::new()).unwrap_err();
+        assert!(err.to_string().contains("max money"), "{}", err);
+    }
+
+    #[test]
+    fn test_coinbase_version_locktime() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            coinbase_version: 2,
+            locktime: 2491603,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time.0, 2491603);
+
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&sample_template().coinbase_tx).unwrap();
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.lock_time.0, 0);
+    }
+
+    #[test]
+    fn test_coinbase_sequence() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            coinbase_sequence: 0xfffffffe,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(tx.input[0].sequence.0, 0xfffffffe);
+        template.self_check().unwrap();
+
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&sample_template().coinbase_tx).unwrap();
+        assert_eq!(tx.input[0].sequence.0, 0xffffffff);
+    }
+
+    #[test]
+    fn test_coinbase_txin_long_script() {
+        let script = Script::op_return_with_limit(&[0xab; 300], 300).unwrap();
+        assert_eq!(script.as_slice().len(), 304);
+        let txin = BlockTemplate::coinbase_txin(&script, 0xffffffff);
+        // 0xfd 后接 2 字节小端长度
+        assert_eq!(&txin[36..39], &[0xfd, 0x30, 0x01]);
+        assert_eq!(&txin[39..39 + 304], script.as_slice());
+        assert_eq!(&txin[39 + 304..], &[0xff; 4]);
+    }
+
+    #[test]
+    fn test_coinbase_txin_round_trip() {
+        for len in [4, 100, 200, 252, 253, 255, 256, 300] {
+            // OP_RETURN 加上 push 前缀后正好 len 字节
+            let script = (0..len)
+                .map(|n| Script::op_return_with_limit(&vec![0xab; n], len).unwrap())
+                .find(|script| script.as_slice().len() == len)
+                .unwrap();
+            assert_eq!(script.as_slice().len(), len);
+            let txin = BlockTemplate::coinbase_txin(&script, 0x1234_5678);
+            // 不超过 252 字节时长度仍为单字节
+            let prefix = if len < 0xfd { 1 } else { 3 };
+            assert_eq!(txin.len(), 36 + prefix + len + 4);
+            if len < 0xfd {
+                assert_eq!(txin[36], len as u8);
+            }
+            let mut reader = OpReader::new(&txin[36..]);
+            assert_eq!(reader.read_var_len().unwrap(), len);
+            assert_eq!(reader.take(len).unwrap(), script.as_slice());
+
+            let decoded: bitcoin::TxIn = bitcoin::consensus::deserialize(&txin).unwrap();
+            assert_eq!(decoded.script_sig.as_bytes(), script.as_slice());
+            assert_eq!(decoded.sequence.0, 0x1234_5678);
+            assert_eq!(bitcoin::consensus::serialize(&decoded), txin);
+        }
+    }
+
+    #[test]
+    fn test_asset_outputs() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let mut script = pool_addr.vout_to_miner();
+        script.push(0xc0);
+        let data = b"rvnt\x04TEST\x00\xe1\xf5\x05\x00\x00\x00\x00";
+        script.push(data.len() as u8);
+        script.extend_from_slice(data);
+        script.push(0x75);
+        let options = BlockTemplateOptions {
+            asset_outputs: vec![AssetOutput { script, value: 0 }],
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr.clone(), String::new(), &options)
+                .unwrap();
+        let coinbase: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(coinbase.output.len(), 3);
+        assert_eq!(
+            coinbase.output[1].script_pubkey.as_bytes(),
+            options.asset_outputs[0].script
+        );
+        assert_eq!(
+            coinbase.output[2].script_pubkey.as_bytes(),
+            hex::decode(&info.default_witness_commitment).unwrap()
+        );
+        // 输出数在 txin 之后
+        let script_len = template.coinbase_tx[COINBASE_SCRIPT_OFFSET - 1] as usize;
+        assert_eq!(
+            template.coinbase_tx[COINBASE_SCRIPT_OFFSET + script_len + 4],
+            0x03
+        );
+        assert_eq!(coinbase.txid().to_vec(), template.coinbase_txid.to_vec());
+
+        let options = BlockTemplateOptions {
+            asset_outputs: vec![AssetOutput {
+                script: vec![0x51],
+                value: info.coinbasevalue + 1,
+            }],
+            ..Default::default()
+        };
+        assert!(
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).is_err()
+        );
+    }
+
+    #[test]
+    fn test_txid_from_witness_tx() {
+        // 一个输入一个输出，witness 栈中有一项 0xab
+        let stripped = OpData::default()
+            .push_u32(2)
+            .var_push_num(1)
+            .push_slice(&[0x11; 32])
+            .push_u32(0)
+            .var_push_num(0)
+            .push_u32(0xffff_ffff)
+            .var_push_num(1)
+            .push_u64(1000)
+            .var_push_num(1)
+            .push_slice(&[0x51])
+            .push_u32(0);
+        let stripped = stripped.as_slice();
+        let witness = OpData::default()
+            .push_slice(&stripped[..4])
+            .push_slice(&[0x00, 0x01])
+            .push_slice(&stripped[4..stripped.len() - 4])
+            .var_push_num(1)
+            .var_push_num(1)
+            .push_slice(&[0xab])
+            .push_slice(&stripped[stripped.len() - 4..]);
+        let raw = witness.as_slice();
+
+        let txid = txid_from_witness_tx(raw).unwrap();
+        assert_eq!(txid, dsha256(stripped));
+        assert_eq!(txid, txid_from_raw(raw).unwrap());
+        // wtxid 是完整序列化的 dsha256
+        assert_ne!(txid, dsha256(raw));
+        let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(raw).unwrap();
+        assert_eq!(tx.txid().to_string(), hex::encode(reverse_hash(txid)));
+
+        assert!(txid_from_witness_tx(stripped).is_err());
+        assert!(txid_from_witness_tx(&raw[..raw.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_txid_from_raw() {
+        let info = sample_template_info();
+        for tx in &info.transactions {
+            let raw = hex::decode(&tx.data).unwrap();
+            assert_eq!(
+                txid_from_raw(&raw).unwrap(),
+                decode_hash32_reversed(&tx.txid).unwrap()
+            );
+            assert_eq!(tx_weight(&raw).unwrap(), tx.weight);
+        }
+
+        // coinbase 带 witness
+        let template = sample_template();
+        assert_eq!(
+            txid_from_raw(&template.coinbase_tx).unwrap(),
+            template.coinbase_txid
+        );
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(
+            tx_weight(&template.coinbase_tx).unwrap() as usize,
+            tx.weight()
+        );
+
+        assert_eq!(
+            txid_from_witness_tx(&template.coinbase_tx).unwrap(),
+            template.coinbase_txid
+        );
+
+        let len = template.coinbase_tx.len();
+        assert!(txid_from_raw(&template.coinbase_tx[..len - 1]).is_err());
+        let mut raw = template.coinbase_tx.clone();
+        raw.push(0);
+        assert!(tx_weight(&raw).is_err());
+    }
+
+    #[test]
+    fn test_verify_payout() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            verify_payout: true,
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr.clone(), String::new(), &options)
+                .unwrap();
+        let coinbase: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert!(pool_addr.matches_script(coinbase.output[0].script_pubkey.as_bytes()));
+    }
+
+    #[test]
+    fn test_signalling_version() {
+        let mut info = sample_template_info();
+        assert_eq!(info.signalling_version(&["segwit"]), info.version);
+        info.vbavailable.insert("segwit".to_string(), 1);
+        info.vbavailable.insert("csv".to_string(), 0);
+        let version = info.signalling_version(&["segwit", "unknown"]);
+        assert_eq!(version, 0x30000002);
+
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            version: Some(version),
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        assert_eq!(template.version, 0x30000002);
+        assert_eq!(
+            Header::from_bytes(&template.header).unwrap().version,
+            0x30000002
+        );
+
+        // vbrequired 中的位总是置位
+        info.vbrequired = 1 << 3;
+        assert_eq!(info.signalling_version(&[]), 0x30000008);
+        assert_eq!(info.signalling_version(&["csv"]), 0x30000009);
+    }
+
+    #[test]
+    fn test_witness_output_last() {
+        let template = sample_template();
+        assert_eq!(template.output_count().unwrap(), 2);
+        assert_eq!(template.witness_output_index().unwrap(), 1);
+
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            asset_outputs: vec![AssetOutput {
+                script: pool_addr.vout_to_miner(),
+                value: 100_000_000,
+            }],
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        assert_eq!(template.output_count().unwrap(), 3);
+        assert_eq!(template.witness_output_index().unwrap(), 2);
+        template.self_check().unwrap();
+    }
+
+    #[test]
+    fn test_debug_report() {
+        let template = sample_template();
+        let report = template.debug_report();
+        assert!(report.contains("height:        2491604\n"));
+        let header_hash = report
+            .lines()
+            .find_map(|l| l.strip_prefix("header hash:"))
+            .unwrap()
+            .trim();
+        assert_eq!(header_hash.len(), 64);
+        assert_eq!(header_hash, hex::encode(template.header_hash));
+        assert!(report.contains(&template.bits_hex));
+    }
+
+    #[test]
+    fn test_witness_reserved_value() {
+        let info = sample_template_info();
+        let transactions: Vec<_> = info.transactions.iter().collect();
+        assert_eq!(
+            witness_commitment(&transactions, &[0; 32]).unwrap(),
+            info.default_witness_commitment
+        );
+
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            witness_reserved_value: [7; 32],
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        let coinbase: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&template.coinbase_tx).unwrap();
+        assert_eq!(coinbase.input[0].witness.to_vec(), vec![vec![7; 32]]);
+        let commitment = witness_commitment(&transactions, &[7; 32]).unwrap();
+        assert_ne!(commitment, info.default_witness_commitment);
+        assert_eq!(
+            coinbase.output[1].script_pubkey.as_bytes(),
+            hex::decode(&commitment).unwrap()
+        );
+        assert_eq!(coinbase.txid().to_vec(), template.coinbase_txid.to_vec());
+    }
+
+    /// 地址版本号与主网相同，但从高度 0 开始使用 KawPow，用于 builder 生成的低高度模板
+    fn regtest_options() -> BlockTemplateOptions {
+        BlockTemplateOptions {
+            chain_params: ChainParams {
+                kawpow_activation_height: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template_info_builder() {
+        let info = BlockTemplateInfo::builder().height(100).build().unwrap();
+        assert_eq!(info.target, format!("7fffff{}", "0".repeat(58)));
+        assert_eq!(info.coinbasevalue, expected_subsidy(100));
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new_with_options(
+            &info,
             pool_addr,
-            "with a little help from http://github.com/kralverde/ravencoin-stratum-proxy"
-                .to_string(),
+            "pool".to_string(),
+            &regtest_options(),
         )
         .unwrap();
+        assert_eq!(template.height, 100);
+        assert!(template.external_txs.is_empty());
 
-        println!("{:?}", template)
+        let sample = sample_template_info();
+        let info = BlockTemplateInfo::builder()
+            .height(sample.height)
+            .previousblockhash(&sample.previousblockhash)
+            .bits(&sample.bits)
+            .target(&sample.target)
+            .coinbasevalue(sample.coinbasevalue)
+            .transactions(sample.transactions.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            info.default_witness_commitment,
+            sample.default_witness_commitment
+        );
+        assert!(info.validate_target_bits().is_ok());
+        assert!(info.validate_coinbase_value().is_ok());
+    }
+
+    #[test]
+    fn test_zero_payout() {
+        let info = BlockTemplateInfo::builder()
+            .height(100)
+            .coinbasevalue(0)
+            .build()
+            .unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let err = BlockTemplate::new_with_options(
+            &info,
+            pool_addr.clone(),
+            String::new(),
+            &regtest_options(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("pool payout is zero"));
+
+        let mut options = regtest_options();
+        options.chain_params.omit_zero_payout = true;
+        options.verify_payout = true;
+        let mut template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        let coinbase = template.coinbase().unwrap();
+        assert_eq!(coinbase.output.len(), 1);
+        assert_eq!(template.witness_output_index().unwrap(), 0);
+        assert!(template.validate_consistency().is_ok());
+        // 没有矿池输出，无手续费的交易集合可以替换，有手续费的不行
+        assert!(template.set_transactions(&[]).is_ok());
+        let sample = sample_template_info();
+        let txs: Vec<_> = sample.transactions.iter().collect();
+        assert!(template.set_transactions(&txs).is_err());
+    }
+
+    #[test]
+    fn test_jobs_for_difficulties() {
+        let info = BlockTemplateInfo::builder().height(100).build().unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &regtest_options())
+                .unwrap();
+        let diffs = [1, 1000, 16384];
+        // regtest 的区块目标比这些难度都容易，份额目标都取区块目标
+        let jobs = template.jobs_for_difficulties(&diffs, true).unwrap();
+        assert_eq!(jobs.len(), diffs.len());
+        for (job, diff) in jobs.iter().zip(diffs) {
+            assert_eq!(job.header_hash, template.header_hash);
+            assert_eq!(job.share_target_hex, template.target_hex);
+            assert_eq!(
+                job,
+                &template.create_job_for_difficulty(diff, true).unwrap()
+            );
+        }
+
+        // 主网的区块目标很难，低于网络难度的份额目标保持不变
+        let template = sample_template();
+        let diffs = [1, 1000, u64::MAX];
+        let jobs = template.jobs_for_difficulties(&diffs, false).unwrap();
+        assert_eq!(jobs[0].share_target_hex, pool_diff_to_target_hex(1));
+        assert_eq!(jobs[1].share_target_hex, pool_diff_to_target_hex(1000));
+        assert_ne!(jobs[0].share_target_hex, jobs[1].share_target_hex);
+        assert_eq!(jobs[2].share_target_hex, template.target_hex);
+        assert!(template
+            .jobs_for_difficulties(&[], false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_normalized_template() {
+        let info = sample_template_info();
+        let normalized = NormalizedTemplate::try_from(info.clone()).unwrap();
+        assert_eq!(normalized.bits, 0x1a5ab50d);
+        assert_eq!(normalized.target, uint256_from_hash(&info.target).unwrap());
+        assert_eq!(normalized.txids.len(), info.transactions.len());
+        assert_eq!(
+            normalized.prev_hash.to_vec(),
+            reverse_bytes(&hex::decode(&info.previousblockhash).unwrap())
+        );
+
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            seed_hash: Some(sample_template().seed_hash),
+            ..Default::default()
+        };
+        for select_transactions in [false, true] {
+            let options = BlockTemplateOptions {
+                select_transactions,
+                ..options.clone()
+            };
+            let mut direct =
+                BlockTemplate::new_with_options(&info, pool_addr.clone(), String::new(), &options)
+                    .unwrap();
+            let mut from_normalized = BlockTemplate::from_normalized(
+                &normalized,
+                pool_addr.clone(),
+                String::new(),
+                &options,
+            )
+            .unwrap();
+            // 时间取自当前时钟，统一后再比较
+            direct.timestamp = 0;
+            from_normalized.timestamp = 0;
+            direct.built_at = 0;
+            from_normalized.built_at = 0;
+            direct.header.truncate(TIME_OFFSET);
+            from_normalized.header.truncate(TIME_OFFSET);
+            direct.header_hash = [0; 32];
+            from_normalized.header_hash = [0; 32];
+            assert_eq!(direct, from_normalized);
+        }
+
+        let mut bad = info.clone();
+        bad.transactions[0].txid = "0102".to_string();
+        assert!(NormalizedTemplate::try_from(bad).is_err());
+        let mut bad = info;
+        bad.previousblockhash.pop();
+        assert!(NormalizedTemplate::try_from(bad).is_err());
+    }
+
+    #[test]
+    fn test_validate_external_txs() {
+        let template = sample_template();
+        assert_eq!(template.external_txids.len(), template.external_txs.len());
+        template.validate_external_txs().unwrap();
+
+        // 改动输出金额，txid 随之改变
+        let mut tampered = template.clone();
+        let mut raw = hex::decode(&tampered.external_txs[2]).unwrap();
+        let last = raw.len() - 10;
+        raw[last] ^= 1;
+        tampered.external_txs[2] = hex::encode(raw);
+        let err = tampered.validate_external_txs().unwrap_err();
+        assert!(err.to_string().starts_with("external tx 2 txid mismatch"));
+
+        let mut tampered = template.clone();
+        tampered.external_txs[1].push('z');
+        let err = tampered.validate_external_txs().unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("external tx 1 is not valid hex"));
+
+        let mut legacy = template;
+        legacy.external_txids.clear();
+        assert!(legacy.validate_external_txs().is_err());
+    }
+
+    #[test]
+    fn test_block_template_builder() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(&info, pool_addr.clone(), "pool".to_string()).unwrap();
+        // 固定为 new 取到的时间，结果应完全一致
+        let builder = BlockTemplate::builder(pool_addr.clone())
+            .coinbase_message("pool")
+            .ntime_policy(NtimePolicy::Fixed(template.timestamp));
+        let mut built = builder.build(&info).unwrap();
+        built.built_at = template.built_at;
+        assert_eq!(built, template);
+
+        let sub = Subscription::new(vec![0x11, 0x22, 0x33, 0x44], 4);
+        let options = BlockTemplateOptions {
+            subscription: Some(sub.clone()),
+            coinbase_version: 2,
+            witness_reserved_value: [1; 32],
+            ntime_policy: NtimePolicy::MinTime,
+            ..Default::default()
+        };
+        let expected =
+            BlockTemplate::new_with_options(&info, pool_addr.clone(), "pool".to_string(), &options)
+                .unwrap();
+        let mut built = BlockTemplate::builder(pool_addr)
+            .coinbase_message("pool")
+            .extranonce1(sub.extranonce1, sub.extranonce2_size)
+            .coinbase_version(2)
+            .witness_reserved([1; 32])
+            .ntime_policy(NtimePolicy::MinTime)
+            .build(&info)
+            .unwrap();
+        built.built_at = expected.built_at;
+        assert_eq!(built, expected);
+        assert_eq!(built.timestamp as u64, info.mintime);
+    }
+
+    #[test]
+    fn test_header_time() {
+        let now = 1665555000;
+        assert_eq!(header_time(now, 1665555669), 1665555669);
+        assert_eq!(header_time(now, 1665554000), now);
+        assert_eq!(header_time(now, now as u64 + 10000), now + 7200);
+
+        let mut info = sample_template_info();
+        info.mintime = super::now() as u64 + 600;
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(&info, pool_addr, String::new()).unwrap();
+        assert_eq!(template.timestamp as u64, info.mintime());
+        assert_eq!(
+            Header::from_bytes(&template.header).unwrap().time,
+            template.timestamp
+        );
+    }
+
+    #[test]
+    fn test_template_decode_error() {
+        let mut info = sample_template_info();
+        info.previousblockhash = "zz".to_string();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let err = BlockTemplate::new(&info, pool_addr, String::new()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RvnError>(),
+            Some(RvnError::TemplateDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_self_check() {
+        let template = sample_template();
+        template.self_check().unwrap();
+
+        let mut tampered = template.clone();
+        tampered.coinbase_tx[COINBASE_SCRIPT_OFFSET + 8] ^= 0xff;
+        assert!(tampered.self_check().is_err());
+
+        let mut tampered = template;
+        tampered.external_txs.swap(0, 1);
+        assert!(tampered.self_check().is_err());
+    }
+
+    #[test]
+    fn test_witness_hex() {
+        let info = sample_template_info();
+        let template = sample_template();
+        assert_eq!(template.witness_hex, info.default_witness_commitment);
+        assert!(hex::encode(&template.coinbase_tx).contains(&template.witness_hex));
+
+        // 非 0 的 reserved value 重新计算 commitment，witness_hex 与 coinbase 一致
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            witness_reserved_value: [1; 32],
+            ..Default::default()
+        };
+        let template =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        assert_ne!(template.witness_hex, info.default_witness_commitment);
+        assert!(hex::encode(&template.coinbase_tx).contains(&template.witness_hex));
+        assert_eq!(
+            template.default_witness_hex,
+            info.default_witness_commitment
+        );
+        assert!(!template.is_new_template(&info));
+    }
+
+    #[test]
+    fn test_set_transactions() {
+        let info = sample_template_info();
+        let template = sample_template();
+        let all: Vec<&Transaction> = info.transactions.iter().collect();
+
+        // 原样设置不改变任何字段
+        let mut same = template.clone();
+        same.set_transactions(&all).unwrap();
+        assert_eq!(same.coinbase_tx, template.coinbase_tx);
+        assert_eq!(same.header, template.header);
+        assert_eq!(same.witness_hex, template.witness_hex);
+        assert_eq!(same.external_fees, template.external_fees);
+
+        let mut dropped = template.clone();
+        let dropped_tx = all.last().unwrap();
+        dropped.set_transactions(&all[..all.len() - 1]).unwrap();
+        dropped.self_check().unwrap();
+        assert_eq!(dropped.external_txs.len(), template.external_txs.len() - 1);
+        assert_ne!(dropped.header, template.header);
+        assert_ne!(dropped.header_hash, template.header_hash);
+        assert_ne!(dropped.witness_hex, template.witness_hex);
+        assert_eq!(
+            dropped.witness_hex,
+            witness_commitment(&all[..all.len() - 1], &[0; 32]).unwrap()
+        );
+        assert!(hex::encode(&dropped.coinbase_tx).contains(&dropped.witness_hex));
+        assert_eq!(dropped.coinbase_tx.len(), template.coinbase_tx.len());
+        // 节点的模板没有变化，不应触发新任务
+        assert_eq!(dropped.default_witness_hex, info.default_witness_commitment);
+        assert!(!dropped.is_new_template(&info));
+
+        // 非 0 的 reserved value 按实际值计算 commitment
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            witness_reserved_value: [1; 32],
+            ..Default::default()
+        };
+        let mut reserved =
+            BlockTemplate::new_with_options(&info, pool_addr, String::new(), &options).unwrap();
+        reserved.set_transactions(&all[..all.len() - 1]).unwrap();
+        reserved.self_check().unwrap();
+        assert_eq!(
+            reserved.witness_hex,
+            witness_commitment(&all[..all.len() - 1], &[1; 32]).unwrap()
+        );
+        assert!(hex::encode(&reserved.coinbase_tx).contains(&reserved.witness_hex));
+        assert!(!reserved.is_new_template(&info));
+        assert_eq!(
+            dropped.external_weight,
+            template.external_weight - dropped_tx.weight as u64
+        );
+        // 去掉的手续费从矿池输出中扣除
+        let pool_value = |t: &BlockTemplate| t.coinbase().unwrap().output[0].value;
+        assert_eq!(pool_value(&dropped), pool_value(&template) - dropped_tx.fee);
+
+        // 直接修改 external_txs 会导致自检失败
+        let mut stale = template;
+        stale.external_txs.pop();
+        assert!(stale.self_check().is_err());
+    }
+
+    #[test]
+    fn test_coinbase_builder() {
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let script = Script::coinbase_script(2491604, "pool").unwrap();
+        let txin = BlockTemplate::coinbase_txin(&script, 0xffffffff);
+        let outputs = OpData::default()
+            .var_push_num(2)
+            .push_u64(250_000_000_000)
+            .op_push_slice(&pool_addr.vout_to_miner())
+            .push_slice(&[0; 8])
+            .op_push_slice(
+                &hex::decode(sample_template_info().default_witness_commitment).unwrap(),
+            );
+        let builder = CoinbaseBuilder {
+            version: 1,
+            txin: txin.clone(),
+            outputs: outputs.as_slice().to_vec(),
+            witness_reserved_value: [0; 32],
+            locktime: 0,
+        };
+
+        let coinbase_tx = OpData::default()
+            .push_u32(1)
+            .push_slice(&[0x00, 0x01, 0x01])
+            .push_slice(&txin)
+            .push_slice(outputs.as_slice())
+            .push_slice(&[0x01, 0x20])
+            .push_slice(&[0; 32])
+            .push_u32(0);
+        let coinbase_no_wit = OpData::default()
+            .push_u32(1)
+            .push_u8(0x01)
+            .push_slice(&txin)
+            .push_slice(outputs.as_slice())
+            .push_u32(0);
+        assert_eq!(builder.to_bytes(), coinbase_tx.as_slice());
+        assert_eq!(builder.to_stripped_bytes(), coinbase_no_wit.as_slice());
+        assert_eq!(builder.txid(), dsha256(coinbase_no_wit.as_slice()));
+        assert_eq!(builder.txid(), txid_from_raw(&builder.to_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_try_create_job() {
+        let template = sample_template();
+        let job = template
+            .try_create_job(template.target_hex.clone(), true)
+            .unwrap();
+        assert_eq!(job, template.create_job(template.target_hex.clone(), true));
+        assert!(template
+            .try_create_job("00000001ffff".to_string(), true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_create_job_for_difficulty() {
+        let info = BlockTemplateInfo::builder().height(100).build().unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new_with_options(
+            &info,
+            pool_addr,
+            "pool".to_string(),
+            &regtest_options(),
+        )
+        .unwrap();
+        // regtest 的区块目标比难度 1 更容易，取区块目标
+        let job = template.create_job_for_difficulty(1, true).unwrap();
+        assert_eq!(job.share_target_hex, template.target_hex);
+
+        let template = sample_template();
+        let job = template.create_job_for_difficulty(1, true).unwrap();
+        assert_eq!(
+            job.share_target_hex,
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+        // 比区块更难的份额目标取区块目标
+        let job = template.create_job_for_difficulty(u64::MAX, true).unwrap();
+        assert_eq!(job.share_target_hex, template.target_hex);
+        // 份额目标总不比区块目标更难
+        for diff in [1, 1 << 20, 1 << 40, u64::MAX] {
+            let job = template.create_job_for_difficulty(diff, true).unwrap();
+            assert!(
+                uint256_from_hash(&job.share_target_hex).unwrap()
+                    >= uint256_from_hash(&template.target_hex).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_template_merkle_branches() {
+        let info = sample_template_info();
+        let template = sample_template();
+        let header = Header::from_bytes(&template.header).unwrap();
+        assert_eq!(
+            merkle_root_from_coinbase(template.coinbase_txid, &template.merkle_branches),
+            header.merkle_root
+        );
+
+        let coinbase_txid = [9; 32];
+        let mut txids = vec![coinbase_txid];
+        for tx in &info.transactions {
+            txids.push(decode_hash32_reversed(&tx.txid).unwrap());
+        }
+        assert_eq!(
+            merkle_root_from_coinbase(coinbase_txid, &template.merkle_branches),
+            merkel_hash(txids)
+        );
+    }
+
+    #[test]
+    fn test_nonce_range() {
+        let mut info = sample_template_info();
+        assert_eq!(info.nonce_range().unwrap(), (0, 0xffffffff));
+        assert!(info.validate_nonce(u64::MAX).is_ok());
+
+        info.noncerange = "00001000000010ff".to_string();
+        assert_eq!(info.nonce_range().unwrap(), (0x1000, 0x10ff));
+        assert!(info.validate_nonce(0x0000_1080_ffff_ffff).is_ok());
+        assert!(info.validate_nonce(0x0000_1100_0000_0000).is_err());
+        assert!(info.validate_nonce(0).is_err());
+
+        info.noncerange = "00000000".to_string();
+        assert!(info.nonce_range().is_err());
+    }
+
+    #[test]
+    fn test_template_hash() {
+        use std::collections::HashSet;
+
+        let template = sample_template();
+        let mut other = template.clone();
+        other.height += 1;
+        let mut set = HashSet::new();
+        assert!(set.insert(template.clone()));
+        assert!(!set.insert(template.clone()));
+        assert!(set.insert(other));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_hash_accessors() {
+        let info = sample_template_info();
+        let template = sample_template();
+        let internal = dsha256(&template.header);
+        assert_eq!(template.header_hash_internal(), internal);
+        assert_eq!(template.header_hash_display(), reverse_hash(internal));
+        assert_eq!(template.header_hash_display(), template.header_hash);
+
+        let txid = txid_from_raw(&template.coinbase_tx).unwrap();
+        assert_eq!(template.coinbase_txid_in(HashOrientation::Internal), txid);
+        assert_eq!(
+            template.coinbase_txid_in(HashOrientation::Display),
+            reverse_hash(txid)
+        );
+
+        let prev_hash = template.prev_hash_in(HashOrientation::Display).unwrap();
+        assert_eq!(hex::encode(prev_hash), info.previousblockhash);
+        assert_eq!(
+            template.prev_hash_in(HashOrientation::Internal).unwrap(),
+            template.header[PREV_OFFSET..MERKLE_OFFSET]
+        );
+
+        // seed 为 keccak 的原始输出，与 kawpowminer 等矿机的 epoch 1 seed 一致
+        let template = BlockTemplate {
+            seed_hash: kawpow_seed_for_epoch(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.seed_hash_hex(),
+            "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563"
+        );
+        assert_eq!(
+            template.create_job(String::new(), true).seed_hash,
+            template.seed_hash_hex()
+        );
+        assert!(template.prev_hash_in(HashOrientation::Internal).is_err());
+
+        let mut hash = [1; 32];
+        hash[0] = 2;
+        assert_eq!(
+            HashOrientation::Display.convert(hash, HashOrientation::Display),
+            hash
+        );
+        assert_eq!(
+            HashOrientation::Internal.convert(hash, HashOrientation::Display),
+            reverse_hash(hash)
+        );
+    }
+
+    #[test]
+    fn test_prev_hash_display() {
+        let mut info = sample_template_info();
+        let template = sample_template();
+        assert_eq!(template.prev_hash_display(), info.previousblockhash);
+
+        // 少一个字节
+        info.previousblockhash = info.previousblockhash[2..].to_string();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let err = BlockTemplate::new(&info, pool_addr, String::new()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RvnError>(),
+            Some(RvnError::TemplateDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_seed_hash() {
+        let params = ChainParams::default();
+        for height in [0, 7499, 7500, 2491604] {
+            assert_eq!(params.epoch(height), kawpow_epoch(height));
+        }
+        assert_eq!(sample_template().seed_hash, kawpow_seed_for_epoch(332));
+
+        // 已知高度对应的 epoch seed，与矿机显示的 seed 对照
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        for (height, seed) in [
+            (7499, "0".repeat(64)),
+            (
+                7500,
+                "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563".to_string(),
+            ),
+            (
+                15000,
+                "510e4e770828ddbf7f7b00ab00a9f6adaf81c0dc9cc85f1f8249c256942d61d9".to_string(),
+            ),
+        ] {
+            let info = BlockTemplateInfo::builder().height(height).build().unwrap();
+            let template = BlockTemplate::new_with_options(
+                &info,
+                pool_addr.clone(),
+                String::new(),
+                &regtest_options(),
+            )
+            .unwrap();
+            assert_eq!(template.seed_hash_hex(), seed);
+        }
+    }
+
+    #[test]
+    fn test_kawpow_activation() {
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let activation = ChainParams::default().kawpow_activation_height;
+        let info = BlockTemplateInfo::builder()
+            .height(activation - 1)
+            .build()
+            .unwrap();
+        let err = BlockTemplate::new(&info, pool_addr.clone(), String::new()).unwrap_err();
+        assert!(err.to_string().contains("kawpow activation"));
+        assert!(BlockTemplate::new_with_options(
+            &info,
+            pool_addr.clone(),
+            String::new(),
+            &regtest_options()
+        )
+        .is_ok());
+
+        let info = BlockTemplateInfo::builder()
+            .height(activation)
+            .build()
+            .unwrap();
+        let template = BlockTemplate::new(&info, pool_addr, String::new()).unwrap();
+        assert_eq!(
+            template.seed_hash,
+            kawpow_seed_for_epoch(activation / KAWPOW_EPOCH_LENGTH)
+        );
+    }
+
+    #[test]
+    fn test_new_with_seed() {
+        let info = sample_template_info();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let pool_info = "pool".to_string();
+        let template = BlockTemplate::new(&info, pool_addr.clone(), pool_info.clone()).unwrap();
+        let seed = kawpow_seed_for_epoch(332);
+        let mut with_seed =
+            BlockTemplate::new_with_seed(&info, pool_addr.clone(), pool_info.clone(), seed)
+                .unwrap();
+        // 两次生成跨过整秒时只有时间不同
+        if with_seed.timestamp != template.timestamp {
+            let header = Header::from_bytes(&with_seed.header)
+                .unwrap()
+                .with_time(template.timestamp, false)
+                .unwrap();
+            with_seed.timestamp = template.timestamp;
+            with_seed.header_hash = header.hash();
+            with_seed.header = header.to_bytes();
+        }
+        with_seed.built_at = template.built_at;
+        assert_eq!(with_seed, template);
+
+        // 不校验 seed 与高度是否对应
+        let other = BlockTemplate::new_with_seed(&info, pool_addr, pool_info, [1; 32]).unwrap();
+        assert_eq!(other.seed_hash, [1; 32]);
+    }
+
+    #[test]
+    fn test_chain_params() {
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let options = BlockTemplateOptions {
+            chain_params: ChainParams {
+                epoch_length: 10,
+                coinbase_message: "regtest".to_string(),
+                kawpow_activation_height: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for (height, epoch) in [(9, 0), (10, 1), (25, 2)] {
+            let info = BlockTemplateInfo::builder().height(height).build().unwrap();
+            let template =
+                BlockTemplate::new_with_options(&info, pool_addr.clone(), String::new(), &options)
+                    .unwrap();
+            assert_eq!(template.seed_hash, kawpow_seed_for_epoch(epoch));
+            assert_eq!(template.pool_info, "regtest");
+            // 主网在这些高度尚未激活 KawPow
+            assert!(BlockTemplate::new(&info, pool_addr.clone(), String::new()).is_err());
+        }
+
+        // 地址版本号与链参数不一致
+        let info = BlockTemplateInfo::builder().build().unwrap();
+        let testnet_addr = Address::from_str("mwLpTDfk4HSCqnYR1ZPHKutXxMBDK3TaFF").unwrap();
+        assert!(BlockTemplate::new(&info, testnet_addr.clone(), String::new()).is_err());
+        let options = BlockTemplateOptions {
+            chain_params: ChainParams::ravencoin_testnet(),
+            ..Default::default()
+        };
+        assert!(
+            BlockTemplate::new_with_options(&info, testnet_addr, String::new(), &options).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let template = sample_template();
+        let header = Header::from_bytes(&template.header).unwrap();
+        assert_eq!(header.to_bytes(), template.header);
+        assert_eq!(header.hash(), template.header_hash);
+        assert_eq!(header.height, template.height);
+        assert_eq!(header.version, template.version);
+        assert_eq!(header.bits, 0x1a5ab50d);
+        assert_eq!(header.time, template.timestamp);
+        assert_eq!(header.prev_hash.to_vec(), template.prev_hash);
+
+        assert!(Header::from_bytes(&template.header[..79]).is_err());
+    }
+
+    #[test]
+    fn test_job_header_with_time() {
+        let template = sample_template();
+        let job = template.create_job(template.target_hex.clone(), true);
+        assert_eq!(job.header_with_time(job.timestamp).unwrap(), job.header);
+        assert_eq!(
+            job.header_hash_with_time(job.timestamp).unwrap(),
+            job.header_hash
+        );
+        assert_ne!(
+            job.header_hash_with_time(job.timestamp + 1).unwrap(),
+            job.header_hash
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let template = sample_template();
+        let summary = template.summary();
+        assert_eq!(summary.tx_count, template.external_txs.len() + 1);
+
+        let json = serde_json::to_string(&summary).unwrap();
+        assert!(!json.contains('\n'));
+        assert!(!json.contains("coinbase_tx"));
+        assert!(!json.contains("external_txs"));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["height"], template.height);
+        assert_eq!(value["header_hash"], hex::encode(template.header_hash));
+        assert!(json.len() < serde_json::to_string(&template).unwrap().len());
     }
 }
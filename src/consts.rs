@@ -0,0 +1,124 @@
+/// KawPow 每个 epoch 的区块数
+pub const KAWPOW_EPOCH_LENGTH: u32 = 7500;
+
+/// 1 RVN 对应的 satoshi 数
+pub const COIN: u64 = 100_000_000;
+
+/// Ravencoin 初始区块奖励 5000 RVN
+pub const INITIAL_SUBSIDY: u64 = 5000 * COIN;
+
+/// 货币总量上限 210 亿 RVN，任何金额都不能超过该值
+pub const MAX_MONEY: u64 = 21_000_000_000 * COIN;
+
+/// 区块奖励每 2100000 个区块减半
+pub const HALVING_INTERVAL: u32 = 2_100_000;
+
+/// 主网切换到 KawPow 的高度，之前的区块使用 X16Rv2，没有 seed hash
+pub const KAWPOW_ACTIVATION_HEIGHT: u32 = 1_219_736;
+
+/// 主网 P2PKH 地址的版本号
+pub const P2PKH_VERSION: u8 = 60;
+
+/// 主网 P2SH 地址的版本号
+pub const P2SH_VERSION: u8 = 122;
+
+/// 链参数，用于在参数不同的测试链上构造模板
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChainParams {
+    /// pool_info 为空时写入 coinbase 的信息
+    pub coinbase_message: String,
+    pub epoch_length: u32,
+    pub initial_subsidy: u64,
+    pub halving_interval: u32,
+    pub p2pkh_version: u8,
+    pub p2sh_version: u8,
+    /// 低于该高度的模板不能生成 KawPow 任务
+    pub kawpow_activation_height: u32,
+    /// 矿池输出金额为 0 时省略该输出，否则报错；主网不允许出现 0 金额输出
+    pub omit_zero_payout: bool,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::ravencoin_mainnet()
+    }
+}
+
+impl ChainParams {
+    pub fn ravencoin_mainnet() -> Self {
+        Self {
+            coinbase_message: String::new(),
+            epoch_length: KAWPOW_EPOCH_LENGTH,
+            initial_subsidy: INITIAL_SUBSIDY,
+            halving_interval: HALVING_INTERVAL,
+            p2pkh_version: P2PKH_VERSION,
+            p2sh_version: P2SH_VERSION,
+            kawpow_activation_height: KAWPOW_ACTIVATION_HEIGHT,
+            omit_zero_payout: false,
+        }
+    }
+
+    /// testnet 和 regtest 的地址版本号与主网不同，且按时间激活 KawPow，这里不限制高度；
+    /// regtest 上可能出现 coinbasevalue 为 0 的模板，省略 0 金额的矿池输出
+    pub fn ravencoin_testnet() -> Self {
+        Self {
+            p2pkh_version: 111,
+            p2sh_version: 196,
+            kawpow_activation_height: 0,
+            omit_zero_payout: true,
+            ..Self::ravencoin_mainnet()
+        }
+    }
+
+    /// 该高度的区块是否使用 KawPow
+    pub fn is_kawpow(&self, height: u32) -> bool {
+        height >= self.kawpow_activation_height
+    }
+
+    /// 高度所在的 epoch
+    pub fn epoch(&self, height: u32) -> u32 {
+        height / self.epoch_length
+    }
+
+    /// 指定高度的区块奖励（不含手续费）
+    pub fn subsidy(&self, height: u32) -> u64 {
+        let halvings = height / self.halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        self.initial_subsidy >> halvings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_consts() {
+        assert_eq!(KAWPOW_EPOCH_LENGTH, 7500);
+        assert_eq!(INITIAL_SUBSIDY, 500_000_000_000);
+        assert_eq!(MAX_MONEY, 2_100_000_000_000_000_000);
+    }
+
+    #[test]
+    fn test_chain_params() {
+        let params = ChainParams::default();
+        assert_eq!(params, ChainParams::ravencoin_mainnet());
+        assert_eq!(params.epoch(7499), 0);
+        assert_eq!(params.epoch(7500), 1);
+        assert_eq!(params.subsidy(2_100_000), INITIAL_SUBSIDY / 2);
+        assert!(!params.is_kawpow(KAWPOW_ACTIVATION_HEIGHT - 1));
+        assert!(params.is_kawpow(KAWPOW_ACTIVATION_HEIGHT));
+        assert!(ChainParams::ravencoin_testnet().is_kawpow(0));
+
+        let params = ChainParams {
+            epoch_length: 10,
+            halving_interval: 150,
+            ..Default::default()
+        };
+        assert_eq!(params.epoch(10), 1);
+        assert_eq!(params.subsidy(150), INITIAL_SUBSIDY / 2);
+        assert_eq!(params.subsidy(150 * 64), 0);
+    }
+}
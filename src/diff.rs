@@ -2,16 +2,40 @@ use anyhow::{bail, Result};
 pub use bitcoin::util::uint::Uint256;
 use std::collections::VecDeque;
 
+use crate::error::RvnError;
+use crate::util::{reverse_bytes, strip_hex_prefix};
+
+/// 由大端（显示序）的 64 位 hex 构造，长度不足时报错而不是补 0
 pub fn uint256_from_hash(s: &str) -> Result<Uint256> {
-    let s = s.trim_start_matches("0x");
+    let s = strip_hex_prefix(s);
     let raw = hex::decode(s)?;
+    if raw.len() != 32 {
+        bail!("invalid uint256 hex length {}, expect 64", s.len());
+    }
     let num = Uint256::from_be_slice(&raw)?;
     Ok(num)
 }
 
+/// uint256_from_hash 的逆运算，输出与 target_hex 相同格式的 64 位大端 hex
+pub fn uint256_to_hash(u: &Uint256) -> String {
+    hex::encode(u.to_be_bytes())
+}
+
+/// 下发给矿机的目标值：补齐前导 0 的 64 位大端 hex，与 RPC 的 target 格式一致
+pub fn format_target_hex(target: &Uint256) -> String {
+    format!("{:0>64}", uint256_to_hash(target))
+}
+
+/// 由小端（内部序）的字节构造，如 dsha256 或 kawpow 的输出
 pub fn uint256_from_bytes(d: [u8; 32]) -> Uint256 {
-    let be_data: Vec<_> = d.into_iter().rev().collect();
-    Uint256::from_be_slice(&be_data).unwrap()
+    Uint256::from_be_slice(&reverse_bytes(&d)).unwrap()
+}
+
+/// uint256_from_bytes 的逆运算，输出小端（内部序）的字节
+pub fn uint256_to_bytes(u: &Uint256) -> [u8; 32] {
+    let mut bytes = u.to_be_bytes();
+    bytes.reverse();
+    bytes
 }
 
 pub fn bits2target(bits: u32) -> Uint256 {
@@ -33,59 +57,105 @@ pub fn bits2target(bits: u32) -> Uint256 {
     }
 }
 
+/// bits2target 的逆运算，将目标值压缩为 4 字节的 bits（总是输出最短的规范编码）
+pub fn target2bits(target: Uint256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut compact = if size <= 3 {
+        target.low_u64() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3))).low_u64()
+    };
+    // 尾数最高位为符号位，需要右移并增加指数
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    (compact as u32) | ((size as u32) << 24)
+}
+
+/// 解析 RPC 返回的大端 hex bits，写入区块头时再按小端序列化
+///
+/// 接受 "1a5ab50d"、"1A5AB50D"、"0x1a5ab50d"、"0X1A5AB50D"
 pub fn parse_bits(str: &str) -> Result<u32> {
-    let b = hex::decode(&str)?;
+    let hex_str = str
+        .strip_prefix("0x")
+        .or_else(|| str.strip_prefix("0X"))
+        .unwrap_or(str);
+    let b = hex::decode(hex_str).map_err(|_| RvnError::InvalidBits(str.to_string()))?;
     if b.len() != 4 {
-        bail!("invalid bits");
+        bail!(RvnError::InvalidBits(str.to_string()));
     }
     Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
 }
 
-fn unit_target() -> Uint256 {
+/// parse_bits 的逆运算，输出 8 位大端 hex，与 BlockTemplate::bits_hex 一致
+pub fn bits_to_hex(bits: u32) -> String {
+    format!("{:08x}", bits)
+}
+
+/// 矿池难度 1 的目标值，即 UNIT_TARGET_HEX
+pub const UNIT_TARGET_HEX: &str =
+    "00000000ffff0000000000000000000000000000000000000000000000000000";
+
+/// 矿池难度 1 的目标值 0xffff << 208，所有矿池难度以此为单位换算。
+/// 与比特币难度 1 的目标值（bits 1d00ffff）相同，不是 Ravencoin 网络的最低难度（pow limit）
+pub fn unit_target() -> Uint256 {
     Uint256::from_u64(0xFFFF).unwrap() << 208
-    // 第一字节是最低位
-    // Uint256([
-    //     0xffffffffffffffffu64,
-    //     0xffffffffffffffffu64,
-    //     0xffffffffffffffffu64,
-    //     0x00000000ffffffffu64,
-    // ])
 }
 
-/// 计算目标值对应的难度
+/// 计算目标值对应的难度，超过 u64::MAX 时返回 u64::MAX
+///
+/// 整数除法向下取整：目标值稍大于 diff2target(d) 时得到 d - 1，即难度不会被高估
 pub fn target2diff(target: Uint256) -> u64 {
-    (unit_target() / target).low_u64()
+    target2diff_u128(target).try_into().unwrap_or(u64::MAX)
+}
+
+/// 计算目标值对应的难度，超过 u128::MAX 时返回 u128::MAX
+pub fn target2diff_u128(target: Uint256) -> u128 {
+    if target == Uint256::from_u64(0u64).unwrap() {
+        return u128::MAX;
+    }
+    let d = unit_target() / target;
+    let words = d.as_bytes();
+    if words[3] != 0 || words[2] != 0 {
+        return u128::MAX;
+    }
+    ((words[1] as u128) << 64) | words[0] as u128
+}
+
+/// uint256_div 允许的最大小数位数，再多也超出了 f64 的有效精度
+const MAX_DECIMAL_LEN: usize = 18;
+
+fn uint256_to_f64(n: Uint256) -> f64 {
+    n.as_bytes()
+        .iter()
+        .rev()
+        .fold(0.0, |acc, word| acc * 18446744073709551616.0 + *word as f64)
 }
 
 /// Uint256 相除得到浮点数
 fn uint256_div(divided: Uint256, divisor: Uint256, decimal_len: usize) -> Result<f64> {
     if divisor == Uint256::from_u64(0u64).unwrap() {
-        bail!("zero divisor")
+        bail!(RvnError::ZeroDivisor)
     }
-    if decimal_len > 10 {
-        bail!("decimal > 10");
+    if decimal_len > MAX_DECIMAL_LEN {
+        bail!("decimal > {}", MAX_DECIMAL_LEN);
     }
 
     let d = divided / divisor;
-    {
-        let bytes = d.as_bytes();
-        if bytes[3] != 0 || bytes[2] != 0 || bytes[1] != 0 {
-            bail!("div result too big");
-        }
-    }
-    let mut decimals: VecDeque<u64> = VecDeque::with_capacity(decimal_len + 1);
+    let mut decimals: VecDeque<u64> = VecDeque::with_capacity(decimal_len);
 
-    let mut divided = divided;
-    let mut divided_result: u64 = d.low_u64();
-    decimals.push_back(divided_result);
+    // 余数始终小于除数
+    let mut remainder = divided - divisor * d;
+    let ten = Uint256::from_u64(10).unwrap();
     for _i in 0..decimal_len {
-        divided = divided - divisor * Uint256::from_u64(divided_result).unwrap();
-        divided = divided * Uint256::from_u64(10).unwrap();
-        divided_result = (divided / divisor).low_u64() % 10;
-        decimals.push_back(divided_result);
+        remainder = remainder * ten;
+        let digit = remainder / divisor;
+        remainder = remainder - divisor * digit;
+        decimals.push_back(digit.low_u64());
     }
 
-    let mut result = decimals.pop_front().unwrap() as f64;
+    let mut result = uint256_to_f64(d);
     let mut ratio = 0.1;
     for d in decimals {
         result += d as f64 * ratio;
@@ -95,7 +165,15 @@ fn uint256_div(divided: Uint256, divisor: Uint256, decimal_len: usize) -> Result
 }
 
 /// 计算难度值对应的目标值
+///
+/// 目标值向下取整，即不比精确值更容易；由于难度 1 的目标值远大于 d^2，
+/// target2diff(diff2target(d)) 总是等于 d
 pub fn diff2target(diff: u64) -> Uint256 {
+    diff2target_u128(diff as u128)
+}
+
+/// 计算难度值对应的目标值，支持超过 u64 的难度
+pub fn diff2target_u128(diff: u128) -> Uint256 {
     if diff == 0 {
         return Uint256([
             0xffffffffffffffffu64,
@@ -105,18 +183,95 @@ pub fn diff2target(diff: u64) -> Uint256 {
         ]);
     }
 
-    unit_target() / Uint256([diff, 0, 0, 0])
+    unit_target() / Uint256([diff as u64, (diff >> 64) as u64, 0, 0])
+}
+
+/// 份额目标不小于区块目标，即份额难度不高于区块难度，满足区块目标的解总能作为份额提交
+pub fn clamp_share_target(share: Uint256, block: Uint256) -> Uint256 {
+    share.max(block)
+}
+
+/// 实际达到的值是否满足目标，值越小工作量越大
+pub fn meets_target(achieved: &Uint256, target_hex: &str) -> Result<bool> {
+    Ok(*achieved <= uint256_from_hash(target_hex)?)
+}
+
+/// 实际达到的值对应的难度
+pub fn achieved_difficulty(achieved: &Uint256) -> u64 {
+    target2diff(*achieved)
+}
+
+/// 显示序 hash hex（如矿机上报的 final hash）对应的难度
+pub fn difficulty_from_hash_hex(hash_hex: &str) -> Result<u64> {
+    Ok(achieved_difficulty(&uint256_from_hash(hash_hex)?))
+}
+
+/// 网络 bits 对应的矿池难度
+pub fn bits_to_pool_diff(bits: u32) -> u64 {
+    target2diff(bits2target(bits))
+}
+
+/// 目标值对应的工作量 2^256 / (target + 1)，与节点的 GetBlockProof 一致，
+/// 累加后用于比较各个链的总工作量；与难度不同，不以难度 1 的目标值为单位
+pub fn target_to_work(target: &Uint256) -> Uint256 {
+    let zero = Uint256::from_u64(0).unwrap();
+    let one = Uint256::from_u64(1).unwrap();
+    if *target == zero {
+        return zero;
+    }
+    if *target == !zero {
+        return one;
+    }
+    // 2^256 无法表示，改写为 (2^256 - target - 1) / (target + 1) + 1
+    (!*target / (*target + one)) + one
+}
+
+/// 网络 bits 对应的区块工作量
+pub fn header_work(bits: u32) -> Uint256 {
+    target_to_work(&bits2target(bits))
+}
+
+/// 矿池难度对应的目标值，显示序的 hex，可直接传给 create_job
+pub fn pool_diff_to_target_hex(diff: u64) -> String {
+    format_target_hex(&diff2target(diff))
 }
 
 /// 仅用于计算链上难度，不要用于性能敏感的场合
 pub fn target2diff_f64(target: Uint256) -> Result<f64> {
-    uint256_div(unit_target(), target, 10)
+    uint256_div(unit_target(), target, MAX_DECIMAL_LEN)
+}
+
+const SI_SUFFIXES: [&str; 7] = ["", "K", "M", "G", "T", "P", "E"];
+
+/// 按 1000 进位缩放到 [1, 1000) 区间，返回缩放后的值和 SI 后缀
+///
+/// 按保留两位小数后的值选择单位，999.999 为 1.00 K 而不是 1000.00
+pub(crate) fn si_scale(value: f64) -> (f64, &'static str) {
+    let mut value = value;
+    let mut idx = 0;
+    while (value.abs() * 100.0).round() >= 100_000.0 && idx < SI_SUFFIXES.len() - 1 {
+        value /= 1000.0;
+        idx += 1;
+    }
+    (value, SI_SUFFIXES[idx])
+}
+
+/// 以 K/M/G/T 等单位输出难度，保留两位小数，如 "1.23 G"
+pub fn format_difficulty(diff: f64) -> String {
+    if !diff.is_finite() {
+        return diff.to_string();
+    }
+    let (value, suffix) = si_scale(diff);
+    if suffix.is_empty() {
+        format!("{:.2}", value)
+    } else {
+        format!("{:.2} {}", value, suffix)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    pub use bitcoin::util::uint::Uint256;
 
     #[test]
     fn test_bits() {
@@ -129,6 +284,208 @@ mod test {
         assert_eq!(block_target2, block_target);
     }
 
+    #[test]
+    fn test_uint256_hash() {
+        let hash = "0000000000005ab50d0000000000000000000000000000000000000000000000";
+        let num = uint256_from_hash(hash).unwrap();
+        assert_eq!(num, bits2target(0x1a5ab50d));
+        assert_eq!(uint256_to_hash(&num), hash);
+        assert_eq!(uint256_from_hash(&format!("0x{}", hash)).unwrap(), num);
+        assert!(uint256_from_hash("5ab50d").is_err());
+        assert!(uint256_from_hash(&format!("{}00", hash)).is_err());
+    }
+
+    #[test]
+    fn test_bits_to_hex() {
+        for bits in ["1a5ab50d", "03123456", "207fffff"] {
+            assert_eq!(bits_to_hex(parse_bits(bits).unwrap()), bits);
+        }
+        assert_eq!(bits_to_hex(0x01010000), "01010000");
+    }
+
+    #[test]
+    fn test_error_kind() {
+        for bits in ["1a5ab5", "zz5ab50d"] {
+            let err = parse_bits(bits).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<RvnError>(),
+                Some(&RvnError::InvalidBits(bits.to_string()))
+            );
+        }
+        let err = uint256_div(unit_target(), Uint256::from_u64(0).unwrap(), 1).unwrap_err();
+        assert_eq!(err.downcast_ref::<RvnError>(), Some(&RvnError::ZeroDivisor));
+    }
+
+    #[test]
+    fn test_target2bits() {
+        for bits in ["1e0090f9", "1a5ab50d", "03123456"] {
+            let bits_num = parse_bits(bits).unwrap();
+            assert_eq!(target2bits(bits2target(bits_num)), bits_num);
+        }
+        // 非规范编码会被压缩为最短形式，但目标值不变
+        let bits_num = parse_bits("03000001").unwrap();
+        assert_eq!(target2bits(bits2target(bits_num)), 0x01010000);
+        assert_eq!(bits2target(0x01010000), bits2target(bits_num));
+        // 尾数最高位为 1 时需要规范化
+        let target = Uint256::from_u64(0x80).unwrap();
+        assert_eq!(target2bits(target), 0x02008000);
+        assert_eq!(bits2target(target2bits(target)), target);
+    }
+
+    #[test]
+    fn test_target2diff_u128() {
+        let target = Uint256::from_u64(0xFFFF).unwrap() << 100;
+        // unit_target / target = 2^108
+        assert_eq!(target2diff_u128(target), 1u128 << 108);
+        assert_eq!(target2diff(target), u64::MAX);
+
+        assert_eq!(target2diff_u128(unit_target()), 1);
+        assert_eq!(target2diff(unit_target()), 1);
+        assert_eq!(target2diff_u128(Uint256::from_u64(1).unwrap()), u128::MAX);
+    }
+
+    #[test]
+    fn test_target2diff_f64() {
+        let target = bits2target(parse_bits("1a5ab50d").unwrap());
+        let diff = target2diff_f64(target).unwrap();
+        assert!(diff.is_finite() && diff > 0.0);
+        assert_eq!(diff.floor() as u64, target2diff(target));
+
+        // 整数部分超过 2^64
+        let target = Uint256::from_u64(0xFFFF).unwrap() << 100;
+        let diff = target2diff_f64(target).unwrap();
+        assert_eq!(diff, 2f64.powi(108));
+
+        let diff = uint256_div(
+            Uint256::from_u64(1).unwrap(),
+            Uint256::from_u64(3).unwrap(),
+            18,
+        )
+        .unwrap();
+        assert!((diff - 1.0 / 3.0).abs() < 1e-15);
+        assert!(uint256_div(unit_target(), unit_target(), 19).is_err());
+    }
+
+    #[test]
+    fn test_diff2target() {
+        assert_eq!(diff2target(1), unit_target());
+        assert_eq!(diff2target_u128(1), unit_target());
+        assert_eq!(diff2target(0), Uint256([u64::MAX; 4]));
+
+        for diff in [1u64, 1024, 184_509, 1 << 40] {
+            assert_eq!(target2diff(diff2target(diff)), diff);
+        }
+
+        let diff = 1u128 << 100;
+        let target = diff2target_u128(diff);
+        assert_eq!(target, unit_target() >> 100);
+        assert_eq!(target2diff_u128(target), diff);
+    }
+
+    #[test]
+    fn test_diff_round_trip() {
+        for diff in [1u64, 2, 1000, 1 << 32, 1 << 48, u64::MAX] {
+            let target = diff2target(diff);
+            assert_eq!(target2diff(target), diff, "diff {}", diff);
+            // 目标值更小（更难）时难度不变，更大时向下取整
+            let one = Uint256::from_u64(1).unwrap();
+            assert_eq!(target2diff(target - one), diff, "diff {}", diff);
+            assert_eq!(target2diff(target + one), diff - 1, "diff {}", diff);
+        }
+        for diff in [1u128, 1 << 64, 1 << 100] {
+            assert_eq!(target2diff_u128(diff2target_u128(diff)), diff);
+        }
+    }
+
+    #[test]
+    fn test_format_target_hex() {
+        let target = bits2target(parse_bits("1a5ab50d").unwrap());
+        assert_eq!(
+            format_target_hex(&target),
+            "0000000000005ab50d0000000000000000000000000000000000000000000000"
+        );
+        let target = Uint256::from_u64(1).unwrap();
+        let s = format_target_hex(&target);
+        assert_eq!(s.len(), 64);
+        assert_eq!(s, format!("{}1", "0".repeat(63)));
+    }
+
+    #[test]
+    fn test_difficulty_from_hash_hex() {
+        let hash = "0000000000001a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f70819203";
+        let diff = difficulty_from_hash_hex(hash).unwrap();
+        assert_eq!(diff, target2diff(uint256_from_hash(hash).unwrap()));
+        assert!(diff > 500_000);
+        assert_eq!(
+            difficulty_from_hash_hex(
+                "0x00000000ffff0000000000000000000000000000000000000000000000000000"
+            )
+            .unwrap(),
+            1
+        );
+        assert!(difficulty_from_hash_hex("00ff").is_err());
+    }
+
+    #[test]
+    fn test_clamp_share_target() {
+        let block = bits2target(parse_bits("1a5ab50d").unwrap());
+        // 低于网络难度的份额目标保持不变
+        let share = diff2target(1);
+        assert_eq!(clamp_share_target(share, block), share);
+        // 比区块更难的份额目标取区块目标
+        assert_eq!(clamp_share_target(diff2target(u64::MAX), block), block);
+    }
+
+    #[test]
+    fn test_pool_diff() {
+        assert_eq!(
+            pool_diff_to_target_hex(1),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(
+            uint256_from_hash(&pool_diff_to_target_hex(1024)).unwrap(),
+            diff2target(1024)
+        );
+        assert_eq!(bits_to_pool_diff(parse_bits("1a5ab50d").unwrap()), 184957);
+    }
+
+    #[test]
+    fn test_uint256_bytes() {
+        let bytes = [
+            146, 149, 38, 139, 144, 227, 187, 148, 138, 108, 170, 235, 138, 113, 53, 205, 105, 90,
+            13, 49, 105, 33, 82, 87, 104, 157, 171, 146, 119, 210, 83, 156,
+        ];
+        let mix_target = uint256_from_bytes(bytes);
+        assert_eq!(uint256_to_bytes(&mix_target), bytes);
+        assert_eq!(uint256_to_bytes(&Uint256::from_u64(1).unwrap())[0], 1);
+    }
+
+    #[test]
+    fn test_meets_target() {
+        let mix_target = uint256_from_bytes([
+            146, 149, 38, 139, 144, 227, 187, 148, 138, 108, 170, 235, 138, 113, 53, 205, 105, 90,
+            13, 49, 105, 33, 82, 87, 104, 157, 171, 146, 119, 210, 83, 156,
+        ]);
+        let real_target = uint256_from_bytes([
+            85, 209, 227, 98, 133, 167, 126, 147, 55, 145, 98, 149, 234, 155, 102, 64, 122, 242,
+            245, 169, 3, 210, 8, 12, 232, 167, 168, 87, 43, 45, 232, 23,
+        ]);
+        let block_target = "00000090f9000000000000000000000000000000000000000000000000000000";
+        assert!(!meets_target(&mix_target, block_target).unwrap());
+        assert!(!meets_target(&real_target, block_target).unwrap());
+        let mix_hex = uint256_to_hash(&mix_target);
+        let real_hex = uint256_to_hash(&real_target);
+        assert!(meets_target(&real_target, &mix_hex).unwrap());
+        assert!(meets_target(&real_target, &real_hex).unwrap());
+        assert!(!meets_target(&mix_target, &real_hex).unwrap());
+        assert!(meets_target(&real_target, "zz").is_err());
+
+        // 两者都大于难度 1 的目标值
+        assert_eq!(achieved_difficulty(&real_target), 0);
+        assert_eq!(achieved_difficulty(&mix_target), 0);
+        assert_eq!(achieved_difficulty(&diff2target(451)), 451);
+    }
+
     #[test]
     fn test_diff() {
         let mix_target = uint256_from_bytes([
@@ -150,4 +507,62 @@ mod test {
         println!("diff: {}, {}", diff, diff_f64);
         assert!(mix_target > block_target);
     }
+
+    #[test]
+    fn test_format_difficulty() {
+        assert_eq!(format_difficulty(0.0), "0.00");
+        assert_eq!(format_difficulty(0.5), "0.50");
+        assert_eq!(format_difficulty(999.0), "999.00");
+        assert_eq!(format_difficulty(1000.0), "1.00 K");
+        // 四舍五入后进位到下一个单位
+        assert_eq!(format_difficulty(999.994), "999.99");
+        assert_eq!(format_difficulty(999.999), "1.00 K");
+        assert_eq!(format_difficulty(999_999.0), "1.00 M");
+        assert_eq!(format_difficulty(12_345.0), "12.35 K");
+        assert_eq!(format_difficulty(1_234_567.0), "1.23 M");
+        assert_eq!(format_difficulty(1_230_000_000.0), "1.23 G");
+        assert_eq!(format_difficulty(45.6e12), "45.60 T");
+        assert_eq!(format_difficulty(f64::INFINITY), "inf");
+
+        let diff = target2diff_f64(diff2target(131072)).unwrap();
+        assert_eq!(format_difficulty(diff), "131.07 K");
+    }
+
+    #[test]
+    fn test_parse_bits_prefix_and_case() {
+        let plain = parse_bits("1a5ab50d").unwrap();
+        assert_eq!(plain, 0x1a5ab50d);
+        assert_eq!(parse_bits("0x1a5ab50d").unwrap(), plain);
+        assert_eq!(parse_bits("1A5AB50D").unwrap(), plain);
+        assert_eq!(parse_bits("0X1A5AB50D").unwrap(), plain);
+        assert!(parse_bits("0x").is_err());
+        assert!(parse_bits("0x0x1a5ab50d").is_err());
+    }
+
+    #[test]
+    fn test_target_to_work() {
+        let zero = Uint256::from_u64(0).unwrap();
+        let one = Uint256::from_u64(1).unwrap();
+        assert_eq!(target_to_work(&zero), zero);
+        assert_eq!(target_to_work(&!zero), one);
+        // 2^224 - 1 的工作量恰为 2^32
+        let target = (one << 224) - one;
+        assert_eq!(target_to_work(&target), one << 32);
+        assert_eq!(target_to_work(&(one << 224)), (one << 32) - one);
+
+        let easy = bits2target(0x1e0090f9);
+        let hard = bits2target(0x1a5ab50d);
+        assert!(hard < easy);
+        assert!(target_to_work(&hard) > target_to_work(&easy));
+        assert!(header_work(0x1a5ab50d) > zero);
+        assert_eq!(header_work(0x1a5ab50d), target_to_work(&hard));
+    }
+
+    #[test]
+    fn test_unit_target() {
+        assert_eq!(target2diff(unit_target()), 1);
+        assert_eq!(uint256_from_hash(UNIT_TARGET_HEX).unwrap(), unit_target());
+        assert_eq!(format_target_hex(&unit_target()), UNIT_TARGET_HEX);
+        assert_eq!(bits2target(0x1d00ffff), unit_target());
+    }
 }
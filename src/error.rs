@@ -0,0 +1,14 @@
+use thiserror::Error;
+
+/// 库中可区分的错误类型，通过 anyhow::Error::downcast_ref 获取
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum RvnError {
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("invalid bits: {0}")]
+    InvalidBits(String),
+    #[error("zero divisor")]
+    ZeroDivisor,
+    #[error("template decode failed: {0}")]
+    TemplateDecode(String),
+}
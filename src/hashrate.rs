@@ -0,0 +1,200 @@
+use crate::diff::si_scale;
+use std::collections::VecDeque;
+
+/// 根据提交的份额估算算力（hash/s）
+///
+/// shares 为 (时间戳, 难度) 列表，只统计最新份额之前 window_secs 秒内的份额，
+/// 算力 = sum(difficulty) * 2^32 / window_secs
+pub fn estimate_hashrate(shares: &[(u32, u64)], window_secs: u32) -> f64 {
+    if window_secs == 0 {
+        return 0.0;
+    }
+    let latest = match shares.iter().map(|(ts, _)| *ts).max() {
+        Some(ts) => ts,
+        None => return 0.0,
+    };
+    let total_diff: f64 = shares
+        .iter()
+        .filter(|(ts, _)| latest - *ts < window_secs)
+        .map(|(_, diff)| *diff as f64)
+        .sum();
+    total_diff * 4294967296.0 / window_secs as f64
+}
+
+/// 份额难度的累加，u128 不会因大量高难度份额溢出
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DifficultyAccumulator {
+    total: u128,
+    count: u64,
+}
+
+impl DifficultyAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, diff: u64) {
+        self.total += diff as u128;
+        self.count += 1;
+    }
+
+    pub fn total(&self) -> u128 {
+        self.total
+    }
+
+    /// 累加的份额数
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// 只保留最近 window_secs 秒内份额的难度累加，用于 PPLNS 等按窗口统计的场合
+///
+/// 与 estimate_hashrate 一致，以最新份额的时间为准，早于它 window_secs 秒及以上的份额被丢弃
+#[derive(Debug, Clone, Default)]
+pub struct WindowedDifficulty {
+    window_secs: u32,
+    /// (时间戳, 难度)，按加入顺序
+    shares: VecDeque<(u32, u64)>,
+    latest: u32,
+    total: u128,
+}
+
+impl WindowedDifficulty {
+    pub fn new(window_secs: u32) -> Self {
+        Self {
+            window_secs,
+            ..Default::default()
+        }
+    }
+
+    /// 时间戳早于最新份额的份额也会加入，过期时一并丢弃
+    pub fn add(&mut self, ts: u32, diff: u64) {
+        self.latest = self.latest.max(ts);
+        self.shares.push_back((ts, diff));
+        self.total += diff as u128;
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        let latest = self.latest;
+        let window_secs = self.window_secs;
+        let mut total = self.total;
+        self.shares.retain(|(ts, diff)| {
+            let keep = latest - ts < window_secs;
+            if !keep {
+                total -= *diff as u128;
+            }
+            keep
+        });
+        self.total = total;
+    }
+
+    pub fn total(&self) -> u128 {
+        self.total
+    }
+
+    pub fn len(&self) -> usize {
+        self.shares.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shares.is_empty()
+    }
+
+    /// 按窗口内的难度估算算力，与 estimate_hashrate 的结果一致
+    pub fn hashrate(&self) -> f64 {
+        if self.window_secs == 0 {
+            return 0.0;
+        }
+        self.total as f64 * 4294967296.0 / self.window_secs as f64
+    }
+}
+
+/// 以 H/s、KH/s ... TH/s 等单位输出算力，保留两位小数，如 "1.05 TH/s"
+pub fn format_hashrate(hps: f64) -> String {
+    if !hps.is_finite() || hps <= 0.0 {
+        return "0.00 H/s".to_string();
+    }
+    let (value, suffix) = si_scale(hps);
+    format!("{:.2} {}H/s", value, suffix)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_estimate_hashrate() {
+        assert_eq!(estimate_hashrate(&[], 60), 0.0);
+        assert_eq!(estimate_hashrate(&[(1000, 1)], 0), 0.0);
+
+        // 每 10 秒一个难度 1 的份额，600 秒内共 60 个
+        let shares: Vec<_> = (0..60).map(|i| (1000 + i * 10, 1)).collect();
+        let hashrate = estimate_hashrate(&shares, 600);
+        let expect = 4294967296.0 / 10.0;
+        assert!((hashrate - expect).abs() / expect < 0.01);
+
+        // 窗口外的份额不计入
+        let hashrate = estimate_hashrate(&shares, 300);
+        assert!((hashrate - expect).abs() / expect < 0.01);
+
+        let hashrate = estimate_hashrate(&[(1000, 8)], 60);
+        assert!(hashrate > 0.0);
+    }
+
+    #[test]
+    fn test_format_hashrate() {
+        assert_eq!(format_hashrate(0.0), "0.00 H/s");
+        assert_eq!(format_hashrate(-1.0), "0.00 H/s");
+        assert_eq!(format_hashrate(f64::NAN), "0.00 H/s");
+        assert_eq!(format_hashrate(0.004), "0.00 H/s");
+        assert_eq!(format_hashrate(0.5), "0.50 H/s");
+        assert_eq!(format_hashrate(999.0), "999.00 H/s");
+        assert_eq!(format_hashrate(1000.0), "1.00 KH/s");
+        assert_eq!(format_hashrate(12_300_000.0), "12.30 MH/s");
+        assert_eq!(format_hashrate(999_990_000.0), "999.99 MH/s");
+        assert_eq!(format_hashrate(999_999_000.0), "1.00 GH/s");
+        assert_eq!(format_hashrate(1e9), "1.00 GH/s");
+        assert_eq!(format_hashrate(1.05e12), "1.05 TH/s");
+
+        let hashrate = estimate_hashrate(&[(1000, 8)], 60);
+        assert_eq!(format_hashrate(hashrate), "572.66 MH/s");
+    }
+
+    #[test]
+    fn test_difficulty_accumulator() {
+        let mut acc = DifficultyAccumulator::new();
+        for _ in 0..1000 {
+            acc.add(u64::MAX);
+        }
+        assert_eq!(acc.total(), u64::MAX as u128 * 1000);
+        assert_eq!(acc.count(), 1000);
+        acc.add(1);
+        assert_eq!(acc.total(), u64::MAX as u128 * 1000 + 1);
+    }
+
+    #[test]
+    fn test_windowed_difficulty() {
+        let mut window = WindowedDifficulty::new(600);
+        assert!(window.is_empty());
+        assert_eq!(window.hashrate(), 0.0);
+        let shares: Vec<_> = (0..120).map(|i| (1000 + i * 10, 1)).collect();
+        for (ts, diff) in &shares {
+            window.add(*ts, *diff);
+        }
+        assert_eq!(window.len(), 60);
+        assert_eq!(window.total(), 60);
+        assert_eq!(window.hashrate(), estimate_hashrate(&shares, 600));
+
+        // 大难度份额不溢出，过期后从总和中扣除
+        let mut window = WindowedDifficulty::new(10);
+        for i in 0..100 {
+            window.add(i, u64::MAX);
+        }
+        assert_eq!(window.total(), u64::MAX as u128 * 10);
+        window.add(200, 5);
+        assert_eq!(window.total(), 5);
+        assert_eq!(window.len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::block_template::header_hash_display;
+
+/// KawPow 计算用的区块头长度，比比特币多了 4 字节的高度，不含 nonce 和 mix_hash
+pub const HEADER_LEN: usize = 80;
+
+/// 区块头各字段的偏移，整数均为小端
+pub const VERSION_OFFSET: usize = 0;
+pub const PREV_OFFSET: usize = 4;
+pub const MERKLE_OFFSET: usize = 36;
+pub const TIME_OFFSET: usize = 68;
+pub const BITS_OFFSET: usize = 72;
+/// 高度位于比特币区块头中 nonce 的位置，nonce 和 mix_hash 在区块中跟在其后
+pub const HEIGHT_OFFSET: usize = 76;
+
+/// version-rolling 中矿机可以修改的版本位
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VersionMask(pub u32);
+
+impl Default for VersionMask {
+    /// BIP320 定义的通用掩码
+    fn default() -> Self {
+        Self(0x1fffe000)
+    }
+}
+
+impl VersionMask {
+    /// 用 rolled 中掩码内的位替换 base 的对应位
+    pub fn apply(&self, base: u32, rolled: u32) -> u32 {
+        (base & !self.0) | (rolled & self.0)
+    }
+
+    /// 检查矿机只修改了掩码内的位
+    pub fn validate(&self, base: u32, rolled: u32) -> Result<()> {
+        let changed = (base ^ rolled) & !self.0;
+        if changed != 0 {
+            bail!("version bits {:#010x} are not allowed to roll", changed);
+        }
+        Ok(())
+    }
+}
+
+/// Ravencoin 区块头
+///
+/// prev_hash 和 merkle_root 保存的是区块头中的字节序（与 RPC 显示的顺序相反）
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub prev_hash: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
+    pub height: u32,
+}
+
+impl Header {
+    pub fn from_bytes(data: &[u8]) -> Result<Header> {
+        if data.len() != HEADER_LEN {
+            bail!("invalid header length {}", data.len());
+        }
+        Ok(Header {
+            version: LittleEndian::read_u32(&data[VERSION_OFFSET..]),
+            prev_hash: data[PREV_OFFSET..MERKLE_OFFSET].try_into()?,
+            merkle_root: data[MERKLE_OFFSET..TIME_OFFSET].try_into()?,
+            time: LittleEndian::read_u32(&data[TIME_OFFSET..]),
+            bits: LittleEndian::read_u32(&data[BITS_OFFSET..]),
+            height: LittleEndian::read_u32(&data[HEIGHT_OFFSET..]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0; HEADER_LEN];
+        LittleEndian::write_u32(&mut data[VERSION_OFFSET..], self.version);
+        data[PREV_OFFSET..MERKLE_OFFSET].copy_from_slice(&self.prev_hash);
+        data[MERKLE_OFFSET..TIME_OFFSET].copy_from_slice(&self.merkle_root);
+        LittleEndian::write_u32(&mut data[TIME_OFFSET..], self.time);
+        LittleEndian::write_u32(&mut data[BITS_OFFSET..], self.bits);
+        LittleEndian::write_u32(&mut data[HEIGHT_OFFSET..], self.height);
+        data
+    }
+
+    pub fn with_version(&self, version: u32) -> Header {
+        Header {
+            version,
+            ..self.clone()
+        }
+    }
+
+    /// 替换 time，fixed 为 true（节点不允许修改 time）时只接受原值
+    pub fn with_time(&self, time: u32, fixed: bool) -> Result<Header> {
+        if fixed && time != self.time {
+            bail!("time is not mutable: {} != {}", time, self.time);
+        }
+        Ok(Header {
+            time,
+            ..self.clone()
+        })
+    }
+
+    /// 区块头的 dsha256，已反转为显示顺序
+    pub fn hash(&self) -> [u8; 32] {
+        header_hash_display(&self.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version_mask() {
+        let mask = VersionMask::default();
+        let base = 0x30000000;
+        let rolled = 0x30002000;
+        assert!(mask.validate(base, rolled).is_ok());
+        assert_eq!(mask.apply(base, rolled), rolled);
+        // 0x40000000 不在掩码内
+        assert!(mask.validate(base, 0x70000000).is_err());
+        assert_eq!(mask.apply(base, 0x70000000), base);
+
+        let header = Header {
+            version: base,
+            prev_hash: [1; 32],
+            merkle_root: [2; 32],
+            time: 3,
+            bits: 4,
+            height: 5,
+        };
+        let bytes = header.with_version(rolled).to_bytes();
+        assert_eq!(&bytes[0..4], &[0x00, 0x20, 0x00, 0x30]);
+        assert_eq!(&bytes[4..], &header.to_bytes()[4..]);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let header = Header {
+            version: 0x30000000,
+            prev_hash: [1; 32],
+            merkle_root: [2; 32],
+            time: 3,
+            bits: 0x1a5ab50d,
+            height: 1_219_736,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        assert_eq!(HEIGHT_OFFSET + 4, HEADER_LEN);
+        assert_eq!(&bytes[HEIGHT_OFFSET..], &1_219_736u32.to_le_bytes());
+        assert_eq!(
+            &bytes[BITS_OFFSET..HEIGHT_OFFSET],
+            &[0x0d, 0xb5, 0x5a, 0x1a]
+        );
+        assert_eq!(&bytes[PREV_OFFSET..MERKLE_OFFSET], &[1; 32]);
+        assert_eq!(Header::from_bytes(&bytes).unwrap(), header);
+        assert_eq!(Header::from_bytes(&bytes).unwrap().height, 1_219_736);
+    }
+}
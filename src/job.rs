@@ -1,8 +1,445 @@
-use crate::op_data::OpData;
+use crate::block_template::{
+    check_block_txs, nonce_in_range, serialize_block, write_block, BlockTemplate,
+};
+use crate::diff::{
+    bits2target, diff2target, format_target_hex, meets_target, parse_bits, target2diff_f64,
+    uint256_from_hash, Uint256,
+};
+use crate::header::{Header, VersionMask, HEADER_LEN};
+use crate::kawpow::{kawpow_epoch, kawpow_final_hash, kawpow_hash_with_cache, LightCache};
+#[cfg(feature = "zstd")]
+use crate::op_data::{OpData, OpReader};
+use crate::stratum::{MiningNotify, MiningSubmit};
+use crate::util::{ct_eq, decode_hash32_reversed, strip_hex_prefix};
+use anyhow::{bail, Result};
+#[cfg(feature = "zstd")]
+use byteorder::LittleEndian;
+use byteorder::{BigEndian, ByteOrder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-/// 矿机任务所需的信息
+static JOB_ID: AtomicU32 = AtomicU32::new(0);
+
+/// 生成任务 id，进程内单调递增（溢出后回绕），与模板无关
+pub fn job_id() -> u32 {
+    JOB_ID.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+}
+
+/// nonce 的字节长度，布局固定
+pub const NONCE_LEN: usize = 12;
+
+/// nonce 前 4 字节为任务 id，后 8 字节为矿机编号，均为大端序
+///
+/// 这是矿池内部用于定位任务和矿机的标识，不写入区块头；区块头中的 8 字节 nonce 见 KawpowNonce
+pub fn nonce(miner_index: u64, job_id: u32) -> String {
+    let mut data = [0; NONCE_LEN];
+    BigEndian::write_u32(&mut data[0..4], job_id);
+    BigEndian::write_u64(&mut data[4..12], miner_index);
+    hex::encode(data)
+}
+
+/// KawPow 区块头中的 8 字节 nonce，按 u64 数值保存
+///
+/// 字节序：
+/// - mining.submit 中的 nonce 为该数值的大端 hex，如 "8e5d0c0019a6f5b2"
+/// - 矿池通过 extranonce1 固定数值的高位字节（即 hex 的开头），矿机只滚动其余的低位
+/// - 写入区块时为小端序列化，与 hex 的字节顺序相反，如 b2f5a619000c5d8e
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct KawpowNonce(pub u64);
+
+impl KawpowNonce {
+    /// 由矿池固定的高位字节和矿机滚动的低位组成，rolled 不能超出剩余的位数
+    pub fn from_parts(pool_prefix: &[u8], rolled: u64) -> Result<Self> {
+        if pool_prefix.len() > 8 {
+            bail!("nonce prefix too long: {} bytes", pool_prefix.len());
+        }
+        let rolled_bits = (8 - pool_prefix.len()) * 8;
+        if rolled_bits < 64 && rolled >> rolled_bits != 0 {
+            bail!("rolled nonce {:x} exceeds {} bits", rolled, rolled_bits);
+        }
+        let mut bytes = [0; 8];
+        bytes[..pool_prefix.len()].copy_from_slice(pool_prefix);
+        Ok(Self(u64::from_be_bytes(bytes) | rolled))
+    }
+
+    /// 解析 mining.submit 中的 nonce，可带 0x 前缀
+    pub fn from_submit_hex(s: &str) -> Result<Self> {
+        let s = strip_hex_prefix(s);
+        let mut bytes = [0; 8];
+        if hex::decode_to_slice(s, &mut bytes).is_err() {
+            bail!("invalid nonce: {}", s);
+        }
+        Ok(Self(u64::from_be_bytes(bytes)))
+    }
+
+    pub fn to_submit_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+
+    /// 区块中的字节，即 nNonce64 的小端序列化
+    pub fn to_header_bytes(&self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_header_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_le_bytes(bytes))
+    }
+
+    /// 是否以矿池分配的高位字节开头，用于拒绝越界滚动的提交
+    pub fn has_prefix(&self, pool_prefix: &[u8]) -> bool {
+        pool_prefix.len() <= 8 && self.0.to_be_bytes().starts_with(pool_prefix)
+    }
+
+    /// 去掉 prefix_len 个高位字节后，矿机滚动的部分
+    pub fn rolled(&self, prefix_len: usize) -> u64 {
+        match prefix_len {
+            0 => self.0,
+            1..=7 => self.0 & (u64::MAX >> (prefix_len * 8)),
+            _ => 0,
+        }
+    }
+}
+
+/// 从 nonce 中解析任务 id，nonce 来自矿机，需要容忍任意输入
+pub fn job_id_from_nonce(nonce: &str) -> Result<u32> {
+    let nonce = hex::decode(nonce)?;
+    if nonce.len() < 4 {
+        bail!("nonce too short: {} bytes", nonce.len());
+    }
+    Ok(BigEndian::read_u32(&nonce[0..4]))
+}
+
+/// 从 nonce 中解析矿机编号
+pub fn miner_index_from_nonce(nonce: &str) -> Result<u64> {
+    let nonce = hex::decode(nonce)?;
+    if nonce.len() < NONCE_LEN {
+        bail!("nonce too short: {} bytes", nonce.len());
+    }
+    Ok(BigEndian::read_u64(&nonce[4..NONCE_LEN]))
+}
+
+/// 为矿机分配互不重叠的 miner_index，并检查重复提交
+#[derive(Debug, Clone, Default)]
+pub struct NonceAllocator {
+    next: u64,
+    /// 矿机断开后回收的编号，优先复用最小的
+    free: BTreeSet<u64>,
+    seen: HashMap<u32, HashSet<String>>,
+}
+
+impl NonceAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 分配一个当前未被占用的 miner_index
+    pub fn allocate(&mut self) -> u64 {
+        if let Some(index) = self.free.pop_first() {
+            return index;
+        }
+        let index = self.next;
+        self.next += 1;
+        index
+    }
+
+    /// 矿机断开时回收其编号
+    pub fn release(&mut self, miner_index: u64) {
+        if miner_index < self.next {
+            self.free.insert(miner_index);
+        }
+    }
+
+    /// 记录一次提交，同一任务下 nonce 已出现过时返回 true
+    pub fn is_duplicate(&mut self, job_id: u32, nonce: &str) -> bool {
+        !self.insert(job_id, nonce)
+    }
+
+    /// 记录 nonce，之前未出现过时返回 true
+    pub fn insert(&mut self, job_id: u32, nonce: &str) -> bool {
+        self.seen
+            .entry(job_id)
+            .or_default()
+            .insert(nonce.to_lowercase())
+    }
+
+    /// 同一任务下的 nonce 是否已出现过，不记录本次提交
+    pub fn contains(&self, job_id: u32, nonce: &str) -> bool {
+        self.seen
+            .get(&job_id)
+            .is_some_and(|nonces| nonces.contains(&nonce.to_lowercase()))
+    }
+
+    /// 任务过期后丢弃其提交记录
+    pub fn forget_job(&mut self, job_id: u32) {
+        self.seen.remove(&job_id);
+    }
+}
+
+/// 按 miner_index 限制提交频率的令牌桶，桶容量为每秒的上限，即允许一秒内的突发
+#[derive(Debug, Clone)]
+pub struct SubmitThrottle {
+    max_per_sec: u32,
+    /// miner_index -> (剩余令牌, 上次补充的时间戳)
+    buckets: HashMap<u64, (u32, u32)>,
+}
+
+impl SubmitThrottle {
+    pub fn new(max_per_sec: u32) -> Self {
+        Self {
+            max_per_sec,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// 消耗一个令牌，令牌不足时返回 false
+    pub fn allow(&mut self, miner_index: u64, now: u32) -> bool {
+        let (tokens, last) = self
+            .buckets
+            .entry(miner_index)
+            .or_insert((self.max_per_sec, now));
+        let refill = now.saturating_sub(*last).saturating_mul(self.max_per_sec);
+        *tokens = tokens.saturating_add(refill).min(self.max_per_sec);
+        *last = (*last).max(now);
+        if *tokens == 0 {
+            return false;
+        }
+        *tokens -= 1;
+        true
+    }
+
+    /// 矿机断开时丢弃其状态
+    pub fn forget(&mut self, miner_index: u64) {
+        self.buckets.remove(&miner_index);
+    }
+}
+
+/// 任务相对当前模板的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// 当前模板的任务
+    Current,
+    /// 模板切换后仍在宽限期内的旧任务
+    Stale,
+    /// 超出宽限期或从未下发过的任务
+    Unknown,
+}
+
+/// 记录当前任务 id，模板切换后旧任务在 grace_secs 秒内的提交记为 Stale
+#[derive(Debug, Clone)]
+pub struct JobTracker {
+    grace_secs: u32,
+    current: Option<u32>,
+    /// 被取代的任务 id 与被取代的时间戳，按时间先后排列
+    superseded: VecDeque<(u32, u32)>,
+}
+
+impl JobTracker {
+    pub fn new(grace_secs: u32) -> Self {
+        Self {
+            grace_secs,
+            current: None,
+            superseded: VecDeque::new(),
+        }
+    }
+
+    pub fn current(&self) -> Option<u32> {
+        self.current
+    }
+
+    /// 模板切换，job_id 成为当前任务，之前的任务从 now 开始进入宽限期
+    pub fn advance(&mut self, job_id: u32, now: u32) {
+        if self.current == Some(job_id) {
+            return;
+        }
+        if let Some(prev) = self.current.replace(job_id) {
+            self.superseded.push_back((prev, now));
+        }
+        self.superseded.retain(|(id, _)| *id != job_id);
+        self.prune(now);
+    }
+
+    /// 丢弃超出宽限期的旧任务
+    pub fn prune(&mut self, now: u32) {
+        while let Some(&(_, at)) = self.superseded.front() {
+            if now.saturating_sub(at) <= self.grace_secs {
+                break;
+            }
+            self.superseded.pop_front();
+        }
+    }
+
+    pub fn grade(&self, job_id: u32, now: u32) -> JobStatus {
+        if self.current == Some(job_id) {
+            return JobStatus::Current;
+        }
+        match self.superseded.iter().find(|(id, _)| *id == job_id) {
+            Some(&(_, at)) if now.saturating_sub(at) <= self.grace_secs => JobStatus::Stale,
+            _ => JobStatus::Unknown,
+        }
+    }
+}
+
+/// miner_index 与矿工名的对应关系，用于把份额归属到具体的矿工
+///
+/// 同一个矿工名可能有多个连接，每个连接有各自的 miner_index
+#[derive(Debug, Clone, Default)]
+pub struct WorkerRegistry {
+    by_index: HashMap<u64, String>,
+    by_name: HashMap<String, BTreeSet<u64>>,
+}
+
+impl WorkerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 矿机 authorize 后登记，miner_index 已登记时替换原来的矿工名
+    pub fn register(&mut self, miner_index: u64, worker: &str) {
+        self.unregister(miner_index);
+        self.by_index.insert(miner_index, worker.to_string());
+        self.by_name
+            .entry(worker.to_string())
+            .or_default()
+            .insert(miner_index);
+    }
+
+    /// 矿机断开时注销，返回原来的矿工名
+    pub fn unregister(&mut self, miner_index: u64) -> Option<String> {
+        let worker = self.by_index.remove(&miner_index)?;
+        if let Some(indexes) = self.by_name.get_mut(&worker) {
+            indexes.remove(&miner_index);
+            if indexes.is_empty() {
+                self.by_name.remove(&worker);
+            }
+        }
+        Some(worker)
+    }
+
+    pub fn worker(&self, miner_index: u64) -> Option<&str> {
+        self.by_index.get(&miner_index).map(String::as_str)
+    }
+
+    /// 矿工名下所有连接的 miner_index，从小到大
+    pub fn miner_indexes(&self, worker: &str) -> Vec<u64> {
+        self.by_name
+            .get(worker)
+            .map(|indexes| indexes.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// 由提交的 nonce 找到矿工名，nonce 格式错误时报错，未登记时返回 None
+    pub fn worker_from_nonce(&self, nonce: &str) -> Result<Option<&str>> {
+        Ok(self.worker(miner_index_from_nonce(nonce)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_index.is_empty()
+    }
+}
+
+/// TemplateCache 的查找结果
 #[derive(Debug, Clone)]
+pub enum TemplateLookup {
+    Found(Arc<BlockTemplate>),
+    /// 曾经缓存过但已被淘汰，通常是迟到的份额
+    Evicted,
+    Unknown,
+}
+
+/// 按任务 id 缓存最近的模板，超过容量时淘汰最久未访问的
+#[derive(Debug, Clone)]
+pub struct TemplateCache {
+    capacity: usize,
+    tick: u64,
+    /// job_id -> (模板, 最近访问的 tick)
+    entries: HashMap<u32, (Arc<BlockTemplate>, u64)>,
+    current: Option<u32>,
+    /// 最近被淘汰的任务 id，最多保留 capacity 个
+    evicted: VecDeque<u32>,
+}
+
+impl TemplateCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            current: None,
+            evicted: VecDeque::new(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// 插入的模板成为 current
+    pub fn insert(&mut self, job_id: u32, template: Arc<BlockTemplate>) {
+        let tick = self.next_tick();
+        self.entries.insert(job_id, (template, tick));
+        self.evicted.retain(|id| *id != job_id);
+        self.current = Some(job_id);
+        while self.entries.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, tick))| *tick)
+                .map(|(id, _)| *id)
+                .unwrap();
+            self.entries.remove(&oldest);
+            self.evicted.push_back(oldest);
+            if self.evicted.len() > self.capacity {
+                self.evicted.pop_front();
+            }
+        }
+    }
+
+    pub fn get(&mut self, job_id: u32) -> Option<Arc<BlockTemplate>> {
+        let tick = self.next_tick();
+        self.entries.get_mut(&job_id).map(|(template, last)| {
+            *last = tick;
+            template.clone()
+        })
+    }
+
+    /// 同 get，但区分已淘汰和从未出现过的任务
+    pub fn lookup(&mut self, job_id: u32) -> TemplateLookup {
+        match self.get(job_id) {
+            Some(template) => TemplateLookup::Found(template),
+            None if self.evicted.contains(&job_id) => TemplateLookup::Evicted,
+            None => TemplateLookup::Unknown,
+        }
+    }
+
+    /// 最近插入的模板，被淘汰后为 None
+    pub fn current(&self) -> Option<Arc<BlockTemplate>> {
+        self.current
+            .and_then(|id| self.entries.get(&id))
+            .map(|(template, _)| template.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// 矿机任务所需的信息，字节数组字段序列化为 hex 字符串
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct JobInfo {
+    #[serde(with = "hex::serde")]
     pub header_hash: [u8; 32],
     pub seed_hash: String,
     pub share_target_hex: String,
@@ -10,36 +447,1457 @@ pub struct JobInfo {
     pub height: u32,
     pub block_bits_hex: String,
     pub refresh: bool,
+    #[serde(with = "hex::serde")]
     pub header: Vec<u8>,
     pub external_txs: Vec<String>,
+    #[serde(with = "hex::serde")]
     pub coinbase_tx: Vec<u8>,
     pub timestamp: u32,
+    /// 模板的 mutable 中没有 time，不能滚动 ntime
+    #[serde(default)]
+    pub fixed_time: bool,
+}
+
+/// 从上游矿池的 mining.notify 中恢复的任务字段
+///
+/// notify 不含区块头、coinbase 和其余交易，只能用于转发任务和按目标值校验份额，不能组装区块
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PartialJob {
+    pub job_id: String,
+    pub header_hash: [u8; 32],
+    pub seed_hash: String,
+    pub share_target_hex: String,
+    pub refresh: bool,
+    pub height: u32,
+    /// 省略 bits 的 notify 没有该字段
+    pub block_bits_hex: Option<String>,
+    /// 由 bits 还原的区块目标
+    pub block_target_hex: Option<String>,
+}
+
+/// zstd 的压缩等级，持久化时更看重速度
+#[cfg(feature = "zstd")]
+const JOB_ZSTD_LEVEL: i32 = 3;
+
+/// 压缩 JobInfo 用于持久化：字段按二进制写入，external_txs 先解码 hex，再整体用 zstd 压缩
+#[cfg(feature = "zstd")]
+pub fn compress_job(job: &JobInfo) -> Result<Vec<u8>> {
+    let push_bytes =
+        |data: OpData, bytes: &[u8]| data.var_push_num(bytes.len() as u64).push_slice(bytes);
+    let mut data = OpData::default().push_slice(&job.header_hash);
+    data = push_bytes(data, job.seed_hash.as_bytes());
+    data = push_bytes(data, job.share_target_hex.as_bytes());
+    data = push_bytes(data, job.block_target_hex.as_bytes());
+    data = data.push_u32(job.height);
+    data = push_bytes(data, job.block_bits_hex.as_bytes());
+    data = data.push_u8(job.refresh as u8);
+    data = push_bytes(data, &job.header);
+    data = push_bytes(data, &job.coinbase_tx);
+    data = data
+        .push_u32(job.timestamp)
+        .push_u8(job.fixed_time as u8)
+        .var_push_num(job.external_txs.len() as u64);
+    for (i, tx) in job.external_txs.iter().enumerate() {
+        let raw = match hex::decode(tx) {
+            Ok(raw) => raw,
+            Err(e) => bail!("external tx {} is not valid hex: {}", i, e),
+        };
+        data = push_bytes(data, &raw);
+    }
+    Ok(zstd::encode_all(data.as_slice(), JOB_ZSTD_LEVEL)?)
+}
+
+/// compress_job 的逆运算
+#[cfg(feature = "zstd")]
+pub fn decompress_job(compressed: &[u8]) -> Result<JobInfo> {
+    let raw = zstd::decode_all(compressed)?;
+    let mut reader = OpReader::new(&raw);
+    let read_bytes = |reader: &mut OpReader<'_>| -> Result<Vec<u8>> {
+        let len = reader.read_var_len()?;
+        Ok(reader.take(len)?.to_vec())
+    };
+    let read_string = |reader: &mut OpReader<'_>| -> Result<String> {
+        Ok(String::from_utf8(read_bytes(reader)?)?)
+    };
+    let read_u32 =
+        |reader: &mut OpReader<'_>| -> Result<u32> { Ok(LittleEndian::read_u32(reader.take(4)?)) };
+    let header_hash = reader.take(32)?.try_into()?;
+    let seed_hash = read_string(&mut reader)?;
+    let share_target_hex = read_string(&mut reader)?;
+    let block_target_hex = read_string(&mut reader)?;
+    let height = read_u32(&mut reader)?;
+    let block_bits_hex = read_string(&mut reader)?;
+    let refresh = reader.read_u8()? != 0;
+    let header = read_bytes(&mut reader)?;
+    let coinbase_tx = read_bytes(&mut reader)?;
+    let timestamp = read_u32(&mut reader)?;
+    let fixed_time = reader.read_u8()? != 0;
+    let count = reader.read_var_len()?;
+    let mut external_txs = Vec::new();
+    for _ in 0..count {
+        external_txs.push(hex::encode(read_bytes(&mut reader)?));
+    }
+    if reader.remaining() != 0 {
+        bail!("{} trailing bytes after job", reader.remaining());
+    }
+    Ok(JobInfo {
+        header_hash,
+        seed_hash,
+        share_target_hex,
+        block_target_hex,
+        height,
+        block_bits_hex,
+        refresh,
+        header,
+        external_txs,
+        coinbase_tx,
+        timestamp,
+        fixed_time,
+    })
+}
+
+/// 份额的判定结果
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ShareOutcome {
+    /// 满足矿池的份额目标
+    pub valid_share: bool,
+    /// 满足区块目标，可以提交区块
+    pub is_block: bool,
+}
+
+/// 带矿池最低难度的份额判定结果
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ShareGrade {
+    /// 满足区块目标，不论最低难度都需要提交
+    Block,
+    /// 满足份额目标
+    Valid,
+    /// 低于矿池的最低难度，直接拒绝，不计入统计
+    TooLow,
+    /// 达到最低难度但不满足该矿机的份额目标
+    AboveTarget,
+}
+
+impl ShareOutcome {
+    /// min_difficulty 为矿池的最低难度，在份额目标之前检查
+    pub fn grade(&self, achieved: &Uint256, min_difficulty: u64) -> ShareGrade {
+        if self.is_block {
+            ShareGrade::Block
+        } else if *achieved > diff2target(min_difficulty) {
+            ShareGrade::TooLow
+        } else if self.valid_share {
+            ShareGrade::Valid
+        } else {
+            ShareGrade::AboveTarget
+        }
+    }
 }
 
 impl JobInfo {
+    /// 解析 mining.notify，兼容省略 bits 以及 hex 字段带 0x 前缀的写法
+    pub fn from_notify(json: &str) -> Result<PartialJob> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        if value["method"] != "mining.notify" {
+            bail!(
+                "unexpected method {}, expect mining.notify",
+                value["method"]
+            );
+        }
+        let params = match value["params"].as_array() {
+            Some(params) if params.len() == 6 || params.len() == 7 => params,
+            _ => bail!("invalid mining.notify params: {}", value["params"]),
+        };
+        let hex_param = |index: usize, name: &str| -> Result<String> {
+            let s = match params[index].as_str() {
+                Some(s) => strip_hex_prefix(s).to_lowercase(),
+                None => bail!("invalid {}: {}", name, params[index]),
+            };
+            let len = if name == "bits" { 8 } else { 64 };
+            if s.len() != len || hex::decode(&s).is_err() {
+                bail!("invalid {}: {}", name, s);
+            }
+            Ok(s)
+        };
+        let job_id = match params[0].as_str() {
+            Some(job_id) => job_id.to_string(),
+            None => bail!("invalid job_id: {}", params[0]),
+        };
+        let mut header_hash = [0; 32];
+        hex::decode_to_slice(hex_param(1, "header_hash")?, &mut header_hash)?;
+        let refresh = match params[4].as_bool() {
+            Some(refresh) => refresh,
+            None => bail!("invalid refresh: {}", params[4]),
+        };
+        let height = match params[5].as_u64().map(u32::try_from) {
+            Some(Ok(height)) => height,
+            _ => bail!("invalid height: {}", params[5]),
+        };
+        let block_bits_hex = match params.len() {
+            7 => Some(hex_param(6, "bits")?),
+            _ => None,
+        };
+        let block_target_hex = match &block_bits_hex {
+            Some(bits) => Some(format_target_hex(&bits2target(parse_bits(bits)?))),
+            None => None,
+        };
+        Ok(PartialJob {
+            job_id,
+            header_hash,
+            seed_hash: hex_param(2, "seed_hash")?,
+            share_target_hex: hex_param(3, "share_target")?,
+            refresh,
+            height,
+            block_bits_hex,
+            block_target_hex,
+        })
+    }
+
+    /// 同 classify，并拒绝低于矿池最低难度 min_difficulty 的份额
+    pub fn grade_share(&self, achieved: Uint256, min_difficulty: u64) -> Result<ShareGrade> {
+        Ok(self.classify(achieved)?.grade(&achieved, min_difficulty))
+    }
+
+    /// 比较实际达到的目标值与份额目标、区块目标，值越小代表工作量越大
+    pub fn classify(&self, achieved: Uint256) -> Result<ShareOutcome> {
+        Ok(ShareOutcome {
+            valid_share: meets_target(&achieved, &self.share_target_hex)?,
+            is_block: meets_target(&achieved, &self.block_target_hex)?,
+        })
+    }
+
+    /// 检查下发给矿机的字段格式：目标值和 seed_hash 为 64 位 hex，区块头为 HEADER_LEN 字节
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("share_target_hex", &self.share_target_hex),
+            ("block_target_hex", &self.block_target_hex),
+            ("seed_hash", &self.seed_hash),
+        ] {
+            if value.len() != 64 || hex::decode(value).is_err() {
+                bail!("invalid {}: {}", name, value);
+            }
+        }
+        if self.header.len() != HEADER_LEN {
+            bail!("invalid header length {}", self.header.len());
+        }
+        Ok(())
+    }
+
+    /// 只替换份额目标和 refresh，用于难度变化时重发任务，其余字段原样复制
+    pub fn with_share_target(&self, target_hex: String, refresh: bool) -> JobInfo {
+        let mut job = self.clone();
+        job.set_share_target(target_hex, refresh);
+        job
+    }
+
+    /// 原地替换份额目标和 refresh，不复制交易数据
+    pub fn set_share_target(&mut self, target_hex: String, refresh: bool) {
+        self.share_target_hex = target_hex;
+        self.refresh = refresh;
+    }
+
+    /// 实际难度是份额目标难度的倍数，用于日志，如 "12.5x"
+    pub fn share_multiplier(&self, achieved: &Uint256) -> Result<f64> {
+        let share_diff = target2diff_f64(uint256_from_hash(&self.share_target_hex)?)?;
+        Ok(target2diff_f64(*achieved)? / share_diff)
+    }
+
+    /// 检查矿机提交的 nonce 在节点的 noncerange 内，在计算 KawPow 之前拒绝格式错误的提交
+    ///
+    /// nonce_hex 为 mining.submit 中 8 字节的 KawPow nonce（大端 hex，可带 0x 前缀），
+    /// range 作用于其高 4 字节，与 BlockTemplateInfo::validate_nonce 一致。
+    /// 注意这里不是 nonce() 生成的 12 字节任务标识，后者不写入区块头
+    pub fn validate_nonce_in_range(&self, nonce_hex: &str, range: (u32, u32)) -> Result<()> {
+        let nonce = KawpowNonce::from_submit_hex(nonce_hex)?.0;
+        if !nonce_in_range(nonce, range) {
+            bail!(
+                "nonce {} out of range {:08x}{:08x}",
+                nonce_hex,
+                range.0,
+                range.1
+            );
+        }
+        Ok(())
+    }
+
+    /// 解析 header 中的各字段，用于在不持有模板的进程中校验份额
+    pub fn parse_header(&self) -> Result<Header> {
+        Header::from_bytes(&self.header)
+    }
+
+    fn header_at(&self, ntime: u32) -> Result<Header> {
+        self.parse_header()?.with_time(ntime, self.fixed_time)
+    }
+
+    /// 用矿机滚动后的 ntime 重写区块头，ntime 是否合法（不小于 mintime）由调用方检查，
+    /// 节点不允许修改 time 时 ntime 与原值不同会报错
+    pub fn header_with_time(&self, ntime: u32) -> Result<Vec<u8>> {
+        Ok(self.header_at(ntime)?.to_bytes())
+    }
+
+    pub fn header_hash_with_time(&self, ntime: u32) -> Result<[u8; 32]> {
+        Ok(self.header_at(ntime)?.hash())
+    }
+
+    /// 找到的区块在浏览器中显示的哈希（显示序），由 ntime 重写后的 header_hash、nonce 和 mix_hash 计算，
+    /// KawPow 区块的哈希不是完整区块头的 dsha256；mix_hash 与 kawpow_hash 的输出同为显示序
+    pub fn block_hash(&self, ntime: u32, nonce: u64, mix_hash: &[u8; 32]) -> Result<[u8; 32]> {
+        let header_hash = self.header_hash_with_time(ntime)?;
+        Ok(kawpow_final_hash(&header_hash, nonce, mix_hash))
+    }
+
+    /// 用矿机滚动后的 version 重写区块头，只允许修改 mask 内的位
+    pub fn header_with_version(&self, version: u32, mask: VersionMask) -> Result<Vec<u8>> {
+        let header = Header::from_bytes(&self.header)?;
+        mask.validate(header.version, version)?;
+        Ok(header.with_version(version).to_bytes())
+    }
+
     pub fn to_resp_str(&self, job_id: &str) -> String {
-        format!(
-            "{{\"id\":null,\"method\":\"mining.notify\",\"params\":[\"{}\",\"{}\",\"{}\",\"{}\",{},{},\"{}\"]}}",
+        notify_str(
             job_id,
-            hex::encode(self.header_hash),
-            self.seed_hash,
-            self.share_target_hex,
+            &self.header_hash,
+            &self.seed_hash,
+            &self.share_target_hex,
             self.refresh,
             self.height,
-            self.block_bits_hex,
+            &self.block_bits_hex,
         )
     }
 
     pub fn build_block(&self, nonce: &str, mix_hash: &str) -> String {
-        let op_data = OpData::default().var_push_num(self.external_txs.len() as u64 + 1);
-        format!(
-            "{}{}{}{}{}{}",
-            hex::encode(&self.header),
+        serialize_block(
+            &self.header,
+            nonce,
+            mix_hash,
+            &self.coinbase_tx,
+            &self.external_txs,
+        )
+    }
+
+    /// 与 build_block 相同，但先检查每笔交易的完整性，保证区块中的交易数为 external_txs.len() + 1
+    pub fn try_build_block(&self, nonce: &str, mix_hash: &str) -> Result<String> {
+        check_block_txs(&self.coinbase_tx, &self.external_txs)?;
+        Ok(self.build_block(nonce, mix_hash))
+    }
+
+    /// 与 build_block 相同，但直接写入 w 而不生成完整的字符串
+    pub fn write_block<W: Write>(&self, w: &mut W, nonce: &str, mix_hash: &str) -> io::Result<()> {
+        write_block(
+            w,
+            &self.header,
             nonce,
             mix_hash,
-            hex::encode(op_data.as_slice()),
-            hex::encode(&self.coinbase_tx),
-            self.external_txs.join(",")
+            &self.coinbase_tx,
+            &self.external_txs,
         )
     }
 }
+
+/// 与 JobInfo 相同，但共享模板数据而不复制交易，适合同一模板下发多种难度的任务
+#[derive(Debug, Clone)]
+pub struct SharedJob {
+    pub template: Arc<BlockTemplate>,
+    pub share_target_hex: String,
+    pub refresh: bool,
+}
+
+impl SharedJob {
+    pub fn new(template: Arc<BlockTemplate>, share_target_hex: String, refresh: bool) -> Self {
+        Self {
+            template,
+            share_target_hex,
+            refresh,
+        }
+    }
+
+    pub fn classify(&self, achieved: Uint256) -> Result<ShareOutcome> {
+        Ok(ShareOutcome {
+            valid_share: meets_target(&achieved, &self.share_target_hex)?,
+            is_block: meets_target(&achieved, &self.template.target_hex)?,
+        })
+    }
+
+    pub fn grade_share(&self, achieved: Uint256, min_difficulty: u64) -> Result<ShareGrade> {
+        Ok(self.classify(achieved)?.grade(&achieved, min_difficulty))
+    }
+
+    pub fn to_resp_str(&self, job_id: &str) -> String {
+        notify_str(
+            job_id,
+            &self.template.header_hash,
+            &hex::encode(self.template.seed_hash),
+            &self.share_target_hex,
+            self.refresh,
+            self.template.height,
+            &self.template.bits_hex,
+        )
+    }
+
+    pub fn build_block(&self, nonce: &str, mix_hash: &str) -> String {
+        serialize_block(
+            &self.template.header,
+            nonce,
+            mix_hash,
+            &self.template.coinbase_tx,
+            &self.template.external_txs,
+        )
+    }
+
+    pub fn write_block<W: Write>(&self, w: &mut W, nonce: &str, mix_hash: &str) -> io::Result<()> {
+        write_block(
+            w,
+            &self.template.header,
+            nonce,
+            mix_hash,
+            &self.template.coinbase_tx,
+            &self.template.external_txs,
+        )
+    }
+}
+
+/// 持有一个 epoch 的 light cache，校验份额时复用，避免每个份额重新生成
+#[derive(Debug, Clone)]
+pub struct ShareVerifier {
+    cache: LightCache,
+}
+
+impl ShareVerifier {
+    /// 生成 epoch 的 light cache，耗时较长，应在 epoch 切换时生成一次
+    pub fn new(epoch: u32) -> Self {
+        Self::with_cache(LightCache::new(epoch))
+    }
+
+    pub fn with_cache(cache: LightCache) -> Self {
+        Self { cache }
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.cache.epoch()
+    }
+
+    /// 校验提交的 header_hash 和 mix_hash，返回 KawPow 的结果，是否满足目标由 JobInfo::classify 判断
+    pub fn verify(&self, job: &JobInfo, submit: &MiningSubmit) -> Result<Uint256> {
+        let epoch = kawpow_epoch(job.height);
+        if epoch != self.epoch() {
+            bail!("job epoch {} != verifier epoch {}", epoch, self.epoch());
+        }
+        let mut header_hash = [0; 32];
+        hex::decode_to_slice(&submit.header_hash, &mut header_hash)?;
+        if !ct_eq(&header_hash, &job.header_hash) {
+            bail!("header_hash mismatch: {}", submit.header_hash);
+        }
+        let nonce = KawpowNonce::from_submit_hex(&submit.nonce)?;
+        let mut mix_hash = [0; 32];
+        hex::decode_to_slice(&submit.mix_hash, &mut mix_hash)?;
+
+        let (expected_mix, final_hash) =
+            kawpow_hash_with_cache(&self.cache, &job.header_hash, job.height, nonce.0);
+        if !ct_eq(&mix_hash, &expected_mix) {
+            bail!("mix_hash mismatch: {}", submit.mix_hash);
+        }
+        Ok(Uint256::from_be_slice(&final_hash)?)
+    }
+
+    /// 批量校验，结果与输入一一对应；开启 rayon 时并行计算
+    pub fn verify_batch(
+        &self,
+        jobs_and_submits: &[(JobInfo, MiningSubmit)],
+    ) -> Vec<Result<Uint256>> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            jobs_and_submits
+                .par_iter()
+                .map(|(job, submit)| self.verify(job, submit))
+                .collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        jobs_and_submits
+            .iter()
+            .map(|(job, submit)| self.verify(job, submit))
+            .collect()
+    }
+}
+
+/// mining.submit 的处理结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareVerdict {
+    /// 满足份额目标，diff 为实际达到的难度
+    Accepted { diff: f64 },
+    /// 满足区块目标，hex 为可直接 submitblock 的区块
+    Block { hex: String },
+    /// 同一任务下的 nonce 已提交过
+    Duplicate,
+    /// 已被取代但仍在宽限期内的任务，工作量有效但不计入份额；满足区块目标时仍为 Block
+    Stale,
+    /// job_id 格式错误、超出宽限期、从未下发过，或传入的任务与 job_id 不对应
+    UnknownJob,
+    /// KawPow 结果不满足份额目标
+    LowDifficulty,
+    /// nonce 越界、格式错误、header_hash 或 mix_hash 不一致
+    InvalidPow,
+}
+
+/// 串联 nonce 范围检查、重复检查、KawPow 校验和份额判定
+///
+/// job_id 为 mining.notify 中的十六进制任务 id，模板切换时需调用 advance
+#[derive(Debug, Clone)]
+pub struct ShareProcessor {
+    /// epoch -> light cache，只保留当前和上一个 epoch
+    verifiers: HashMap<u32, ShareVerifier>,
+    tracker: JobTracker,
+    /// job_id -> 下发时的 header_hash，用于确认传入的任务与 job_id 对应
+    headers: HashMap<u32, [u8; 32]>,
+    seen: NonceAllocator,
+    nonce_range: (u32, u32),
+}
+
+impl ShareProcessor {
+    /// grace_secs 为旧任务的宽限期，期间的有效提交记为 Stale
+    pub fn new(verifier: ShareVerifier, grace_secs: u32) -> Self {
+        Self {
+            verifiers: HashMap::from([(verifier.epoch(), verifier)]),
+            tracker: JobTracker::new(grace_secs),
+            headers: HashMap::new(),
+            seen: NonceAllocator::new(),
+            nonce_range: (0, u32::MAX),
+        }
+    }
+
+    /// 节点的 noncerange，默认不限制
+    pub fn with_nonce_range(mut self, range: (u32, u32)) -> Self {
+        self.set_nonce_range(range);
+        self
+    }
+
+    pub fn set_nonce_range(&mut self, range: (u32, u32)) {
+        self.nonce_range = range;
+    }
+
+    pub fn nonce_range(&self) -> (u32, u32) {
+        self.nonce_range
+    }
+
+    /// 已有 light cache 的 epoch，从小到大
+    pub fn epochs(&self) -> Vec<u32> {
+        let mut epochs: Vec<u32> = self.verifiers.keys().copied().collect();
+        epochs.sort_unstable();
+        epochs
+    }
+
+    /// 加入在其他线程预先生成的 light cache，只保留最新的两个 epoch
+    pub fn add_verifier(&mut self, verifier: ShareVerifier) {
+        self.verifiers.insert(verifier.epoch(), verifier);
+        if let Some(&latest) = self.verifiers.keys().max() {
+            self.verifiers.retain(|epoch, _| *epoch + 1 >= latest);
+        }
+    }
+
+    /// 模板切换，job 为以 job_id 下发的新任务，同时丢弃超出宽限期的任务的记录
+    ///
+    /// 注意：job 所在 epoch 没有 light cache 时会在调用线程上同步生成，占用数十 MB 内存、耗时数秒；
+    /// epoch 切换前应在其他线程用 ShareVerifier::new 生成并通过 add_verifier 加入
+    pub fn advance(&mut self, job_id: u32, job: &JobInfo, now: u32) {
+        let epoch = kawpow_epoch(job.height);
+        if !self.verifiers.contains_key(&epoch) {
+            self.add_verifier(ShareVerifier::new(epoch));
+        }
+        self.tracker.advance(job_id, now);
+        self.headers.insert(job_id, job.header_hash);
+        let tracker = &self.tracker;
+        self.headers
+            .retain(|id, _| tracker.grade(*id, now) != JobStatus::Unknown);
+        self.seen
+            .seen
+            .retain(|id, _| tracker.grade(*id, now) != JobStatus::Unknown);
+    }
+
+    /// 该任务下的 nonce 是否已被记录
+    pub fn is_seen(&self, job_id: u32, nonce: &str) -> bool {
+        self.seen.contains(job_id, nonce)
+    }
+
+    pub fn process(&mut self, submit: &MiningSubmit, job: &JobInfo) -> ShareVerdict {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        self.process_at(submit, job, now)
+    }
+
+    /// 同 process，now 为当前时间戳
+    pub fn process_at(&mut self, submit: &MiningSubmit, job: &JobInfo, now: u32) -> ShareVerdict {
+        let job_id = match u32::from_str_radix(&submit.job_id, 16) {
+            Ok(job_id) => job_id,
+            Err(_) => return ShareVerdict::UnknownJob,
+        };
+        let status = self.tracker.grade(job_id, now);
+        if status == JobStatus::Unknown {
+            return ShareVerdict::UnknownJob;
+        }
+        // 调用方传错任务时不能按错误的区块头校验
+        match self.headers.get(&job_id) {
+            Some(header_hash) if ct_eq(header_hash, &job.header_hash) => {}
+            _ => return ShareVerdict::UnknownJob,
+        }
+        // 未经 advance 准备 light cache 的 epoch 不在此处生成
+        let verifier = match self.verifiers.get(&kawpow_epoch(job.height)) {
+            Some(verifier) => verifier,
+            None => return ShareVerdict::UnknownJob,
+        };
+        if job
+            .validate_nonce_in_range(&submit.nonce, self.nonce_range)
+            .is_err()
+        {
+            return ShareVerdict::InvalidPow;
+        }
+        if self.seen.contains(job_id, &submit.nonce) {
+            return ShareVerdict::Duplicate;
+        }
+        let achieved = match verifier.verify(job, submit) {
+            Ok(achieved) => achieved,
+            Err(_) => return ShareVerdict::InvalidPow,
+        };
+        // 校验通过后才记录，mix_hash 错误的提交修正后仍可重新提交
+        self.seen.insert(job_id, &submit.nonce);
+
+        let outcome = match job.classify(achieved) {
+            Ok(outcome) => outcome,
+            Err(_) => return ShareVerdict::InvalidPow,
+        };
+        // 旧任务与当前任务的 prev_hash 可能相同，满足区块目标时仍然提交，由节点判断
+        if outcome.is_block {
+            return match block_hex(job, submit) {
+                Ok(hex) => ShareVerdict::Block { hex },
+                Err(_) => ShareVerdict::InvalidPow,
+            };
+        }
+        if !outcome.valid_share {
+            return ShareVerdict::LowDifficulty;
+        }
+        if status == JobStatus::Stale {
+            return ShareVerdict::Stale;
+        }
+        match target2diff_f64(achieved) {
+            Ok(diff) => ShareVerdict::Accepted { diff },
+            Err(_) => ShareVerdict::InvalidPow,
+        }
+    }
+}
+
+/// submit 中的 nonce 和 mix_hash 为大端/显示序，写入区块时转为区块中的字节序
+fn block_hex(job: &JobInfo, submit: &MiningSubmit) -> Result<String> {
+    let nonce = KawpowNonce::from_submit_hex(&submit.nonce)?;
+    let mix_hash = decode_hash32_reversed(&submit.mix_hash)?;
+    Ok(job.build_block(
+        &hex::encode(nonce.to_header_bytes()),
+        &hex::encode(mix_hash),
+    ))
+}
+
+fn notify_str(
+    job_id: &str,
+    header_hash: &[u8; 32],
+    seed_hash: &str,
+    share_target_hex: &str,
+    refresh: bool,
+    height: u32,
+    bits_hex: &str,
+) -> String {
+    let notify = MiningNotify {
+        id: None,
+        method: "mining.notify",
+        params: (
+            job_id,
+            hex::encode(header_hash),
+            seed_hash,
+            share_target_hex,
+            refresh,
+            height,
+            bits_hex,
+        ),
+    };
+    serde_json::to_string(&notify).expect("serialize mining.notify")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consts::KAWPOW_EPOCH_LENGTH;
+    use crate::diff::uint256_to_hash;
+    use crate::header::{BITS_OFFSET, TIME_OFFSET};
+    use crate::stratum::set_target_notify;
+    use crate::util::{hex_le_to_be, reverse_hash};
+
+    fn job() -> JobInfo {
+        JobInfo {
+            header_hash: [0; 32],
+            seed_hash: hex::encode([0; 32]),
+            share_target_hex: "00000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+                .to_string(),
+            block_target_hex: "0000000000005ab50d0000000000000000000000000000000000000000000000"
+                .to_string(),
+            height: 2491604,
+            block_bits_hex: "1a5ab50d".to_string(),
+            refresh: true,
+            header: vec![0; 80],
+            external_txs: vec![],
+            coinbase_tx: vec![],
+            timestamp: 1665556235,
+            fixed_time: false,
+        }
+    }
+
+    #[test]
+    fn test_nonce_allocator() {
+        let mut allocator = NonceAllocator::new();
+        let a = allocator.allocate();
+        let b = allocator.allocate();
+        assert_ne!(a, b);
+        assert_ne!(nonce(a, 1), nonce(b, 1));
+
+        allocator.release(a);
+        assert_eq!(allocator.allocate(), a);
+        assert_eq!(allocator.allocate(), 2);
+
+        assert!(!allocator.is_duplicate(1, "00000000ABCDEF01"));
+        assert!(allocator.is_duplicate(1, "00000000abcdef01"));
+        assert!(!allocator.is_duplicate(2, "00000000abcdef01"));
+        allocator.forget_job(1);
+        assert!(!allocator.contains(1, "00000000abcdef01"));
+        assert!(allocator.insert(1, "00000000abcdef01"));
+        assert!(!allocator.insert(1, "00000000ABCDEF01"));
+        assert!(allocator.contains(1, "00000000abcdef01"));
+    }
+
+    #[test]
+    fn test_submit_throttle() {
+        let mut throttle = SubmitThrottle::new(3);
+        for _ in 0..3 {
+            assert!(throttle.allow(1, 100));
+        }
+        assert!(!throttle.allow(1, 100));
+        // 其他矿机不受影响
+        assert!(throttle.allow(2, 100));
+
+        // 每秒补充 3 个，但不超过容量
+        assert!(throttle.allow(1, 101));
+        for _ in 0..2 {
+            assert!(throttle.allow(1, 101));
+        }
+        assert!(!throttle.allow(1, 101));
+        for _ in 0..3 {
+            assert!(throttle.allow(1, 110));
+        }
+        assert!(!throttle.allow(1, 110));
+        // 时间回退时不补充
+        assert!(!throttle.allow(1, 105));
+
+        throttle.forget(1);
+        assert!(throttle.allow(1, 110));
+    }
+
+    #[test]
+    fn test_job_tracker() {
+        let mut tracker = JobTracker::new(10);
+        assert_eq!(tracker.grade(1, 100), JobStatus::Unknown);
+
+        tracker.advance(1, 100);
+        assert_eq!(tracker.grade(1, 100), JobStatus::Current);
+
+        tracker.advance(2, 105);
+        assert_eq!(tracker.current(), Some(2));
+        assert_eq!(tracker.grade(2, 105), JobStatus::Current);
+        assert_eq!(tracker.grade(1, 105), JobStatus::Stale);
+        assert_eq!(tracker.grade(1, 115), JobStatus::Stale);
+        assert_eq!(tracker.grade(1, 116), JobStatus::Unknown);
+
+        // 重复下发当前任务不会让其过期
+        tracker.advance(2, 108);
+        assert_eq!(tracker.grade(2, 108), JobStatus::Current);
+
+        tracker.advance(3, 110);
+        assert_eq!(tracker.grade(1, 110), JobStatus::Stale);
+        assert_eq!(tracker.grade(2, 110), JobStatus::Stale);
+        // 模板再次切换时清理超出宽限期的任务
+        tracker.advance(4, 117);
+        assert_eq!(tracker.superseded.len(), 2);
+        assert_eq!(tracker.grade(1, 117), JobStatus::Unknown);
+        assert_eq!(tracker.grade(2, 117), JobStatus::Stale);
+        assert_eq!(tracker.grade(3, 117), JobStatus::Stale);
+        assert_eq!(tracker.grade(2, 121), JobStatus::Unknown);
+        assert_eq!(tracker.grade(99, 117), JobStatus::Unknown);
+    }
+
+    #[test]
+    fn test_template_cache() {
+        let mut cache = TemplateCache::new(2);
+        assert!(cache.is_empty());
+        assert!(cache.current().is_none());
+        let template = |height| {
+            Arc::new(BlockTemplate {
+                height,
+                ..Default::default()
+            })
+        };
+
+        cache.insert(1, template(100));
+        cache.insert(2, template(101));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.current().unwrap().height, 101);
+        assert_eq!(cache.get(1).unwrap().height, 100);
+
+        // 1 刚被访问过，淘汰 2
+        cache.insert(3, template(102));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(2).is_none());
+        assert!(matches!(cache.lookup(2), TemplateLookup::Evicted));
+        assert!(matches!(cache.lookup(9), TemplateLookup::Unknown));
+        assert!(matches!(cache.lookup(1), TemplateLookup::Found(t) if t.height == 100));
+        assert_eq!(cache.current().unwrap().height, 102);
+
+        cache.insert(4, template(103));
+        assert!(cache.get(3).is_none());
+        assert!(cache.get(1).is_some());
+        assert_eq!(cache.current().unwrap().height, 103);
+    }
+
+    #[test]
+    fn test_header_with_time() {
+        let mut job = job();
+        let header = job.header_with_time(0x01020304).unwrap();
+        assert_eq!(&header[TIME_OFFSET..BITS_OFFSET], &[4, 3, 2, 1]);
+        assert_eq!(&header[..68], &job.header[..68]);
+        assert_eq!(&header[72..], &job.header[72..]);
+
+        job.fixed_time = true;
+        assert_eq!(job.header_with_time(0).unwrap(), job.header);
+        assert!(job.header_with_time(0x01020304).is_err());
+        assert!(job.header_hash_with_time(0x01020304).is_err());
+
+        job.header.truncate(72);
+        assert!(job.header_with_time(0).is_err());
+        assert!(job.header_hash_with_time(0).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let job = job();
+        job.validate().unwrap();
+
+        let mut bad = job.clone();
+        bad.share_target_hex = "00000001ffff".to_string();
+        assert!(bad.validate().is_err());
+        let mut bad = job.clone();
+        bad.share_target_hex = "zz".repeat(32);
+        assert!(bad.validate().is_err());
+        let mut bad = job.clone();
+        bad.seed_hash = String::new();
+        assert!(bad.validate().is_err());
+        let mut bad = job;
+        bad.header.push(0);
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_with_share_target() {
+        let mut job = job();
+        job.external_txs = vec!["00".repeat(100)];
+        job.coinbase_tx = vec![1; 100];
+        let target = "0000000fffffffffffffffffffffffffffffffffffffffffffffffffffffffff".to_string();
+        let retargeted = job.with_share_target(target.clone(), false);
+        assert_eq!(retargeted.share_target_hex, target);
+        assert!(!retargeted.refresh);
+        assert_eq!(
+            JobInfo {
+                share_target_hex: job.share_target_hex.clone(),
+                refresh: job.refresh,
+                ..retargeted.clone()
+            },
+            job
+        );
+
+        job.set_share_target(target, false);
+        assert_eq!(job, retargeted);
+    }
+
+    #[test]
+    fn test_block_hash() {
+        let job = job();
+        let ntime = 0x6345_1a2b;
+        let nonce = 0x8e5d_0c00_19a6_f5b2;
+        let header_hash = job.header_hash_with_time(ntime).unwrap();
+        // 区块头中的高度为 0，使用 epoch 0 的 cache
+        let cache = LightCache::new(0);
+        let (mix_hash, final_hash) = kawpow_hash_with_cache(&cache, &header_hash, 0, nonce);
+        assert_eq!(job.block_hash(ntime, nonce, &mix_hash).unwrap(), final_hash);
+        assert_ne!(
+            job.block_hash(ntime + 1, nonce, &mix_hash).unwrap(),
+            final_hash
+        );
+    }
+
+    #[test]
+    fn test_share_verifier() {
+        let verifier = ShareVerifier::new(0);
+        assert_eq!(verifier.epoch(), 0);
+        let mut job = job();
+        job.height = 0;
+        job.header_hash = [7; 32];
+        let submit = |nonce: u64, mix_hash: &[u8; 32]| MiningSubmit {
+            id: serde_json::Value::from(1),
+            worker: "worker".to_string(),
+            job_id: "1".to_string(),
+            nonce: hex::encode(nonce.to_be_bytes()),
+            header_hash: hex::encode(job.header_hash),
+            mix_hash: hex::encode(mix_hash),
+        };
+        let (mix_hash, final_hash) =
+            kawpow_hash_with_cache(&LightCache::new(0), &job.header_hash, 0, 42);
+
+        let valid = submit(42, &mix_hash);
+        let mut wrong_header = valid.clone();
+        wrong_header.header_hash = hex::encode([8; 32]);
+        let mut bad_hex = valid.clone();
+        bad_hex.nonce = "zz".repeat(8);
+        let mut other_epoch = job.clone();
+        other_epoch.height = KAWPOW_EPOCH_LENGTH;
+        let batch = vec![
+            (job.clone(), valid.clone()),
+            (job.clone(), submit(43, &mix_hash)),
+            (job.clone(), submit(42, &[0; 32])),
+            (job.clone(), wrong_header),
+            (job.clone(), bad_hex),
+            (other_epoch, valid.clone()),
+        ];
+        let results = verifier.verify_batch(&batch);
+        assert_eq!(results.len(), batch.len());
+        let achieved = results[0].as_ref().unwrap();
+        assert_eq!(uint256_to_hash(achieved), hex::encode(final_hash));
+        assert_eq!(verifier.verify(&job, &valid).unwrap(), *achieved);
+        for result in &results[1..] {
+            assert!(result.is_err());
+        }
+        assert!(verifier.verify_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_share_processor() {
+        let cache = LightCache::new(0);
+        let mut job = job();
+        job.height = 0;
+        job.header_hash = [7; 32];
+        job.share_target_hex = "ff".repeat(32);
+        job.block_target_hex = "00".repeat(32);
+        let submit = |job_id: &str, nonce: u64| MiningSubmit {
+            id: serde_json::Value::from(1),
+            worker: "worker".to_string(),
+            job_id: job_id.to_string(),
+            nonce: hex::encode(nonce.to_be_bytes()),
+            header_hash: hex::encode(job.header_hash),
+            mix_hash: hex::encode(kawpow_hash_with_cache(&cache, &job.header_hash, 0, nonce).0),
+        };
+        let mut processor = ShareProcessor::new(ShareVerifier::with_cache(cache.clone()), 10);
+        // epoch 0 已有 light cache，不会重新生成
+        processor.advance(0x1a, &job, 100);
+        assert_eq!(processor.epochs(), vec![0]);
+
+        let valid = submit("1a", 42);
+        let final_hash = kawpow_hash_with_cache(&cache, &job.header_hash, 0, 42).1;
+        let diff = target2diff_f64(Uint256::from_be_slice(&final_hash).unwrap()).unwrap();
+        assert_eq!(
+            processor.process_at(&valid, &job, 100),
+            ShareVerdict::Accepted { diff }
+        );
+        assert!(processor.is_seen(0x1a, &valid.nonce));
+        assert_eq!(
+            processor.process_at(&valid, &job, 100),
+            ShareVerdict::Duplicate
+        );
+
+        // mix_hash 错误的提交不占用 nonce，修正后可以重新提交
+        let mut wrong_mix = submit("1a", 43);
+        wrong_mix.mix_hash = hex::encode([0; 32]);
+        assert_eq!(
+            processor.process_at(&wrong_mix, &job, 100),
+            ShareVerdict::InvalidPow
+        );
+        assert!(!processor.is_seen(0x1a, &wrong_mix.nonce));
+        assert!(matches!(
+            processor.process_at(&submit("1a", 43), &job, 100),
+            ShareVerdict::Accepted { .. }
+        ));
+
+        processor.set_nonce_range((1, 2));
+        assert_eq!(processor.nonce_range(), (1, 2));
+        assert_eq!(
+            processor.process_at(&submit("1a", 44), &job, 100),
+            ShareVerdict::InvalidPow
+        );
+        processor.set_nonce_range((0, u32::MAX));
+
+        let mut hard = job.clone();
+        hard.share_target_hex = "00".repeat(32);
+        assert_eq!(
+            processor.process_at(&submit("1a", 45), &hard, 100),
+            ShareVerdict::LowDifficulty
+        );
+
+        let mut easy = job.clone();
+        easy.block_target_hex = "ff".repeat(32);
+        let found = submit("1a", 46);
+        let mut mix_hash = [0; 32];
+        hex::decode_to_slice(&found.mix_hash, &mut mix_hash).unwrap();
+        assert_eq!(
+            processor.process_at(&found, &easy, 100),
+            ShareVerdict::Block {
+                hex: easy.build_block(
+                    &hex::encode(46u64.to_le_bytes()),
+                    &hex::encode(reverse_hash(mix_hash))
+                )
+            }
+        );
+
+        // 没有 light cache 的 epoch 不在提交时生成
+        let mut other_epoch = job.clone();
+        other_epoch.height = KAWPOW_EPOCH_LENGTH;
+        assert_eq!(
+            processor.process_at(&submit("1a", 47), &other_epoch, 100),
+            ShareVerdict::UnknownJob
+        );
+
+        // 宽限期内的旧任务仍做完整校验，有效的记为 Stale，之后为 UnknownJob
+        let mut next = job.clone();
+        next.header_hash = [8; 32];
+        processor.advance(0x1b, &next, 105);
+        // 传入的任务与 job_id 不对应
+        assert_eq!(
+            processor.process_at(&submit("1b", 42), &job, 105),
+            ShareVerdict::UnknownJob
+        );
+        assert_eq!(
+            processor.process_at(&valid, &job, 110),
+            ShareVerdict::Duplicate
+        );
+        assert_eq!(
+            processor.process_at(&submit("1a", 47), &job, 110),
+            ShareVerdict::Stale
+        );
+        assert_eq!(
+            processor.process_at(&submit("1a", 48), &hard, 110),
+            ShareVerdict::LowDifficulty
+        );
+        // 旧任务满足区块目标时仍返回区块
+        assert!(matches!(
+            processor.process_at(&submit("1a", 50), &easy, 110),
+            ShareVerdict::Block { .. }
+        ));
+        assert_eq!(
+            processor.process_at(&submit("1a", 49), &job, 116),
+            ShareVerdict::UnknownJob
+        );
+        assert_eq!(
+            processor.process_at(&submit("99", 42), &job, 110),
+            ShareVerdict::UnknownJob
+        );
+        assert_eq!(
+            processor.process_at(&submit("zz", 42), &job, 110),
+            ShareVerdict::UnknownJob
+        );
+        next.header_hash = [9; 32];
+        processor.advance(0x1c, &next, 120);
+        assert!(!processor.is_seen(0x1a, &valid.nonce));
+    }
+
+    #[test]
+    fn test_header_with_version() {
+        let mut job = job();
+        job.header[0..4].copy_from_slice(&[0x00, 0x00, 0x00, 0x30]);
+        let mask = VersionMask::default();
+        let header = job.header_with_version(0x30004000, mask).unwrap();
+        assert_eq!(&header[0..4], &[0x00, 0x40, 0x00, 0x30]);
+        assert!(job.header_with_version(0x30000001, mask).is_err());
+    }
+
+    #[test]
+    fn test_kawpow_nonce() {
+        // 矿机在 extranonce1 为 8e5d 时提交的 nonce
+        let nonce = KawpowNonce::from_submit_hex("0x8e5d0c0019a6f5b2").unwrap();
+        assert_eq!(nonce.0, 0x8e5d_0c00_19a6_f5b2);
+        assert_eq!(nonce.to_submit_hex(), "8e5d0c0019a6f5b2");
+        assert_eq!(
+            nonce.to_header_bytes(),
+            [0xb2, 0xf5, 0xa6, 0x19, 0x00, 0x0c, 0x5d, 0x8e]
+        );
+        assert_eq!(
+            hex::encode(nonce.to_header_bytes()),
+            hex_le_to_be("8e5d0c0019a6f5b2").unwrap()
+        );
+        assert_eq!(
+            KawpowNonce::from_header_bytes(nonce.to_header_bytes()),
+            nonce
+        );
+
+        assert!(nonce.has_prefix(&[0x8e, 0x5d]));
+        assert!(!nonce.has_prefix(&[0x5d, 0x8e]));
+        assert_eq!(nonce.rolled(2), 0x0c00_19a6_f5b2);
+        assert_eq!(nonce.rolled(0), nonce.0);
+        assert_eq!(nonce.rolled(8), 0);
+        assert_eq!(
+            KawpowNonce::from_parts(&[0x8e, 0x5d], 0x0c00_19a6_f5b2).unwrap(),
+            nonce
+        );
+        assert_eq!(KawpowNonce::from_parts(&[], nonce.0).unwrap(), nonce);
+        // 滚动部分越界会覆盖矿池的前缀
+        assert!(KawpowNonce::from_parts(&[0x8e, 0x5d], 1 << 48).is_err());
+        assert!(KawpowNonce::from_parts(&[0; 9], 0).is_err());
+
+        assert!(KawpowNonce::from_submit_hex("8e5d0c").is_err());
+        assert!(KawpowNonce::from_submit_hex(&"zz".repeat(8)).is_err());
+        // 只去掉一个前缀，大写 0X 同样接受
+        assert_eq!(
+            KawpowNonce::from_submit_hex("0X8e5d0c0019a6f5b2").unwrap(),
+            KawpowNonce::from_submit_hex("8e5d0c0019a6f5b2").unwrap()
+        );
+        assert!(KawpowNonce::from_submit_hex("0x0x8e5d0c0019a6f5b2").is_err());
+    }
+
+    #[test]
+    fn test_job_id() {
+        let id = job_id();
+        assert!(job_id() > id);
+        let nonce = nonce(7, id);
+        assert_eq!(nonce.len(), 24);
+        assert_eq!(job_id_from_nonce(&nonce).unwrap(), id);
+    }
+
+    #[test]
+    fn test_malformed_nonce() {
+        assert!(job_id_from_nonce("").is_err());
+        assert!(miner_index_from_nonce("").is_err());
+        assert!(job_id_from_nonce("010203").is_err());
+        assert!(miner_index_from_nonce("010203").is_err());
+        assert!(job_id_from_nonce("zz010203").is_err());
+
+        let nonce = nonce(0x0102030405060708, 0xaabbccdd);
+        assert_eq!(nonce, "aabbccdd0102030405060708");
+        assert_eq!(job_id_from_nonce(&nonce).unwrap(), 0xaabbccdd);
+        assert_eq!(miner_index_from_nonce(&nonce).unwrap(), 0x0102030405060708);
+    }
+
+    #[test]
+    fn test_worker_registry() {
+        let mut allocator = NonceAllocator::new();
+        let mut registry = WorkerRegistry::new();
+        assert!(registry.is_empty());
+        let a = allocator.allocate();
+        let b = allocator.allocate();
+        let c = allocator.allocate();
+        registry.register(a, "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1");
+        registry.register(b, "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig2");
+        registry.register(c, "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1");
+        assert_eq!(registry.len(), 3);
+
+        let id = job_id();
+        let submitted = nonce(b, id);
+        assert_eq!(miner_index_from_nonce(&submitted).unwrap(), b);
+        assert_eq!(job_id_from_nonce(&submitted).unwrap(), id);
+        assert_eq!(
+            registry.worker_from_nonce(&submitted).unwrap(),
+            Some("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig2")
+        );
+        assert_eq!(
+            registry.miner_indexes("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1"),
+            vec![a, c]
+        );
+        assert_eq!(registry.worker_from_nonce(&nonce(99, id)).unwrap(), None);
+        assert!(registry.worker_from_nonce("0102").is_err());
+
+        assert_eq!(
+            registry.unregister(a).as_deref(),
+            Some("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1")
+        );
+        assert_eq!(registry.unregister(a), None);
+        assert_eq!(
+            registry.miner_indexes("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1"),
+            vec![c]
+        );
+
+        // 重新登记时替换原来的矿工名
+        registry.register(b, "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig3");
+        assert_eq!(
+            registry.worker(b),
+            Some("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig3")
+        );
+        assert!(registry
+            .miner_indexes("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig2")
+            .is_empty());
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compress_job() {
+        use crate::address::Address;
+        use crate::block_template::BlockTemplateInfo;
+        use std::str::FromStr;
+
+        let info: BlockTemplateInfo =
+            serde_json::from_str(include_str!("testdata/block_template.json")).unwrap();
+        let pool_addr = Address::from_str("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk").unwrap();
+        let template = BlockTemplate::new(&info, pool_addr, "pool".to_string()).unwrap();
+        let mut job = template.create_job(job().share_target_hex, true);
+        job.fixed_time = true;
+
+        let compressed = compress_job(&job).unwrap();
+        assert_eq!(decompress_job(&compressed).unwrap(), job);
+        let json = serde_json::to_vec(&job).unwrap();
+        assert!(compressed.len() < json.len());
+        // 交易以二进制压缩，应明显小于 hex 的一半
+        assert!(compressed.len() < json.len() / 2);
+
+        assert!(decompress_job(&compressed[..compressed.len() / 2]).is_err());
+        let mut invalid = job.clone();
+        invalid.external_txs[0].push('z');
+        assert!(compress_job(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_share_multiplier() {
+        let mut job = job();
+        job.share_target_hex =
+            "00000000ffff0000000000000000000000000000000000000000000000000000".to_string();
+        let target = uint256_from_hash(&job.share_target_hex).unwrap();
+        assert!((job.share_multiplier(&target).unwrap() - 1.0).abs() < 1e-9);
+        // 目标值减半，难度翻倍
+        let achieved =
+            uint256_from_hash("000000007fff8000000000000000000000000000000000000000000000000000")
+                .unwrap();
+        assert!((job.share_multiplier(&achieved).unwrap() - 2.0).abs() < 1e-9);
+        let achieved =
+            uint256_from_hash("0000000001fffe00000000000000000000000000000000000000000000000000")
+                .unwrap();
+        assert!((job.share_multiplier(&achieved).unwrap() - 128.0).abs() < 1e-6);
+        let achieved =
+            uint256_from_hash("00000001fffe0000000000000000000000000000000000000000000000000000")
+                .unwrap();
+        assert!((job.share_multiplier(&achieved).unwrap() - 0.5).abs() < 1e-9);
+
+        assert!(job.share_multiplier(&Uint256::default()).is_err());
+    }
+
+    #[test]
+    fn test_validate_nonce_in_range() {
+        let job = job();
+        let full = (0, u32::MAX);
+        assert!(job
+            .validate_nonce_in_range("8e5d0c0019a6f5b2", full)
+            .is_ok());
+        assert!(job
+            .validate_nonce_in_range("0x8e5d0c0019a6f5b2", full)
+            .is_ok());
+        assert!(job
+            .validate_nonce_in_range("0X8e5d0c0019a6f5b2", full)
+            .is_ok());
+        assert!(job
+            .validate_nonce_in_range("0x0x8e5d0c0019a6f5b2", full)
+            .is_err());
+
+        let range = (0x10000000, 0x1fffffff);
+        assert!(job
+            .validate_nonce_in_range("10000000ffffffff", range)
+            .is_ok());
+        assert!(job
+            .validate_nonce_in_range("1fffffff00000000", range)
+            .is_ok());
+        // 只比较高 4 字节
+        assert!(job
+            .validate_nonce_in_range("0fffffffffffffff", range)
+            .is_err());
+        assert!(job
+            .validate_nonce_in_range("2000000000000000", range)
+            .is_err());
+
+        assert!(job.validate_nonce_in_range("", full).is_err());
+        assert!(job.validate_nonce_in_range("8e5d0c0019a6f5", full).is_err());
+        assert!(job
+            .validate_nonce_in_range("zz5d0c0019a6f5b2", full)
+            .is_err());
+        // 12 字节的任务标识不是合法的 KawPow nonce
+        assert!(job.validate_nonce_in_range(&nonce(1, 1), full).is_err());
+    }
+
+    #[test]
+    fn test_from_notify() {
+        let mut job = job();
+        job.header_hash = [0xab; 32];
+        job.seed_hash = "cd".repeat(32);
+        let standard: serde_json::Value = serde_json::from_str(&job.to_resp_str("1a")).unwrap();
+        let mut prefixed = standard.clone();
+        for i in [1, 2, 3, 6] {
+            prefixed["params"][i] = format!("0x{}", prefixed["params"][i].as_str().unwrap()).into();
+        }
+        for value in [&standard, &prefixed] {
+            let partial = JobInfo::from_notify(&value.to_string()).unwrap();
+            assert_eq!(partial.job_id, "1a");
+            assert_eq!(partial.header_hash, job.header_hash);
+            assert_eq!(partial.seed_hash, job.seed_hash);
+            assert_eq!(partial.share_target_hex, job.share_target_hex);
+            assert_eq!(partial.refresh, job.refresh);
+            assert_eq!(partial.height, job.height);
+            assert_eq!(partial.block_bits_hex.as_ref(), Some(&job.block_bits_hex));
+            assert_eq!(
+                partial.block_target_hex.as_ref(),
+                Some(&job.block_target_hex)
+            );
+        }
+        let mut no_bits = standard.clone();
+        no_bits["params"].as_array_mut().unwrap().truncate(6);
+        let partial = JobInfo::from_notify(&no_bits.to_string()).unwrap();
+        assert_eq!(partial.block_bits_hex, None);
+        assert_eq!(partial.block_target_hex, None);
+
+        assert!(JobInfo::from_notify(&set_target_notify(&job.share_target_hex)).is_err());
+        let mut value: serde_json::Value = serde_json::from_str(&job.to_resp_str("1a")).unwrap();
+        value["params"][1] = "0102".into();
+        assert!(JobInfo::from_notify(&value.to_string()).is_err());
+        value["params"].as_array_mut().unwrap().truncate(5);
+        assert!(JobInfo::from_notify(&value.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_to_resp_str() {
+        let job = job();
+        let expect = format!(
+            "{{\"id\":null,\"method\":\"mining.notify\",\"params\":[\"{}\",\"{}\",\"{}\",\"{}\",{},{},\"{}\"]}}",
+            "1a",
+            hex::encode(job.header_hash),
+            job.seed_hash,
+            job.share_target_hex,
+            job.refresh,
+            job.height,
+            job.block_bits_hex,
+        );
+        assert_eq!(job.to_resp_str("1a"), expect);
+
+        // 字段中的特殊字符会被正确转义
+        let s = job.to_resp_str("a\"b");
+        let value: serde_json::Value = serde_json::from_str(&s).unwrap();
+        assert_eq!(value["params"][0], "a\"b");
+    }
+
+    #[test]
+    fn test_classify() {
+        let job = job();
+        let achieved =
+            uint256_from_hash("0000000000000001000000000000000000000000000000000000000000000000")
+                .unwrap();
+        let outcome = job.classify(achieved).unwrap();
+        assert!(outcome.valid_share && outcome.is_block);
+
+        let achieved =
+            uint256_from_hash("0000000100000000000000000000000000000000000000000000000000000000")
+                .unwrap();
+        let outcome = job.classify(achieved).unwrap();
+        assert!(outcome.valid_share && !outcome.is_block);
+
+        let achieved =
+            uint256_from_hash("0000000300000000000000000000000000000000000000000000000000000000")
+                .unwrap();
+        let outcome = job.classify(achieved).unwrap();
+        assert!(!outcome.valid_share && !outcome.is_block);
+    }
+
+    #[test]
+    fn test_grade_share() {
+        let job = job();
+        let hash = |s: &str| uint256_from_hash(s).unwrap();
+        // 难度略低于 1，满足份额目标
+        let achieved = hash("0000000100000000000000000000000000000000000000000000000000000000");
+        assert_eq!(job.grade_share(achieved, 0).unwrap(), ShareGrade::Valid);
+        assert_eq!(job.grade_share(achieved, 1).unwrap(), ShareGrade::TooLow);
+        // 低于最低难度时即使满足份额目标也拒绝
+        assert_eq!(job.grade_share(achieved, 2).unwrap(), ShareGrade::TooLow);
+
+        let achieved = hash("0000000300000000000000000000000000000000000000000000000000000000");
+        assert_eq!(
+            job.grade_share(achieved, 0).unwrap(),
+            ShareGrade::AboveTarget
+        );
+        assert_eq!(job.grade_share(achieved, 1).unwrap(), ShareGrade::TooLow);
+
+        // 区块不受最低难度影响
+        let achieved = hash("0000000000000001000000000000000000000000000000000000000000000000");
+        assert_eq!(
+            job.grade_share(achieved, u64::MAX).unwrap(),
+            ShareGrade::Block
+        );
+
+        let shared = SharedJob::new(
+            Arc::new(BlockTemplate {
+                target_hex: job.block_target_hex.clone(),
+                ..Default::default()
+            }),
+            job.share_target_hex.clone(),
+            true,
+        );
+        let achieved = hash("0000000100000000000000000000000000000000000000000000000000000000");
+        assert_eq!(shared.grade_share(achieved, 2).unwrap(), ShareGrade::TooLow);
+    }
+
+    #[test]
+    fn test_shared_job() {
+        let template = BlockTemplate {
+            header: vec![1; 80],
+            coinbase_tx: vec![2; 100],
+            external_txs: vec!["00".repeat(200), "ff".repeat(300)],
+            target_hex: job().block_target_hex,
+            bits_hex: "1a5ab50d".to_string(),
+            height: 2491604,
+            ..Default::default()
+        };
+        let target = job().share_target_hex;
+        let cloned = template.create_job(target.clone(), true);
+        let shared = SharedJob::new(Arc::new(template), target, true);
+        let nonce = "0123456789abcdef";
+        let mix_hash = "11".repeat(32);
+        assert_eq!(
+            shared.build_block(nonce, &mix_hash),
+            cloned.build_block(nonce, &mix_hash)
+        );
+        assert_eq!(shared.to_resp_str("1"), cloned.to_resp_str("1"));
+        let achieved = uint256_from_hash(&cloned.block_target_hex).unwrap();
+        assert_eq!(
+            shared.classify(achieved).unwrap(),
+            cloned.classify(achieved).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_block() {
+        let mut job = job();
+        job.coinbase_tx = (0..=255u8).cycle().take(1500).collect();
+        job.external_txs = vec!["00".repeat(200), "ff".repeat(300)];
+        let nonce = "0123456789abcdef";
+        let mix_hash = "11".repeat(32);
+        let mut out = Vec::new();
+        job.write_block(&mut out, nonce, &mix_hash).unwrap();
+        assert_eq!(out, job.build_block(nonce, &mix_hash).into_bytes());
+
+        let shared = SharedJob::new(
+            Arc::new(BlockTemplate {
+                header: job.header.clone(),
+                coinbase_tx: job.coinbase_tx.clone(),
+                external_txs: job.external_txs.clone(),
+                ..Default::default()
+            }),
+            job.share_target_hex.clone(),
+            true,
+        );
+        let mut shared_out = Vec::new();
+        shared
+            .write_block(&mut shared_out, nonce, &mix_hash)
+            .unwrap();
+        assert_eq!(shared_out, out);
+    }
+}
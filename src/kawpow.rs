@@ -0,0 +1,511 @@
+use sha3::{Digest, Keccak256, Keccak512};
+
+pub use crate::consts::KAWPOW_EPOCH_LENGTH;
+
+/// 高度所在的 epoch
+pub fn kawpow_epoch(height: u32) -> u32 {
+    height / KAWPOW_EPOCH_LENGTH
+}
+
+/// epoch 对应的 seed hash，即对 32 字节 0 做 epoch 次 keccak256
+pub fn kawpow_seed_for_epoch(epoch: u32) -> [u8; 32] {
+    let mut seed = [0; 32];
+    for _ in 0..epoch {
+        let mut hasher = Keccak256::default();
+        hasher.update(seed);
+        seed = hasher.finalize().into();
+    }
+    seed
+}
+
+const LIGHT_CACHE_INIT_SIZE: u64 = 1 << 24;
+const LIGHT_CACHE_GROWTH: u64 = 1 << 17;
+const LIGHT_CACHE_ROUNDS: usize = 3;
+const FULL_DATASET_INIT_SIZE: u64 = 1 << 30;
+const FULL_DATASET_GROWTH: u64 = 1 << 23;
+/// KawPow 沿用 ProgPoW 0.9.3 起的 512，ethash 为 256
+const FULL_DATASET_ITEM_PARENTS: u32 = 512;
+
+const PERIOD_LENGTH: u32 = 3;
+const NUM_REGS: usize = 32;
+const NUM_LANES: usize = 16;
+const NUM_CACHE_ACCESSES: usize = 11;
+const NUM_MATH_OPERATIONS: usize = 18;
+const NUM_ROUNDS: u32 = 64;
+/// 取 DAG 开头的 16KB 作为 L1 缓存
+const L1_CACHE_WORDS: usize = 4096;
+/// 每次 DAG 访问为 2048 位，每条 lane 4 个字
+const DAG_WORDS_PER_LANE: usize = 4;
+
+const FNV_PRIME: u32 = 0x01000193;
+const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+
+/// keccak 填充用的 "RAVENCOINKAWPOW"
+const RAVENCOIN_KAWPOW: [u32; 15] = [
+    0x72, 0x41, 0x56, 0x45, 0x4e, 0x43, 0x4f, 0x49, 0x4e, 0x4b, 0x41, 0x57, 0x50, 0x4f, 0x57,
+];
+
+type Hash512 = [u32; 16];
+
+fn fnv1(u: u32, v: u32) -> u32 {
+    u.wrapping_mul(FNV_PRIME) ^ v
+}
+
+fn fnv1a(u: u32, v: u32) -> u32 {
+    (u ^ v).wrapping_mul(FNV_PRIME)
+}
+
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let mut d = 2;
+    while d * d <= n {
+        if n.is_multiple_of(d) {
+            return false;
+        }
+        d += 1;
+    }
+    true
+}
+
+fn largest_prime(upper_bound: u64) -> u64 {
+    let mut n = upper_bound;
+    while !is_prime(n) {
+        n -= 1;
+    }
+    n
+}
+
+fn keccak512(data: &[u8]) -> Hash512 {
+    let hash = Keccak512::digest(data);
+    let mut out = [0; 16];
+    for (word, chunk) in out.iter_mut().zip(hash.chunks(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    out
+}
+
+fn keccak512_words(words: &Hash512) -> Hash512 {
+    let mut data = [0; 64];
+    for (chunk, word) in data.chunks_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    keccak512(&data)
+}
+
+/// ethash 的 light cache，可以按需计算任意 DAG 数据项，不需要生成完整的 DAG
+#[derive(Debug, Clone)]
+pub struct LightCache {
+    epoch: u32,
+    items: Vec<Hash512>,
+    /// 以 1024 位为单位的 DAG 数据项个数
+    dataset_items: u32,
+    l1_cache: Vec<u32>,
+}
+
+impl LightCache {
+    /// 生成 epoch 对应的 light cache，主网高度下约 60MB，耗时较长，应当复用
+    pub fn new(epoch: u32) -> Self {
+        let num_items =
+            largest_prime((LIGHT_CACHE_INIT_SIZE + LIGHT_CACHE_GROWTH * epoch as u64) / 64)
+                as usize;
+        let mut items = Vec::with_capacity(num_items);
+        items.push(keccak512(&kawpow_seed_for_epoch(epoch)));
+        for i in 1..num_items {
+            let item = keccak512_words(&items[i - 1]);
+            items.push(item);
+        }
+        for _ in 0..LIGHT_CACHE_ROUNDS {
+            for i in 0..num_items {
+                let v = items[i][0] as usize % num_items;
+                let u = (i + num_items - 1) % num_items;
+                let mut xor = [0; 16];
+                for (w, x) in xor.iter_mut().enumerate() {
+                    *x = items[u][w] ^ items[v][w];
+                }
+                items[i] = keccak512_words(&xor);
+            }
+        }
+        let dataset_items =
+            largest_prime((FULL_DATASET_INIT_SIZE + FULL_DATASET_GROWTH * epoch as u64) / 128)
+                as u32;
+        let mut cache = Self {
+            epoch,
+            items,
+            dataset_items,
+            l1_cache: Vec::with_capacity(L1_CACHE_WORDS),
+        };
+        for i in 0..(L1_CACHE_WORDS / 16) as u32 {
+            let item = cache.dataset_item_512(i);
+            cache.l1_cache.extend_from_slice(&item);
+        }
+        cache
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// 计算第 index 个 512 位 DAG 数据项
+    fn dataset_item_512(&self, index: u32) -> Hash512 {
+        let num_items = self.items.len() as u32;
+        let mut mix = self.items[(index % num_items) as usize];
+        mix[0] ^= index;
+        let mut mix = keccak512_words(&mix);
+        for j in 0..FULL_DATASET_ITEM_PARENTS {
+            let parent = fnv1(index ^ j, mix[j as usize % 16]) % num_items;
+            let parent = &self.items[parent as usize];
+            for (w, m) in mix.iter_mut().enumerate() {
+                *m = fnv1(*m, parent[w]);
+            }
+        }
+        keccak512_words(&mix)
+    }
+
+    /// 计算第 index 个 2048 位 DAG 数据项
+    fn dataset_item_2048(&self, index: u32) -> [u32; 64] {
+        let mut item = [0; 64];
+        for (k, chunk) in item.chunks_mut(16).enumerate() {
+            chunk.copy_from_slice(&self.dataset_item_512(index * 4 + k as u32));
+        }
+        item
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Kiss99 {
+    z: u32,
+    w: u32,
+    jsr: u32,
+    jcong: u32,
+}
+
+impl Kiss99 {
+    fn next(&mut self) -> u32 {
+        self.z = 36969u32
+            .wrapping_mul(self.z & 0xffff)
+            .wrapping_add(self.z >> 16);
+        self.w = 18000u32
+            .wrapping_mul(self.w & 0xffff)
+            .wrapping_add(self.w >> 16);
+        self.jcong = 69069u32.wrapping_mul(self.jcong).wrapping_add(1234567);
+        self.jsr ^= self.jsr << 17;
+        self.jsr ^= self.jsr >> 13;
+        self.jsr ^= self.jsr << 5;
+        ((self.z << 16).wrapping_add(self.w) ^ self.jcong).wrapping_add(self.jsr)
+    }
+}
+
+/// 每个 period 内固定的随机程序
+#[derive(Debug, Clone)]
+struct MixRngState {
+    rng: Kiss99,
+    dst_seq: [usize; NUM_REGS],
+    src_seq: [usize; NUM_REGS],
+    dst_counter: usize,
+    src_counter: usize,
+}
+
+impl MixRngState {
+    fn new(period: u64) -> Self {
+        let seed_lo = period as u32;
+        let seed_hi = (period >> 32) as u32;
+        let z = fnv1a(FNV_OFFSET_BASIS, seed_lo);
+        let w = fnv1a(z, seed_hi);
+        let jsr = fnv1a(w, seed_lo);
+        let jcong = fnv1a(jsr, seed_hi);
+        let mut rng = Kiss99 { z, w, jsr, jcong };
+        let mut dst_seq = [0; NUM_REGS];
+        let mut src_seq = [0; NUM_REGS];
+        for i in 0..NUM_REGS {
+            dst_seq[i] = i;
+            src_seq[i] = i;
+        }
+        // Fisher-Yates 洗牌
+        for i in (2..=NUM_REGS).rev() {
+            dst_seq.swap(i - 1, rng.next() as usize % i);
+            src_seq.swap(i - 1, rng.next() as usize % i);
+        }
+        Self {
+            rng,
+            dst_seq,
+            src_seq,
+            dst_counter: 0,
+            src_counter: 0,
+        }
+    }
+
+    fn next_dst(&mut self) -> usize {
+        let dst = self.dst_seq[self.dst_counter % NUM_REGS];
+        self.dst_counter += 1;
+        dst
+    }
+
+    fn next_src(&mut self) -> usize {
+        let src = self.src_seq[self.src_counter % NUM_REGS];
+        self.src_counter += 1;
+        src
+    }
+}
+
+fn random_math(a: u32, b: u32, selector: u32) -> u32 {
+    match selector % 11 {
+        0 => a.wrapping_add(b),
+        1 => a.wrapping_mul(b),
+        2 => ((a as u64 * b as u64) >> 32) as u32,
+        3 => a.min(b),
+        4 => a.rotate_left(b % 32),
+        5 => a.rotate_right(b % 32),
+        6 => a & b,
+        7 => a | b,
+        8 => a ^ b,
+        9 => a.leading_zeros() + b.leading_zeros(),
+        _ => a.count_ones() + b.count_ones(),
+    }
+}
+
+fn random_merge(a: &mut u32, b: u32, selector: u32) {
+    let x = (selector >> 16) % 31 + 1;
+    *a = match selector % 4 {
+        0 => a.wrapping_mul(33).wrapping_add(b),
+        1 => (*a ^ b).wrapping_mul(33),
+        2 => a.rotate_left(x) ^ b,
+        _ => a.rotate_right(x) ^ b,
+    };
+}
+
+type Mix = [[u32; NUM_REGS]; NUM_LANES];
+
+fn init_mix(seed: [u32; 2]) -> Mix {
+    let z = fnv1a(FNV_OFFSET_BASIS, seed[0]);
+    let w = fnv1a(z, seed[1]);
+    let mut mix = [[0; NUM_REGS]; NUM_LANES];
+    for (l, lane) in mix.iter_mut().enumerate() {
+        let jsr = fnv1a(w, l as u32);
+        let jcong = fnv1a(jsr, l as u32);
+        let mut rng = Kiss99 { z, w, jsr, jcong };
+        for reg in lane.iter_mut() {
+            *reg = rng.next();
+        }
+    }
+    mix
+}
+
+fn round(cache: &LightCache, r: u32, mix: &mut Mix, state: &mut MixRngState) {
+    let num_items = cache.dataset_items / 2;
+    let item_index = mix[r as usize % NUM_LANES][0] % num_items;
+    let item = cache.dataset_item_2048(item_index);
+
+    for i in 0..NUM_CACHE_ACCESSES.max(NUM_MATH_OPERATIONS) {
+        if i < NUM_CACHE_ACCESSES {
+            let src = state.next_src();
+            let dst = state.next_dst();
+            let sel = state.rng.next();
+            for lane in mix.iter_mut() {
+                let offset = lane[src] as usize % L1_CACHE_WORDS;
+                random_merge(&mut lane[dst], cache.l1_cache[offset], sel);
+            }
+        }
+        if i < NUM_MATH_OPERATIONS {
+            let src_rnd = state.rng.next() as usize % (NUM_REGS * (NUM_REGS - 1));
+            let src1 = src_rnd % NUM_REGS;
+            let mut src2 = src_rnd / NUM_REGS;
+            if src2 >= src1 {
+                src2 += 1;
+            }
+            let sel1 = state.rng.next();
+            let dst = state.next_dst();
+            let sel2 = state.rng.next();
+            for lane in mix.iter_mut() {
+                let data = random_math(lane[src1], lane[src2], sel1);
+                random_merge(&mut lane[dst], data, sel2);
+            }
+        }
+    }
+
+    let mut dsts = [0; DAG_WORDS_PER_LANE];
+    let mut sels = [0; DAG_WORDS_PER_LANE];
+    for i in 0..DAG_WORDS_PER_LANE {
+        dsts[i] = if i == 0 { 0 } else { state.next_dst() };
+        sels[i] = state.rng.next();
+    }
+    for (l, lane) in mix.iter_mut().enumerate() {
+        let offset = ((l ^ r as usize) % NUM_LANES) * DAG_WORDS_PER_LANE;
+        for i in 0..DAG_WORDS_PER_LANE {
+            random_merge(&mut lane[dsts[i]], item[offset + i], sels[i]);
+        }
+    }
+}
+
+fn hash_mix(cache: &LightCache, height: u32, seed: [u32; 2]) -> [u32; 8] {
+    let mut mix = init_mix(seed);
+    // 每一轮都从 period 的初始状态开始
+    let state = MixRngState::new((height / PERIOD_LENGTH) as u64);
+    for r in 0..NUM_ROUNDS {
+        round(cache, r, &mut mix, &mut state.clone());
+    }
+
+    let mut mix_hash = [FNV_OFFSET_BASIS; 8];
+    for (l, lane) in mix.iter().enumerate() {
+        let lane_hash = lane
+            .iter()
+            .fold(FNV_OFFSET_BASIS, |acc, reg| fnv1a(acc, *reg));
+        mix_hash[l % 8] = fnv1a(mix_hash[l % 8], lane_hash);
+    }
+    mix_hash
+}
+
+fn words_to_bytes(words: &[u32]) -> [u8; 32] {
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+/// header_hash 和 nonce 经 keccak 得到的初始状态，决定 mix 的种子
+fn seed_state(header_hash: &[u8; 32], nonce: u64) -> [u32; 8] {
+    let mut state = [0u32; 25];
+    for (word, chunk) in state.iter_mut().zip(header_hash.chunks(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    state[8] = nonce as u32;
+    state[9] = (nonce >> 32) as u32;
+    state[10..].copy_from_slice(&RAVENCOIN_KAWPOW);
+    keccak::f800(&mut state);
+    let mut state2 = [0; 8];
+    state2.copy_from_slice(&state[..8]);
+    state2
+}
+
+fn final_from_mix(state2: &[u32; 8], mix_hash: &[u32; 8]) -> [u8; 32] {
+    let mut state = [0u32; 25];
+    state[..8].copy_from_slice(state2);
+    state[8..16].copy_from_slice(mix_hash);
+    state[16..].copy_from_slice(&RAVENCOIN_KAWPOW[..9]);
+    keccak::f800(&mut state);
+    words_to_bytes(&state[..8])
+}
+
+/// 使用已生成的 light cache 计算 KawPow，cache 的 epoch 需与 height 一致
+///
+/// header_hash 为显示序，与下发给矿机的一致；返回的 mix_hash 和 final_hash 同为显示序
+pub fn kawpow_hash_with_cache(
+    cache: &LightCache,
+    header_hash: &[u8; 32],
+    height: u32,
+    nonce: u64,
+) -> ([u8; 32], [u8; 32]) {
+    let state2 = seed_state(header_hash, nonce);
+    let mix_hash = hash_mix(cache, height, [state2[0], state2[1]]);
+    (
+        words_to_bytes(&mix_hash),
+        final_from_mix(&state2, &mix_hash),
+    )
+}
+
+/// 由矿机给出的 mix_hash 直接计算 final_hash，不校验 mix_hash 是否正确，
+/// 即节点的 hash_no_verify，也是区块浏览器中显示的区块哈希
+pub fn kawpow_final_hash(header_hash: &[u8; 32], nonce: u64, mix_hash: &[u8; 32]) -> [u8; 32] {
+    let mut mix = [0u32; 8];
+    for (word, chunk) in mix.iter_mut().zip(mix_hash.chunks(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    final_from_mix(&seed_state(header_hash, nonce), &mix)
+}
+
+/// 计算 KawPow 的 (mix_hash, final_hash)，每次调用都会重新生成 light cache，
+/// 批量计算时应使用 LightCache 和 kawpow_hash_with_cache
+pub fn kawpow_hash(header_hash: &[u8; 32], height: u32, nonce: u64) -> ([u8; 32], [u8; 32]) {
+    let cache = LightCache::new(kawpow_epoch(height));
+    kawpow_hash_with_cache(&cache, header_hash, height, nonce)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kawpow_epoch() {
+        assert_eq!(kawpow_epoch(0), 0);
+        assert_eq!(kawpow_epoch(7499), 0);
+        assert_eq!(kawpow_epoch(7500), 1);
+        assert_eq!(kawpow_epoch(2491604), 332);
+
+        assert_eq!(kawpow_seed_for_epoch(0), [0; 32]);
+        assert_eq!(
+            hex::encode(kawpow_seed_for_epoch(1)),
+            "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563"
+        );
+    }
+
+    #[test]
+    fn test_progpow_primitives() {
+        // 以下数值来自 ProgPoW 参考实现的测试
+        let mut state = [0u32; 25];
+        keccak::f800(&mut state);
+        assert_eq!(
+            hex::encode(words_to_bytes(&state[..8])),
+            "5dd431e5fbc604f499bfa0232f45f8f142d0ff5178f539e5a7800bf0643697af"
+        );
+
+        let merges = [
+            (1000000, 101, 33000101),
+            (2000000, 102, 66003366),
+            (3000000, 103, 6000103),
+            (4000000, 104, 2000104),
+        ];
+        for (i, (a, b, expected)) in merges.into_iter().enumerate() {
+            let mut a = a;
+            random_merge(&mut a, b, i as u32);
+            assert_eq!(a, expected);
+        }
+        let maths = [
+            (20, 22, 42),
+            (70000, 80000, 1305032704),
+            (70000, 80000, 1),
+            (1, 2, 1),
+            (3, 10000, 196608),
+            (3, 0, 3),
+            (3, 6, 2),
+            (3, 6, 7),
+            (3, 6, 5),
+            (0, 0xffffffff, 32),
+            (3 << 13, 1 << 5, 3),
+        ];
+        for (i, (a, b, expected)) in maths.into_iter().enumerate() {
+            assert_eq!(random_math(a, b, i as u32), expected);
+        }
+
+        // epoch 0 的 DAG 开头
+        let cache = LightCache::new(0);
+        assert_eq!(
+            cache.l1_cache[..8],
+            [
+                2492749011, 430724829, 2029256771, 3095580433, 3583790154, 3025086503, 805985885,
+                4121693337
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kawpow_hash() {
+        let cache = LightCache::new(0);
+        let (mix_hash, final_hash) = kawpow_hash_with_cache(&cache, &[0; 32], 0, 0);
+        // cpp-kawpow（Ravencoin 节点使用的 ethash 实现）的测试向量
+        assert_eq!(
+            hex::encode(mix_hash),
+            "6e97b47b134fda0c7888802988e1a373affeb28bcd813b6e9a0fc669c935d03a"
+        );
+        assert_eq!(
+            hex::encode(final_hash),
+            "e601a7257a70dc48fccc97a7330d704d776047623b92883d77111fb36870f3d1"
+        );
+        assert_eq!(kawpow_hash(&[0; 32], 0, 0), (mix_hash, final_hash));
+        assert_eq!(kawpow_final_hash(&[0; 32], 0, &mix_hash), final_hash);
+        assert_ne!(kawpow_hash_with_cache(&cache, &[0; 32], 0, 1).1, final_hash);
+        // 同一 epoch 不同 period 的随机程序不同
+        assert_ne!(kawpow_hash_with_cache(&cache, &[0; 32], 3, 0).0, mix_hash);
+    }
+}
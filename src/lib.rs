@@ -2,8 +2,19 @@ extern crate core;
 
 pub mod address;
 pub mod block_template;
+pub mod consts;
 pub mod diff;
+pub mod error;
+pub mod hashrate;
+pub mod header;
 pub mod job;
-mod merkle;
+pub mod kawpow;
+pub mod merkle;
 mod op_data;
-mod script;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod script;
+pub mod stratum;
+pub mod submit;
+pub mod util;
+pub mod vardiff;
@@ -1,6 +1,15 @@
-use crate::block_template::dsha256;
+use crate::block_template::{dsha256, Dsha256};
+use crate::util::ct_eq;
+use anyhow::{bail, Result};
 use std::collections::VecDeque;
 
+/// 默认的叶子数上限：8MB 的区块按最小交易长度 60 字节计算
+pub const MAX_MERKLE_LEAVES: usize = 8_000_000 / 60;
+
+/// 计算 merkle 根，txids 为内部序
+///
+/// 只有一个叶子时直接返回该叶子，即只含 coinbase 的区块 merkle 根就是 coinbase 的 txid。
+/// 空输入返回 dsha256(b"")，组装区块时叶子总是包含 coinbase，不会走到这个分支
 pub fn merkel_hash(txids: Vec<[u8; 32]>) -> [u8; 32] {
     if txids.is_empty() {
         return dsha256(b"");
@@ -9,7 +18,7 @@ pub fn merkel_hash(txids: Vec<[u8; 32]>) -> [u8; 32] {
         return txids[0];
     }
     let mut txids: VecDeque<_> = txids.into_iter().collect();
-    let mut data = Vec::with_capacity(32 * 2);
+    let mut hasher = Dsha256::default();
     while txids.len() > 1 {
         if txids.len() % 2 == 1 {
             txids.push_back(*txids.back().unwrap());
@@ -18,20 +27,151 @@ pub fn merkel_hash(txids: Vec<[u8; 32]>) -> [u8; 32] {
         while !txids.is_empty() {
             let first = txids.pop_front().unwrap();
             let second = txids.pop_front().unwrap();
-            data.clear();
-            data.extend_from_slice(&first);
-            data.extend_from_slice(&second);
-            let hash = dsha256(&data);
-            tmp_txids.push_back(hash);
+            tmp_txids.push_back(hasher.hash_pair(&first, &second));
         }
         txids = tmp_txids;
     }
     txids.pop_front().unwrap()
 }
 
+/// 同 merkel_hash，每一层的两两哈希并行计算，适合交易很多的区块
+#[cfg(feature = "rayon")]
+pub fn merkle_root_parallel(txids: Vec<[u8; 32]>) -> [u8; 32] {
+    use rayon::prelude::*;
+
+    if txids.is_empty() {
+        return dsha256(b"");
+    }
+    let mut level = txids;
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        level = level
+            .par_chunks(2)
+            .map_init(Dsha256::default, |hasher, pair| {
+                hasher.hash_pair(&pair[0], &pair[1])
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// 同 merkel_hash，但叶子数超过 max_leaves 时报错，用于限制来自外部的输入占用的内存
+pub fn merkel_hash_with_limit(txids: Vec<[u8; 32]>, max_leaves: usize) -> Result<[u8; 32]> {
+    if txids.len() > max_leaves {
+        bail!("too many merkle leaves: {} > {}", txids.len(), max_leaves);
+    }
+    Ok(merkel_hash(txids))
+}
+
+/// 由显示序的 txid hex 计算 merkle 根，结果与 merkel_hash 一致
+///
+/// 解码和反转写入同一个缓冲区，之后逐层在缓冲区内原地合并，不再分配
+pub fn merkle_root_from_hex(txids_be_hex: &[&str]) -> Result<[u8; 32]> {
+    if txids_be_hex.is_empty() {
+        return Ok(dsha256(b""));
+    }
+    let mut level = vec![[0u8; 32]; txids_be_hex.len() + 1];
+    for (txid, s) in level.iter_mut().zip(txids_be_hex) {
+        if hex::decode_to_slice(s, txid).is_err() {
+            bail!("invalid hash {}", s);
+        }
+        txid.reverse();
+    }
+    let mut len = txids_be_hex.len();
+    let mut hasher = Dsha256::default();
+    while len > 1 {
+        // 多分配的一个位置用于奇数个节点时复制最后一个
+        if len % 2 == 1 {
+            level[len] = level[len - 1];
+            len += 1;
+        }
+        for i in 0..len / 2 {
+            level[i] = hasher.hash_pair(&level[2 * i], &level[2 * i + 1]);
+        }
+        len /= 2;
+    }
+    Ok(level[0])
+}
+
+/// 生成 index 处交易的 merkle 证明，返回每一层的兄弟节点及其是否位于右侧
+pub fn merkle_proof(txids: &[[u8; 32]], index: usize) -> Result<Vec<([u8; 32], bool)>> {
+    if index >= txids.len() {
+        bail!("index {} out of range, txids len {}", index, txids.len());
+    }
+    let mut proof = Vec::new();
+    let mut hasher = Dsha256::default();
+    let mut level = txids.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        // 与 merkel_hash 一致：奇数个节点时复制最后一个
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        let sibling_is_right = index.is_multiple_of(2);
+        let sibling = if sibling_is_right {
+            level[index + 1]
+        } else {
+            level[index - 1]
+        };
+        proof.push((sibling, sibling_is_right));
+        level = level
+            .chunks(2)
+            .map(|pair| hasher.hash_pair(&pair[0], &pair[1]))
+            .collect();
+        index /= 2;
+    }
+    Ok(proof)
+}
+
+/// coinbase（下标 0）到根的兄弟节点，txids 不含 coinbase
+///
+/// 兄弟节点与 coinbase 的 txid 无关，每个模板只需计算一次
+pub fn merkle_branches(txids: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    let mut branches = Vec::new();
+    let mut hasher = Dsha256::default();
+    let mut level = txids.to_vec();
+    while !level.is_empty() {
+        branches.push(level[0]);
+        // level 不含下标 0 的节点，偶数个时与其配对后为奇数个，需复制最后一个
+        if level.len().is_multiple_of(2) {
+            level.push(*level.last().unwrap());
+        }
+        level = level[1..]
+            .chunks(2)
+            .map(|pair| hasher.hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    branches
+}
+
+/// 用 merkle_branches 的结果计算新的 coinbase 对应的根，复杂度 O(log n)
+pub fn merkle_root_from_coinbase(coinbase_txid: [u8; 32], branches: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Dsha256::default();
+    branches
+        .iter()
+        .fold(coinbase_txid, |acc, branch| hasher.hash_pair(&acc, branch))
+}
+
+/// 校验 merkle 证明
+pub fn verify_merkle_proof(leaf: [u8; 32], proof: &[([u8; 32], bool)], root: [u8; 32]) -> bool {
+    let mut hasher = Dsha256::default();
+    let hash = proof.iter().fold(leaf, |acc, (sibling, sibling_is_right)| {
+        if *sibling_is_right {
+            hasher.hash_pair(&acc, sibling)
+        } else {
+            hasher.hash_pair(sibling, &acc)
+        }
+    });
+    ct_eq(&hash, &root)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::block_template::dsha256_pair;
+    use crate::util::hash_from_hex;
 
     #[test]
     fn test_merkel_hash() {
@@ -88,4 +228,170 @@ mod test {
         ];
         assert_eq!(hash, hash_exp);
     }
+
+    #[test]
+    fn test_merkel_hash_leaves_from_hex() {
+        // hash_from_hex 与按字节反转解码的结果一致
+        for s in [
+            "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d",
+            "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde",
+        ] {
+            let mut h = hex::decode(s).unwrap();
+            h.reverse();
+            assert_eq!(hash_from_hex(s).unwrap().to_vec(), h);
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_from_hex() {
+        let txids = [
+            "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d",
+            "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17",
+            "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5",
+            "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde",
+        ];
+        for n in 0..=txids.len() {
+            let leaves = txids[..n]
+                .iter()
+                .map(|s| hash_from_hex(s).unwrap())
+                .collect();
+            assert_eq!(
+                merkle_root_from_hex(&txids[..n]).unwrap(),
+                merkel_hash(leaves),
+                "{} txids",
+                n
+            );
+        }
+        assert_eq!(
+            merkle_root_from_hex(&txids).unwrap(),
+            [
+                164, 138, 132, 38, 242, 38, 43, 175, 80, 36, 97, 27, 230, 230, 92, 110, 198, 155,
+                84, 180, 201, 165, 88, 181, 44, 125, 16, 244, 103, 183, 95, 83,
+            ]
+        );
+
+        let hexes: Vec<String> = (0..9u8).map(|i| hex::encode(dsha256(&[i]))).collect();
+        let hexes: Vec<&str> = hexes.iter().map(String::as_str).collect();
+        let leaves = hexes.iter().map(|s| hash_from_hex(s).unwrap()).collect();
+        assert_eq!(merkle_root_from_hex(&hexes).unwrap(), merkel_hash(leaves));
+
+        assert!(merkle_root_from_hex(&["0102"]).is_err());
+        assert!(merkle_root_from_hex(&[&"zz".repeat(32)]).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_merkle_root_parallel() {
+        let txids: Vec<[u8; 32]> = [
+            "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d",
+            "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17",
+            "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5",
+            "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde",
+        ]
+        .iter()
+        .map(|s| hash_from_hex(s).unwrap())
+        .collect();
+        for n in 0..=txids.len() {
+            assert_eq!(
+                merkle_root_parallel(txids[..n].to_vec()),
+                merkel_hash(txids[..n].to_vec())
+            );
+        }
+
+        let txids: Vec<[u8; 32]> = (0..4096u32).map(|i| dsha256(&i.to_le_bytes())).collect();
+        assert_eq!(
+            merkle_root_parallel(txids.clone()),
+            merkel_hash(txids.clone())
+        );
+        assert_eq!(
+            merkle_root_parallel(txids[..4095].to_vec()),
+            merkel_hash(txids[..4095].to_vec())
+        );
+    }
+
+    #[test]
+    fn test_merkel_hash_with_limit() {
+        let coinbase = [7; 32];
+        assert_eq!(merkel_hash(vec![coinbase]), coinbase);
+        assert_eq!(merkel_hash_with_limit(vec![coinbase], 1).unwrap(), coinbase);
+        assert!(merkel_hash_with_limit(vec![coinbase; 3], 2).is_err());
+        assert_eq!(
+            merkel_hash_with_limit(vec![coinbase; 3], MAX_MERKLE_LEAVES).unwrap(),
+            merkel_hash(vec![coinbase; 3])
+        );
+    }
+
+    #[test]
+    fn test_merkle_branches() {
+        let txids: Vec<[u8; 32]> = (0..9u8).map(|i| dsha256(&[i])).collect();
+        for n in 0..txids.len() {
+            let branches = merkle_branches(&txids[..n]);
+            for coinbase in [[1; 32], [2; 32]] {
+                let mut all = vec![coinbase];
+                all.extend_from_slice(&txids[..n]);
+                assert_eq!(
+                    merkle_root_from_coinbase(coinbase, &branches),
+                    merkel_hash(all),
+                    "{} txids",
+                    n
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let txids = vec![
+            "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d",
+            "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17",
+            "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5",
+            "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde",
+        ];
+        let txids: Vec<[u8; 32]> = txids
+            .into_iter()
+            .map(|s| hash_from_hex(s).expect("invalid txid"))
+            .collect();
+        let root = merkel_hash(txids.clone());
+        for (i, txid) in txids.iter().enumerate() {
+            let proof = merkle_proof(&txids, i).unwrap();
+            assert!(verify_merkle_proof(*txid, &proof, root));
+        }
+
+        // odd number
+        let odd = txids[1..].to_vec();
+        let odd_root = merkel_hash(odd.clone());
+        for (i, txid) in odd.iter().enumerate() {
+            let proof = merkle_proof(&odd, i).unwrap();
+            assert!(verify_merkle_proof(*txid, &proof, odd_root));
+        }
+
+        // corrupted sibling
+        let mut proof = merkle_proof(&txids, 2).unwrap();
+        proof[0].0[0] ^= 0xff;
+        assert!(!verify_merkle_proof(txids[2], &proof, root));
+
+        assert!(merkle_proof(&txids, 4).is_err());
+    }
+
+    #[test]
+    fn test_dsha256_pair() {
+        let txids: Vec<_> = [
+            "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d",
+            "ac23877029f22329372c8c9382f22ecdd480b829561c99b4ee28a4bce4b16c17",
+            "5bebb64036b0733ed3230a10dc1e93f8ecae0f324239e5928331b3b4adbc79c5",
+            "784f313ab617c14e08139f0e4257304eda8a82b6d1ed142d0d5d02d8d9772fde",
+        ]
+        .iter()
+        .map(|s| hash_from_hex(s).unwrap())
+        .collect();
+        let mut hasher = Dsha256::default();
+        for a in &txids {
+            for b in &txids {
+                let expect = dsha256(&[a.as_slice(), b.as_slice()].concat());
+                assert_eq!(dsha256_pair(a, b), expect);
+                // 复用的 hasher 每次调用后都已重置
+                assert_eq!(hasher.hash_pair(a, b), expect);
+            }
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
 
 #[derive(Debug, Clone, Default)]
@@ -86,3 +87,51 @@ impl OpData {
         self
     }
 }
+/// 按顺序读取 OpData 写入的数据，越界时报错而不是 panic
+#[derive(Debug, Clone)]
+pub(crate) struct OpReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> OpReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        match self.data.get(self.pos..self.pos.saturating_add(len)) {
+            Some(data) => {
+                self.pos += len;
+                Ok(data)
+            }
+            None => bail!("data truncated at {}", self.pos),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// var_push_num 的逆运算
+    pub fn read_var_num(&mut self) -> Result<u64> {
+        Ok(match self.read_u8()? {
+            0xfd => LittleEndian::read_u16(self.take(2)?) as u64,
+            0xfe => LittleEndian::read_u32(self.take(4)?) as u64,
+            0xff => LittleEndian::read_u64(self.take(8)?),
+            n => n as u64,
+        })
+    }
+
+    pub fn read_var_len(&mut self) -> Result<usize> {
+        Ok(usize::try_from(self.read_var_num()?)?)
+    }
+}
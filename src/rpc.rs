@@ -0,0 +1,300 @@
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+use crate::block_template::BlockTemplateInfo;
+use crate::submit::SubmitResult;
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<RpcError>,
+}
+
+/// 节点 JSON-RPC 客户端
+#[derive(Debug)]
+pub struct RvnRpc {
+    client: reqwest::Client,
+    url: String,
+    user: String,
+    password: String,
+    id: AtomicU64,
+}
+
+impl RvnRpc {
+    pub fn new(url: &str, user: &str, password: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            id: AtomicU64::new(0),
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        // 节点出错时 HTTP 状态码非 200，但 body 中仍然是 JSON-RPC 响应
+        let resp = self
+            .client
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.password))
+            .json(&body)
+            .send()
+            .await?;
+        let status = resp.status();
+        let text = resp.text().await?;
+        let resp: RpcResponse = match serde_json::from_str(&text) {
+            Ok(resp) => resp,
+            Err(_) => bail!("{} failed with http status {}: {}", method, status, text),
+        };
+        if let Some(err) = resp.error {
+            bail!("{} failed: code {}, {}", method, err.code, err.message);
+        }
+        Ok(serde_json::from_value(resp.result)?)
+    }
+
+    pub async fn get_block_template(&self) -> Result<BlockTemplateInfo> {
+        self.call("getblocktemplate", json!([])).await
+    }
+
+    /// 长轮询，直到节点的模板相对 long_poll_id 发生变化才返回
+    pub async fn get_block_template_longpoll(
+        &self,
+        long_poll_id: &str,
+    ) -> Result<BlockTemplateInfo> {
+        self.call("getblocktemplate", json!([{ "longpollid": long_poll_id }]))
+            .await
+    }
+
+    /// 提交区块，节点拒绝时返回其给出的原因
+    pub async fn submit_block(&self, hex: &str) -> Result<()> {
+        match self.submit_block_result(hex).await? {
+            SubmitResult::Accepted => Ok(()),
+            SubmitResult::Duplicate => bail!("submitblock rejected: duplicate"),
+            SubmitResult::Inconclusive => bail!("submitblock rejected: inconclusive"),
+            SubmitResult::Rejected(reason) => bail!("submitblock rejected: {}", reason),
+        }
+    }
+
+    /// 提交区块并返回节点给出的结果
+    pub async fn submit_block_result(&self, hex: &str) -> Result<SubmitResult> {
+        let result: Value = self.call("submitblock", json!([hex])).await?;
+        match result {
+            Value::Null => Ok(SubmitResult::from_rpc(None)),
+            Value::String(reason) => Ok(SubmitResult::from_rpc(Some(&reason))),
+            other => Ok(SubmitResult::Rejected(other.to_string())),
+        }
+    }
+}
+
+/// watch_templates 出错后重试的等待时间，每次失败翻倍直到上限
+const WATCH_MIN_BACKOFF: Duration = Duration::from_millis(500);
+const WATCH_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// 持续长轮询节点，每当 longpollid 变化就把新模板发送到 tx，直到接收端关闭
+///
+/// 第一次请求不带 longpollid，之后带上一个模板的 longpollid；RPC 出错时按退避时间重试，不会返回错误
+pub async fn watch_templates(rpc: &RvnRpc, tx: Sender<BlockTemplateInfo>) -> Result<()> {
+    let mut long_poll_id: Option<String> = None;
+    let mut backoff = WATCH_MIN_BACKOFF;
+    loop {
+        let result = match &long_poll_id {
+            Some(id) => rpc.get_block_template_longpoll(id).await,
+            None => rpc.get_block_template().await,
+        };
+        let info = match result {
+            Ok(info) => info,
+            Err(_) => {
+                if tx.is_closed() {
+                    return Ok(());
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(WATCH_MAX_BACKOFF);
+                continue;
+            }
+        };
+        backoff = WATCH_MIN_BACKOFF;
+        // 长轮询超时时节点可能返回未变化的模板
+        if long_poll_id.as_deref() == Some(info.long_poll_id()) {
+            continue;
+        }
+        long_poll_id = Some(info.long_poll_id().to_string());
+        if tx.send(info).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// 只处理一个请求的 HTTP 服务，返回请求 body
+    fn mock_server(status: &'static str, body: String) -> (String, thread::JoinHandle<String>) {
+        let (url, handle) = mock_server_seq(vec![(status, body)]);
+        (url, thread::spawn(move || handle.join().unwrap().remove(0)))
+    }
+
+    /// 按顺序处理多个请求的 HTTP 服务，每个连接返回一个响应，返回所有请求的 body
+    fn mock_server_seq(
+        responses: Vec<(&'static str, String)>,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|(status, body)| serve_one(&listener, status, &body))
+                .collect()
+        });
+        (url, handle)
+    }
+
+    fn serve_one(listener: &TcpListener, status: &str, body: &str) -> String {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut req = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            req.extend_from_slice(&buf[..n]);
+            let s = String::from_utf8_lossy(&req).to_string();
+            if let Some(pos) = s.find("\r\n\r\n") {
+                let len: usize = s
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse().unwrap())
+                    })
+                    .unwrap_or(0);
+                if req.len() >= pos + 4 + len {
+                    break;
+                }
+            }
+        }
+        let resp = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        stream.write_all(resp.as_bytes()).unwrap();
+        let s = String::from_utf8_lossy(&req).to_string();
+        s[s.find("\r\n\r\n").unwrap() + 4..].to_string()
+    }
+
+    #[tokio::test]
+    async fn test_get_block_template() {
+        let template = include_str!("testdata/block_template.json").trim();
+        let body = format!(r#"{{"result":{},"error":null,"id":0}}"#, template);
+        let (url, handle) = mock_server("200 OK", body);
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        let info = rpc.get_block_template().await.unwrap();
+        assert_eq!(info.height, 2491604);
+        let req: Value = serde_json::from_str(&handle.join().unwrap()).unwrap();
+        assert_eq!(req["method"], "getblocktemplate");
+    }
+
+    #[tokio::test]
+    async fn test_submit_block() {
+        let (url, handle) = mock_server("200 OK", r#"{"result":null,"error":null,"id":0}"#.into());
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        rpc.submit_block("00").await.unwrap();
+        let req: Value = serde_json::from_str(&handle.join().unwrap()).unwrap();
+        assert_eq!(req["params"][0], "00");
+
+        let (url, _) = mock_server(
+            "200 OK",
+            r#"{"result":"high-hash","error":null,"id":0}"#.into(),
+        );
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        let err = rpc.submit_block("00").await.unwrap_err();
+        assert!(err.to_string().contains("high-hash"));
+
+        let (url, _) = mock_server(
+            "200 OK",
+            r#"{"result":"duplicate","error":null,"id":0}"#.into(),
+        );
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        let result = rpc.submit_block_result("00").await.unwrap();
+        assert_eq!(result, SubmitResult::Duplicate);
+
+        let body = r#"{"result":null,"error":{"code":-22,"message":"Block decode failed"},"id":0}"#;
+        let (url, _) = mock_server("500 Internal Server Error", body.into());
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        let err = rpc.submit_block("00").await.unwrap_err();
+        assert!(err.to_string().contains("Block decode failed"));
+    }
+
+    #[tokio::test]
+    async fn test_watch_templates() {
+        let template = include_str!("testdata/block_template.json").trim();
+        let mut info: Value = serde_json::from_str(template).unwrap();
+        let mut response = |longpollid: &str| {
+            info["longpollid"] = longpollid.into();
+            (
+                "200 OK",
+                format!(r#"{{"result":{},"error":null,"id":0}}"#, info),
+            )
+        };
+        let error = r#"{"result":null,"error":{"code":-10,"message":"Ravencoin is downloading blocks..."},"id":0}"#;
+        let (url, handle) = mock_server_seq(vec![
+            ("500 Internal Server Error", error.to_string()),
+            response("a"),
+            // 长轮询超时，longpollid 未变化
+            response("a"),
+            response("b"),
+        ]);
+        let rpc = RvnRpc::new(&url, "user", "pass");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let watcher = async {
+            watch_templates(&rpc, tx).await.unwrap();
+        };
+        let receiver = async {
+            let first = rx.recv().await.unwrap();
+            let second = rx.recv().await.unwrap();
+            drop(rx);
+            (first, second)
+        };
+        let (first, second) = tokio::select! {
+            _ = watcher => panic!("watcher stopped before receiving templates"),
+            received = receiver => received,
+        };
+        assert_eq!(first.long_poll_id(), "a");
+        assert_eq!(second.long_poll_id(), "b");
+        assert_eq!(second.height, 2491604);
+
+        let reqs: Vec<Value> = handle
+            .join()
+            .unwrap()
+            .iter()
+            .map(|req| serde_json::from_str(req).unwrap())
+            .collect();
+        assert_eq!(reqs[0]["params"], serde_json::json!([]));
+        assert_eq!(reqs[1]["params"], serde_json::json!([]));
+        assert_eq!(reqs[2]["params"][0]["longpollid"], "a");
+        assert_eq!(reqs[3]["params"][0]["longpollid"], "a");
+    }
+}
@@ -1,7 +1,36 @@
 use crate::op_data::OpData;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
 
+/// OP_RETURN 数据的默认中继长度上限
+pub const MAX_OP_RETURN_RELAY: usize = 80;
+
+/// 共识规则中 coinbase 脚本的最大长度（含 BIP34 高度）
+pub const MAX_COINBASE_SCRIPT_LEN: usize = 100;
+
+/// 单次直接 push 的最大长度，更长的数据需要 OP_PUSHDATA1
+pub const MAX_DIRECT_PUSH: usize = 75;
+
+/// 按 UTF-8 字符边界把 message 切分为不超过 max_len 字节的片段，
+/// max_len 小于单个字符的长度时该字符单独成为一段
+pub fn split_utf8(message: &str, max_len: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = message;
+    while !rest.is_empty() {
+        let mut len = max_len.min(rest.len());
+        while len > 0 && !rest.is_char_boundary(len) {
+            len -= 1;
+        }
+        if len == 0 {
+            len = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (part, tail) = rest.split_at(len);
+        parts.push(part);
+        rest = tail;
+    }
+    parts
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Script {
     inner: OpData,
@@ -12,18 +41,209 @@ impl Script {
         self.inner.as_slice()
     }
 
-    pub fn coinbase_script(height: u32, arbitrary_data: &str) -> Result<Self> {
-        let mut data = OpData::default();
+    /// BIP34 高度的最小小端编码：只去掉末尾的 0，至少保留 1 字节，
+    /// 最高字节 >= 0x80 时补 0 以免被解释为负数
+    fn bip34_height(height: u32) -> Vec<u8> {
         let mut bip34_height = vec![0; 4];
         LittleEndian::write_u32(&mut bip34_height, height);
-        let bip34_len = bip34_height.iter().position(|i| i == &0).unwrap_or(4);
-        let arbit_data = arbitrary_data.as_bytes();
-        data = data
-            .op_push_slice(&bip34_height[0..bip34_len])
-            .push_u8(0)
-            .op_push_slice(arbit_data);
+        while bip34_height.len() > 1 && bip34_height.last() == Some(&0) {
+            bip34_height.pop();
+        }
+        if bip34_height.last().unwrap() & 0x80 != 0 {
+            bip34_height.push(0);
+        }
+        bip34_height
+    }
+
+    /// 脚本超过 MAX_COINBASE_SCRIPT_LEN 时报错
+    pub fn coinbase_script(height: u32, arbitrary_data: &str) -> Result<Self> {
+        // 空的 segment 即 OP_0，与以往的脚本格式保持一致
+        Self::coinbase_script_multi(height, &[&[], arbitrary_data.as_bytes()])
+    }
+
+    /// BIP34 高度之后依次 push 每个 segment，如可读的矿池标识和合并挖矿的 commitment，
+    /// 脚本超过 MAX_COINBASE_SCRIPT_LEN 时报错
+    pub fn coinbase_script_multi(height: u32, segments: &[&[u8]]) -> Result<Self> {
+        let inner = segments.iter().fold(
+            OpData::default().op_push_slice(&Self::bip34_height(height)),
+            |script, segment| script.op_push_slice(segment),
+        );
+        if inner.as_slice().len() > MAX_COINBASE_SCRIPT_LEN {
+            bail!(
+                "coinbase script too long: {} > {}",
+                inner.as_slice().len(),
+                MAX_COINBASE_SCRIPT_LEN
+            );
+        }
+        Ok(Self { inner })
+    }
+
+    /// truncate 为 true 时截断 arbitrary_data（按字符边界）使脚本不超过 MAX_COINBASE_SCRIPT_LEN，否则报错
+    pub fn coinbase_script_with_limit(
+        height: u32,
+        arbitrary_data: &str,
+        truncate: bool,
+    ) -> Result<Self> {
+        Self::coinbase_script_with_aux(height, &[], arbitrary_data, truncate)
+    }
+
+    /// aux_flags 为节点 coinbaseaux.flags 解码后的字节，原样写在 BIP34 高度之后
+    pub fn coinbase_script_with_aux(
+        height: u32,
+        aux_flags: &[u8],
+        arbitrary_data: &str,
+        truncate: bool,
+    ) -> Result<Self> {
+        let prefix = OpData::default()
+            .op_push_slice(&Self::bip34_height(height))
+            .push_slice(aux_flags)
+            .push_u8(0);
+        let mut arbitrary_data = arbitrary_data;
+        let script_len = |len: usize| prefix.as_slice().len() + push_len(len);
+        if script_len(arbitrary_data.len()) > MAX_COINBASE_SCRIPT_LEN {
+            if !truncate {
+                bail!(
+                    "coinbase script too long: {} > {}",
+                    script_len(arbitrary_data.len()),
+                    MAX_COINBASE_SCRIPT_LEN
+                );
+            }
+            // 高度和 aux_flags 本身已超长时，截断 arbitrary_data 也无济于事
+            if script_len(0) > MAX_COINBASE_SCRIPT_LEN {
+                bail!(
+                    "coinbase script prefix too long: {} > {}",
+                    script_len(0),
+                    MAX_COINBASE_SCRIPT_LEN
+                );
+            }
+            let mut len = arbitrary_data.len();
+            while len > 0
+                && (script_len(len) > MAX_COINBASE_SCRIPT_LEN
+                    || !arbitrary_data.is_char_boundary(len))
+            {
+                len -= 1;
+            }
+            arbitrary_data = &arbitrary_data[..len];
+        }
+        let data = prefix.op_push_slice(arbitrary_data.as_bytes());
         Ok(Self { inner: data })
     }
+
+    /// 与 coinbase_script_with_aux 相同，但 arbitrary_data 按字符边界拆成多个不超过 MAX_DIRECT_PUSH 的 push，
+    /// 不使用 OP_PUSHDATA1；truncate 为 true 时从末尾按字符截断使脚本不超过 MAX_COINBASE_SCRIPT_LEN，否则报错
+    pub fn coinbase_script_split(
+        height: u32,
+        aux_flags: &[u8],
+        arbitrary_data: &str,
+        truncate: bool,
+    ) -> Result<Self> {
+        let prefix = OpData::default()
+            .op_push_slice(&Self::bip34_height(height))
+            .push_slice(aux_flags)
+            .push_u8(0);
+        let script_len = |data: &str| {
+            prefix.as_slice().len()
+                + split_utf8(data, MAX_DIRECT_PUSH)
+                    .iter()
+                    .map(|part| push_len(part.len()))
+                    .sum::<usize>()
+        };
+        let mut data = arbitrary_data;
+        if script_len(data) > MAX_COINBASE_SCRIPT_LEN {
+            if !truncate {
+                bail!(
+                    "coinbase script too long: {} > {}",
+                    script_len(data),
+                    MAX_COINBASE_SCRIPT_LEN
+                );
+            }
+            // 高度和 aux_flags 本身已超长时，截断 arbitrary_data 也无济于事
+            if script_len("") > MAX_COINBASE_SCRIPT_LEN {
+                bail!(
+                    "coinbase script prefix too long: {} > {}",
+                    script_len(""),
+                    MAX_COINBASE_SCRIPT_LEN
+                );
+            }
+            while script_len(data) > MAX_COINBASE_SCRIPT_LEN {
+                let mut chars = data.chars();
+                chars.next_back();
+                data = chars.as_str();
+            }
+        }
+        let inner = split_utf8(data, MAX_DIRECT_PUSH)
+            .into_iter()
+            .fold(prefix, |script, part| script.op_push_slice(part.as_bytes()));
+        Ok(Self { inner })
+    }
+
+    /// 带 extranonce 预留区域的 coinbase 脚本
+    ///
+    /// 依次为 BIP34 高度、extranonce1 + extranonce2_size 个 0（同一次 push）、tail，
+    /// 返回脚本以及 extranonce2 在脚本中的起始偏移
+    pub fn coinbase_script_with_extranonce(
+        height: u32,
+        extranonce1: &[u8],
+        extranonce2_size: usize,
+        tail: &str,
+    ) -> Result<(Self, usize)> {
+        Self::coinbase_script_with_extranonce_and_aux(
+            height,
+            &[],
+            extranonce1,
+            extranonce2_size,
+            tail,
+        )
+    }
+
+    /// 同 coinbase_script_with_extranonce，aux_flags 原样写在 BIP34 高度之后
+    pub fn coinbase_script_with_extranonce_and_aux(
+        height: u32,
+        aux_flags: &[u8],
+        extranonce1: &[u8],
+        extranonce2_size: usize,
+        tail: &str,
+    ) -> Result<(Self, usize)> {
+        let mut extranonce = extranonce1.to_vec();
+        extranonce.resize(extranonce1.len() + extranonce2_size, 0);
+        let data = OpData::default()
+            .op_push_slice(&Self::bip34_height(height))
+            .push_slice(aux_flags)
+            .op_push_slice(&extranonce);
+        let offset = data.as_slice().len() - extranonce2_size;
+        let data = data.op_push_slice(tail.as_bytes());
+        if data.as_slice().len() > MAX_COINBASE_SCRIPT_LEN {
+            bail!(
+                "coinbase script too long: {} > {}",
+                data.as_slice().len(),
+                MAX_COINBASE_SCRIPT_LEN
+            );
+        }
+        Ok((Self { inner: data }, offset))
+    }
+
+    /// OP_RETURN 输出脚本，数据长度不能超过 MAX_OP_RETURN_RELAY
+    pub fn op_return(data: &[u8]) -> Result<Self> {
+        Self::op_return_with_limit(data, MAX_OP_RETURN_RELAY)
+    }
+
+    pub fn op_return_with_limit(data: &[u8], limit: usize) -> Result<Self> {
+        if data.len() > limit {
+            bail!("op_return data too long: {} > {}", data.len(), limit);
+        }
+        let inner = OpData::default().push_u8(0x6a).op_push_slice(data);
+        Ok(Self { inner })
+    }
+}
+
+/// op_push_slice 写入 len 字节数据后的长度
+fn push_len(len: usize) -> usize {
+    match len {
+        n if n < 0x4c => 1 + n,
+        n if n < 0x100 => 2 + n,
+        n if n < 0x10000 => 3 + n,
+        n => 5 + n,
+    }
 }
 
 #[cfg(test)]
@@ -45,4 +265,167 @@ mod test {
         ];
         assert_eq!(expect, script.as_slice().to_vec());
     }
+
+    #[test]
+    fn test_coinbase_script_limit() {
+        let message = "a".repeat(120);
+        assert!(Script::coinbase_script(2491604, &message).is_err());
+        let script = Script::coinbase_script_with_limit(2491604, &message, true).unwrap();
+        assert_eq!(script.as_slice().len(), MAX_COINBASE_SCRIPT_LEN);
+        // 4 字节高度 + OP_0 + OP_PUSHDATA1 和长度
+        assert_eq!(&script.as_slice()[5..7], &[0x4c, 93]);
+
+        // 不截断多字节字符
+        let message = "矿".repeat(40);
+        let script = Script::coinbase_script_with_limit(2491604, &message, true).unwrap();
+        let data = &script.as_slice()[7..];
+        assert_eq!(data.len(), 93);
+        assert!(std::str::from_utf8(data).is_ok());
+
+        let message = "a".repeat(93);
+        assert!(Script::coinbase_script(2491604, &message).is_ok());
+        assert!(Script::coinbase_script_with_extranonce(2491604, &[0; 4], 4, &message).is_err());
+    }
+
+    #[test]
+    fn test_coinbase_script_multi() {
+        let tag = b"/pool/";
+        let commitment = [0xab; 32];
+        let script = Script::coinbase_script_multi(2491604, &[tag, &commitment]).unwrap();
+        let data = script.as_slice();
+        assert_eq!(&data[..4], &[3, 0xd4, 0x04, 0x26]);
+        assert_eq!(data[4], 6);
+        assert_eq!(&data[5..11], tag);
+        assert_eq!(data[11], 32);
+        assert_eq!(&data[12..], &commitment);
+
+        assert_eq!(
+            Script::coinbase_script_multi(2491604, &[&[], b"pool"])
+                .unwrap()
+                .as_slice(),
+            Script::coinbase_script_with_limit(2491604, "pool", false)
+                .unwrap()
+                .as_slice()
+        );
+        // 4 字节高度 + 两次 47 字节的 push，再 push 1 字节正好 100 字节
+        let long = [0; 46];
+        let script = Script::coinbase_script_multi(2491604, &[&long, &long, &[1]]).unwrap();
+        assert_eq!(script.as_slice().len(), MAX_COINBASE_SCRIPT_LEN);
+        assert!(Script::coinbase_script_multi(2491604, &[&long, &long, &[1, 2]]).is_err());
+    }
+
+    #[test]
+    fn test_split_utf8() {
+        assert!(split_utf8("", 75).is_empty());
+        assert_eq!(split_utf8("abcdef", 4), vec!["abcd", "ef"]);
+        // "矿" 为 3 字节
+        assert_eq!(split_utf8("a矿矿", 4), vec!["a矿", "矿"]);
+        assert_eq!(split_utf8("矿", 2), vec!["矿"]);
+    }
+
+    #[test]
+    fn test_coinbase_script_split() {
+        let script = Script::coinbase_script_split(2491604, &[], "pool", false).unwrap();
+        assert_eq!(
+            script.as_slice(),
+            Script::coinbase_script(2491604, "pool").unwrap().as_slice()
+        );
+
+        // 80 字节拆成 75 + 5 两次 push
+        let message = "a".repeat(80);
+        let script = Script::coinbase_script_split(2491604, &[], &message, false).unwrap();
+        let data = script.as_slice();
+        assert_eq!(data.len(), 5 + 76 + 6);
+        assert_eq!(data[5], 75);
+        assert_eq!(data[81], 5);
+
+        // 多字节字符：高度 4 + OP_0 1 之后还剩 95 字节，两次 push 最多 93 字节
+        let message = "矿".repeat(40);
+        assert!(Script::coinbase_script_split(2491604, &[], &message, false).is_err());
+        let script = Script::coinbase_script_split(2491604, &[], &message, true).unwrap();
+        let data = script.as_slice();
+        assert!(data.len() <= MAX_COINBASE_SCRIPT_LEN);
+        // 每段都在字符边界上：75 字节为 25 个字符，剩余 18 字节为 6 个字符
+        assert_eq!(data[5], 75);
+        assert!(std::str::from_utf8(&data[6..81]).is_ok());
+        assert_eq!(data[81], 18);
+        assert!(std::str::from_utf8(&data[82..]).is_ok());
+
+        // aux flags 占用的空间也计入
+        let flags = [0; 10];
+        let script = Script::coinbase_script_split(2491604, &flags, &message, true).unwrap();
+        assert!(script.as_slice().len() <= MAX_COINBASE_SCRIPT_LEN);
+        assert_eq!(&script.as_slice()[4..14], &flags);
+
+        // 仅 aux flags 就超长时报错而不是无限截断
+        let flags = [0; 120];
+        assert!(Script::coinbase_script_split(2491604, &flags, &message, true).is_err());
+        assert!(Script::coinbase_script_split(2491604, &flags, "", true).is_err());
+    }
+
+    #[test]
+    fn test_bip34_height() {
+        assert_eq!(Script::bip34_height(0), vec![0x00]);
+        assert_eq!(Script::bip34_height(255), vec![0xff, 0x00]);
+        assert_eq!(Script::bip34_height(256), vec![0x00, 0x01]);
+        assert_eq!(Script::bip34_height(65535), vec![0xff, 0xff, 0x00]);
+        assert_eq!(Script::bip34_height(2491604), vec![0xd4, 0x04, 0x26]);
+    }
+
+    #[test]
+    fn test_coinbase_script_with_extranonce() {
+        let extranonce1 = [0x11, 0x22, 0x33, 0x44];
+        let (script, offset) =
+            Script::coinbase_script_with_extranonce(2491604, &extranonce1, 4, "pool").unwrap();
+        let data = script.as_slice();
+        // 3 字节高度 push + 8 字节 extranonce push + 4 字节 tail push
+        assert_eq!(data.len(), 4 + 9 + 5);
+        assert_eq!(offset, 4 + 1 + 4);
+        assert_eq!(&data[offset - 4..offset], &extranonce1);
+        assert_eq!(&data[offset..offset + 4], &[0; 4]);
+        assert_eq!(&data[offset + 4..], &[4, b'p', b'o', b'o', b'l']);
+    }
+
+    #[test]
+    fn test_coinbase_script_with_aux() {
+        let flags = [0x06, 0x2f, 0x50, 0x32, 0x53, 0x48, 0x2f];
+        let script = Script::coinbase_script_with_aux(2491604, &flags, "pool", false).unwrap();
+        let data = script.as_slice();
+        assert_eq!(&data[..4], &[3, 0xd4, 0x04, 0x26]);
+        assert_eq!(&data[4..11], &flags);
+        assert_eq!(&data[11..], &[0, 4, b'p', b'o', b'o', b'l']);
+
+        // 仅 aux flags 就超长时即使允许截断也报错
+        let long_flags = [0; 120];
+        assert!(Script::coinbase_script_with_aux(2491604, &long_flags, "pool", true).is_err());
+        assert!(Script::coinbase_script_with_aux(2491604, &long_flags, "", true).is_err());
+        let flags_90 = [0; 90];
+        let script = Script::coinbase_script_with_aux(2491604, &flags_90, "pool", true).unwrap();
+        assert!(script.as_slice().len() <= MAX_COINBASE_SCRIPT_LEN);
+
+        let (script, offset) = Script::coinbase_script_with_extranonce_and_aux(
+            2491604,
+            &flags,
+            &[0x11, 0x22],
+            2,
+            "pool",
+        )
+        .unwrap();
+        let data = script.as_slice();
+        assert_eq!(&data[4..11], &flags);
+        assert_eq!(offset, 4 + 7 + 1 + 2);
+        assert_eq!(&data[offset - 2..offset], &[0x11, 0x22]);
+    }
+
+    #[test]
+    fn test_op_return() {
+        let data = [0xab; 40];
+        let script = Script::op_return(&data).unwrap();
+        assert_eq!(script.as_slice().len(), 42);
+        assert_eq!(&script.as_slice()[0..2], &[0x6a, 40]);
+        assert_eq!(&script.as_slice()[2..], &data);
+
+        assert!(Script::op_return(&[0; 81]).is_err());
+        assert!(Script::op_return_with_limit(&[0; 81], 100).is_ok());
+    }
 }
@@ -0,0 +1,388 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::diff::pool_diff_to_target_hex;
+use crate::util::strip_hex_prefix;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    params: Vec<String>,
+}
+
+fn parse_request(s: &str, method: &str) -> Result<Request> {
+    let req: Request = serde_json::from_str(s)?;
+    if req.method != method {
+        bail!("unexpected method {}, expect {}", req.method, method);
+    }
+    Ok(req)
+}
+
+/// 校验并去掉 0x 前缀
+fn hex_param(name: &str, s: &str, len: usize) -> Result<String> {
+    let s = strip_hex_prefix(s);
+    if s.len() != len || hex::decode(s).is_err() {
+        bail!("invalid {}: {}", name, s);
+    }
+    Ok(s.to_lowercase())
+}
+
+/// 矿池接受的请求方法
+const KNOWN_METHODS: [&str; 3] = ["mining.subscribe", "mining.authorize", "mining.submit"];
+
+/// 矿机发来的请求，params 保留原始的 json 值
+///
+/// - mining.subscribe: [user_agent, session_id]，均可省略
+/// - mining.authorize: [worker, password]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StratumRequest {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+impl StratumRequest {
+    pub fn from_json(s: &str) -> Result<StratumRequest> {
+        let req: StratumRequest = serde_json::from_str(s)?;
+        if !KNOWN_METHODS.contains(&req.method.as_str()) {
+            bail!("unknown method {}", req.method);
+        }
+        Ok(req)
+    }
+
+    fn str_param(&self, method: &str, index: usize) -> Option<&str> {
+        if self.method != method {
+            return None;
+        }
+        self.params.get(index).and_then(Value::as_str)
+    }
+
+    /// mining.subscribe 中矿机的 user agent
+    pub fn user_agent(&self) -> Option<&str> {
+        self.str_param("mining.subscribe", 0)
+    }
+
+    /// mining.authorize 中的矿工名，通常为 address.worker
+    pub fn worker(&self) -> Option<&str> {
+        self.str_param("mining.authorize", 0)
+    }
+
+    /// mining.authorize 中的密码
+    pub fn password(&self) -> Option<&str> {
+        self.str_param("mining.authorize", 1)
+    }
+}
+
+/// 矿机提交的 mining.submit
+///
+/// params 依次为 [worker, job_id, nonce, header_hash, mix_hash]，hex 字段已去掉 0x 前缀
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MiningSubmit {
+    pub id: Value,
+    pub worker: String,
+    pub job_id: String,
+    pub nonce: String,
+    pub header_hash: String,
+    pub mix_hash: String,
+}
+
+impl MiningSubmit {
+    pub fn from_json(s: &str) -> Result<MiningSubmit> {
+        let req = parse_request(s, "mining.submit")?;
+        if req.params.len() != 5 {
+            bail!("invalid mining.submit params len {}", req.params.len());
+        }
+        Ok(MiningSubmit {
+            id: req.id,
+            worker: req.params[0].clone(),
+            job_id: req.params[1].clone(),
+            nonce: hex_param("nonce", &req.params[2], 16)?,
+            header_hash: hex_param("header_hash", &req.params[3], 64)?,
+            mix_hash: hex_param("mix_hash", &req.params[4], 64)?,
+        })
+    }
+}
+
+/// mining.subscribe 分配的 extranonce
+///
+/// extranonce1 由矿池分配，extranonce2 由矿机滚动，二者拼接为完整的 nonce
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Subscription {
+    pub extranonce1: Vec<u8>,
+    pub extranonce2_size: usize,
+}
+
+impl Subscription {
+    pub fn new(extranonce1: Vec<u8>, extranonce2_size: usize) -> Self {
+        Self {
+            extranonce1,
+            extranonce2_size,
+        }
+    }
+
+    /// mining.subscribe 的响应，以 extranonce1 作为订阅 id
+    pub fn to_resp_str(&self, id: &Value) -> String {
+        let extranonce1 = hex::encode(&self.extranonce1);
+        let resp = serde_json::json!({
+            "id": id,
+            "result": [
+                [
+                    ["mining.set_difficulty", extranonce1],
+                    ["mining.notify", extranonce1],
+                ],
+                extranonce1,
+                self.extranonce2_size,
+            ],
+            "error": null,
+        });
+        resp.to_string()
+    }
+
+    /// 拼接矿机提交的 extranonce2，得到完整的 nonce
+    pub fn full_nonce(&self, extranonce2: &[u8]) -> Result<Vec<u8>> {
+        if extranonce2.len() != self.extranonce2_size {
+            bail!(
+                "invalid extranonce2 size {}, expect {}",
+                extranonce2.len(),
+                self.extranonce2_size
+            );
+        }
+        let mut nonce = self.extranonce1.clone();
+        nonce.extend_from_slice(extranonce2);
+        Ok(nonce)
+    }
+}
+
+/// 下发给矿机的 mining.notify
+///
+/// params 依次为 [job_id, header_hash, seed_hash, share_target, refresh, height, bits]
+#[derive(Debug, Clone, Serialize)]
+pub struct MiningNotify<'a> {
+    pub id: Option<u64>,
+    pub method: &'static str,
+    pub params: (&'a str, String, &'a str, &'a str, bool, u32, &'a str),
+}
+
+/// mining.set_target，难度变化时需在新任务之前下发，KawPow 矿机使用目标值
+pub fn set_target_notify(target_hex: &str) -> String {
+    serde_json::json!({
+        "id": null,
+        "method": "mining.set_target",
+        "params": [target_hex],
+    })
+    .to_string()
+}
+
+/// mining.set_difficulty，用于只接受难度数值的矿机
+pub fn set_difficulty_notify(difficulty: u64) -> String {
+    serde_json::json!({
+        "id": null,
+        "method": "mining.set_difficulty",
+        "params": [difficulty],
+    })
+    .to_string()
+}
+
+/// 矿机接受的难度消息，由矿池在 mining.subscribe 时通过 from_user_agent 确定并随连接保存；
+/// 发错消息的矿机会一直使用旧难度，不再提交份额
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum MinerDialect {
+    /// mining.set_target，KawPow 矿机的默认方式
+    #[default]
+    Target,
+    /// mining.set_difficulty
+    Difficulty,
+}
+
+/// 已确认使用 mining.set_target 的矿机 user agent 前缀（小写）
+const TARGET_MINERS: [&str; 1] = ["kawpowminer"];
+
+impl MinerDialect {
+    /// 按 mining.subscribe 的 user agent 确定 dialect，未识别的矿机使用矿池配置的 fallback
+    pub fn from_user_agent(user_agent: Option<&str>, fallback: MinerDialect) -> MinerDialect {
+        let user_agent = match user_agent {
+            Some(ua) => ua.trim().to_ascii_lowercase(),
+            None => return fallback,
+        };
+        if TARGET_MINERS.iter().any(|m| user_agent.starts_with(m)) {
+            MinerDialect::Target
+        } else {
+            fallback
+        }
+    }
+}
+
+/// 按矿机的 dialect 下发矿池难度
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DifficultyNotice {
+    pub difficulty: u64,
+    pub dialect: MinerDialect,
+}
+
+impl DifficultyNotice {
+    pub fn new(difficulty: u64, dialect: MinerDialect) -> Self {
+        Self {
+            difficulty,
+            dialect,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self.dialect {
+            MinerDialect::Target => set_target_notify(&pool_diff_to_target_hex(self.difficulty)),
+            MinerDialect::Difficulty => set_difficulty_notify(self.difficulty),
+        }
+    }
+
+    /// 难度消息和随后的 mining.notify，按顺序发送，新难度从这个任务开始生效
+    pub fn before_notify(&self, notify: String) -> [String; 2] {
+        [self.to_json(), notify]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::job::KawpowNonce;
+    use crate::kawpow::kawpow_hash;
+    use crate::util::decode_hash32;
+
+    #[test]
+    fn test_mining_submit() {
+        // kawpowminer 的提交格式：worker 为 地址.矿机名，nonce、header_hash、mix_hash 均带 0x 前缀；
+        // 哈希取自 cpp-kawpow 的测试向量（高度 0），可以通过 KawPow 校验
+        let s = r#"{"id":4,"method":"mining.submit","params":["RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1","1c9a","0x0000000000000000","0x0000000000000000000000000000000000000000000000000000000000000000","0x6e97b47b134fda0c7888802988e1a373affeb28bcd813b6e9a0fc669c935d03a"]}"#;
+        let submit = MiningSubmit::from_json(s).unwrap();
+        assert_eq!(submit.id, Value::from(4));
+        assert_eq!(submit.worker, "RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1");
+        assert_eq!(submit.job_id, "1c9a");
+        assert_eq!(submit.nonce, "0000000000000000");
+        assert_eq!(submit.header_hash, "0".repeat(64));
+        assert_eq!(
+            submit.mix_hash,
+            "6e97b47b134fda0c7888802988e1a373affeb28bcd813b6e9a0fc669c935d03a"
+        );
+        let nonce = KawpowNonce::from_submit_hex(&submit.nonce).unwrap();
+        let (mix_hash, _) = kawpow_hash(&decode_hash32(&submit.header_hash).unwrap(), 0, nonce.0);
+        assert_eq!(hex::encode(mix_hash), submit.mix_hash);
+
+        // nonce 长度不对
+        let s = r#"{"id":4,"method":"mining.submit","params":["w","1","0x8e5d0c","0x6a6c2a3e8c1d5f9a4e0b7e9d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7980f1e2d3","0x1f2e3d4c5b6a79880f1e2d3c4b5a69788f9e0d1c2b3a49586f7e8d9c0b1a2f3e"]}"#;
+        assert!(MiningSubmit::from_json(s).is_err());
+        let s = r#"{"id":4,"method":"mining.subscribe","params":[]}"#;
+        assert!(MiningSubmit::from_json(s).is_err());
+        // 前缀只去一次，0X 同样接受
+        let s = r#"{"id":4,"method":"mining.submit","params":["w","1","0X0000000000000000","0X0000000000000000000000000000000000000000000000000000000000000000","0X6E97B47B134FDA0C7888802988E1A373AFFEB28BCD813B6E9A0FC669C935D03A"]}"#;
+        let submit = MiningSubmit::from_json(s).unwrap();
+        assert_eq!(submit.nonce, "0000000000000000");
+        assert_eq!(
+            submit.mix_hash,
+            "6e97b47b134fda0c7888802988e1a373affeb28bcd813b6e9a0fc669c935d03a"
+        );
+        let s = r#"{"id":4,"method":"mining.submit","params":["w","1","0x0x00000000000000","0x0000000000000000000000000000000000000000000000000000000000000000","0x6e97b47b134fda0c7888802988e1a373affeb28bcd813b6e9a0fc669c935d03a"]}"#;
+        assert!(MiningSubmit::from_json(s).is_err());
+    }
+
+    #[test]
+    fn test_stratum_request() {
+        let s = r#"{"id":1,"method":"mining.subscribe","params":["kawpowminer/1.2.4",null]}"#;
+        let req = StratumRequest::from_json(s).unwrap();
+        assert_eq!(req.id, Value::from(1));
+        assert_eq!(req.method, "mining.subscribe");
+        assert_eq!(req.user_agent(), Some("kawpowminer/1.2.4"));
+        assert_eq!(req.worker(), None);
+
+        let s = r#"{"id":2,"method":"mining.subscribe","params":[]}"#;
+        assert_eq!(StratumRequest::from_json(s).unwrap().user_agent(), None);
+
+        let s = r#"{"id":3,"method":"mining.authorize","params":["RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1","x"]}"#;
+        let req = StratumRequest::from_json(s).unwrap();
+        assert_eq!(
+            req.worker(),
+            Some("RNs3ne88DoNEnXFTqUrj6zrYejeQpcj4jk.rig1")
+        );
+        assert_eq!(req.password(), Some("x"));
+        assert_eq!(req.user_agent(), None);
+
+        let s = r#"{"id":4,"method":"mining.extranonce.subscribe","params":[]}"#;
+        assert!(StratumRequest::from_json(s).is_err());
+    }
+
+    #[test]
+    fn test_subscription() {
+        let sub = Subscription::new(vec![0x08, 0x00, 0x00, 0x2a], 4);
+        assert_eq!(
+            sub.to_resp_str(&Value::from(1)),
+            r#"{"error":null,"id":1,"result":[[["mining.set_difficulty","0800002a"],["mining.notify","0800002a"]],"0800002a",4]}"#
+        );
+        let nonce = sub.full_nonce(&[0xde, 0xad, 0xbe, 0xef]).unwrap();
+        assert_eq!(hex::encode(nonce), "0800002adeadbeef");
+        assert!(sub.full_nonce(&[0xde, 0xad]).is_err());
+    }
+
+    #[test]
+    fn test_set_target_notify() {
+        let target = "00000000ffff0000000000000000000000000000000000000000000000000000";
+        assert_eq!(
+            set_target_notify(target),
+            r#"{"id":null,"method":"mining.set_target","params":["00000000ffff0000000000000000000000000000000000000000000000000000"]}"#
+        );
+        assert_eq!(
+            set_difficulty_notify(1024),
+            r#"{"id":null,"method":"mining.set_difficulty","params":[1024]}"#
+        );
+    }
+
+    #[test]
+    fn test_difficulty_notice() {
+        let notice = DifficultyNotice::new(1024, MinerDialect::default());
+        assert_eq!(
+            notice.to_json(),
+            set_target_notify(&pool_diff_to_target_hex(1024))
+        );
+        assert_eq!(
+            notice.to_json(),
+            r#"{"id":null,"method":"mining.set_target","params":["00000000003fffc0000000000000000000000000000000000000000000000000"]}"#
+        );
+
+        let notice = DifficultyNotice::new(1024, MinerDialect::Difficulty);
+        assert_eq!(
+            notice.to_json(),
+            r#"{"id":null,"method":"mining.set_difficulty","params":[1024]}"#
+        );
+        let [first, second] = notice.before_notify("notify".to_string());
+        assert_eq!(first, notice.to_json());
+        assert_eq!(second, "notify");
+    }
+
+    #[test]
+    fn test_miner_dialect() {
+        let s = r#"{"id":1,"method":"mining.subscribe","params":["kawpowminer/1.2.4",null]}"#;
+        let req = StratumRequest::from_json(s).unwrap();
+        assert_eq!(
+            MinerDialect::from_user_agent(req.user_agent(), MinerDialect::Difficulty),
+            MinerDialect::Target
+        );
+        assert_eq!(
+            MinerDialect::from_user_agent(Some("KawpowMiner-0.9"), MinerDialect::Difficulty),
+            MinerDialect::Target
+        );
+
+        // 未识别或未提供 user agent 时使用配置
+        for ua in [None, Some(""), Some("someminer/1.0")] {
+            assert_eq!(
+                MinerDialect::from_user_agent(ua, MinerDialect::Difficulty),
+                MinerDialect::Difficulty
+            );
+            assert_eq!(
+                MinerDialect::from_user_agent(ua, MinerDialect::default()),
+                MinerDialect::Target
+            );
+        }
+    }
+}
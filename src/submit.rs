@@ -0,0 +1,55 @@
+/// submitblock 的结果
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SubmitResult {
+    Accepted,
+    /// 节点已有该区块，通常是重复提交，无需告警
+    Duplicate,
+    /// 区块有效但不在主链上，结果待定
+    Inconclusive,
+    Rejected(String),
+}
+
+impl SubmitResult {
+    /// value 为 submitblock 的返回值，成功时为 null
+    pub fn from_rpc(value: Option<&str>) -> SubmitResult {
+        match value {
+            None => SubmitResult::Accepted,
+            Some("duplicate") => SubmitResult::Duplicate,
+            Some("inconclusive") | Some("duplicate-inconclusive") => SubmitResult::Inconclusive,
+            Some(reason) => SubmitResult::Rejected(reason.to_string()),
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        *self == SubmitResult::Accepted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_rpc() {
+        assert_eq!(SubmitResult::from_rpc(None), SubmitResult::Accepted);
+        assert!(SubmitResult::from_rpc(None).is_accepted());
+        assert_eq!(
+            SubmitResult::from_rpc(Some("duplicate")),
+            SubmitResult::Duplicate
+        );
+        assert_eq!(
+            SubmitResult::from_rpc(Some("inconclusive")),
+            SubmitResult::Inconclusive
+        );
+        assert_eq!(
+            SubmitResult::from_rpc(Some("duplicate-inconclusive")),
+            SubmitResult::Inconclusive
+        );
+        for reason in ["bad-cb-amount", "high-hash", "duplicate-invalid"] {
+            assert_eq!(
+                SubmitResult::from_rpc(Some(reason)),
+                SubmitResult::Rejected(reason.to_string())
+            );
+        }
+    }
+}
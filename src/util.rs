@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use subtle::ConstantTimeEq;
+
+/// 反转字节序
+pub fn reverse_bytes(d: &[u8]) -> Vec<u8> {
+    d.iter().rev().copied().collect()
+}
+
+pub fn reverse_hash(mut d: [u8; 32]) -> [u8; 32] {
+    d.reverse();
+    d
+}
+
+/// 去掉一个 0x 或 0X 前缀，只去一次，0x0x 开头的仍会被 hex 解码拒绝
+pub fn strip_hex_prefix(s: &str) -> &str {
+    s.strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s)
+}
+
+/// 反转 hex 字符串的字节序
+pub fn hex_le_to_be(s: &str) -> Result<String> {
+    Ok(hex::encode(reverse_bytes(&hex::decode(s)?)))
+}
+
+/// 解码 64 位 hex 为 32 字节，不改变字节序；长度不对时报错而不是 panic
+pub fn decode_hash32(s: &str) -> Result<[u8; 32]> {
+    let h = hex::decode(s)?;
+    match h.try_into() {
+        Ok(h) => Ok(h),
+        Err(h) => bail!("invalid hash {}: {} bytes, expect 32", s, h.len()),
+    }
+}
+
+/// 解码 64 位 hex 为 32 字节并反转，即显示序转为内部序
+pub fn decode_hash32_reversed(s: &str) -> Result<[u8; 32]> {
+    Ok(reverse_hash(decode_hash32(s)?))
+}
+
+/// 显示序的 hash hex 转为内部序的字节，同 decode_hash32_reversed
+pub fn hash_from_hex(s: &str) -> Result<[u8; 32]> {
+    decode_hash32_reversed(s)
+}
+
+/// 常数时间比较两个 32 字节的哈希，用于比较来自矿机等不可信输入的值，避免通过耗时泄露信息
+pub fn ct_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reverse() {
+        let txid = "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d";
+        let mut hand = hex::decode(txid).unwrap();
+        hand.reverse();
+        assert_eq!(hex_le_to_be(txid).unwrap(), hex::encode(&hand));
+        assert_eq!(hash_from_hex(txid).unwrap().to_vec(), hand);
+        assert_eq!(
+            reverse_hash(hash_from_hex(txid).unwrap()).to_vec(),
+            hex::decode(txid).unwrap()
+        );
+        assert_eq!(reverse_bytes(&[1, 2, 3]), vec![3, 2, 1]);
+
+        assert!(hex_le_to_be("zz").is_err());
+        assert!(hash_from_hex("0102").is_err());
+    }
+
+    #[test]
+    fn test_decode_hash32() {
+        let txid = "ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d";
+        let raw = decode_hash32(txid).unwrap();
+        assert_eq!(raw.to_vec(), hex::decode(txid).unwrap());
+        let reversed = decode_hash32_reversed(txid).unwrap();
+        assert_eq!(reversed.to_vec(), reverse_bytes(&raw));
+        assert_eq!(hash_from_hex(txid).unwrap(), reversed);
+
+        for s in ["", "0102", &txid[..62], &format!("{}00", txid)] {
+            assert!(decode_hash32(s).is_err());
+            assert!(decode_hash32_reversed(s).is_err());
+        }
+        assert!(decode_hash32(&txid.replace('e', "z")).is_err());
+    }
+
+    #[test]
+    fn test_strip_hex_prefix() {
+        assert_eq!(strip_hex_prefix("0xab"), "ab");
+        assert_eq!(strip_hex_prefix("0Xab"), "ab");
+        assert_eq!(strip_hex_prefix("ab"), "ab");
+        assert_eq!(strip_hex_prefix("0x0xab"), "0xab");
+        assert_eq!(strip_hex_prefix(""), "");
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        let a = hash_from_hex("ec2d3ab8906000942dfffc6fb4793e2f95130e41a64fb693c3512119d3a96e8d")
+            .unwrap();
+        let mut cases = vec![(a, a), ([0; 32], [0; 32]), ([0xff; 32], [0; 32])];
+        for i in [0, 15, 31] {
+            let mut b = a;
+            b[i] ^= 1;
+            cases.push((a, b));
+        }
+        for (a, b) in cases {
+            assert_eq!(ct_eq(&a, &b), a == b);
+            assert_eq!(ct_eq(&b, &a), a == b);
+        }
+    }
+}
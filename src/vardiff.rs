@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+
+/// 单次调整时难度最多变化的倍数，避免难度剧烈跳变
+const MAX_RAMP_FACTOR: f64 = 4.0;
+
+/// 可变难度的配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDiffConfig {
+    /// 期望的两次提交之间的间隔（秒）
+    pub target_interval: u32,
+    /// 统计份额的时间窗口（秒）
+    pub retarget_window: u32,
+    pub min_diff: u64,
+    pub max_diff: u64,
+    /// 允许的偏差比例，实际间隔落在 target_interval * (1 ± variance) 内时不调整
+    pub variance: f64,
+}
+
+impl VarDiffConfig {
+    /// 检查配置是否合法
+    pub fn validate(&self) -> Result<()> {
+        if self.min_diff == 0 {
+            bail!("min_diff must be positive");
+        }
+        if self.min_diff > self.max_diff {
+            bail!("min_diff {} > max_diff {}", self.min_diff, self.max_diff);
+        }
+        if self.target_interval == 0 {
+            bail!("target_interval must be positive");
+        }
+        if !(0.0..1.0).contains(&self.variance) {
+            bail!("variance {} out of range [0, 1)", self.variance);
+        }
+        Ok(())
+    }
+}
+
+impl Default for VarDiffConfig {
+    fn default() -> Self {
+        Self {
+            target_interval: 15,
+            retarget_window: 300,
+            min_diff: 1,
+            max_diff: u64::MAX,
+            variance: 0.3,
+        }
+    }
+}
+
+/// 根据矿机的提交频率调整其难度
+#[derive(Debug, Clone)]
+pub struct VarDiff {
+    config: VarDiffConfig,
+    difficulty: u64,
+    shares: VecDeque<u32>,
+    /// 最近一次提交或调整的时间，用于判断矿机是否停止提交
+    last_activity: Option<u32>,
+}
+
+impl VarDiff {
+    pub fn new(config: VarDiffConfig, initial_diff: u64) -> Result<Self> {
+        config.validate()?;
+        let difficulty = initial_diff.clamp(config.min_diff, config.max_diff);
+        Ok(Self {
+            config,
+            difficulty,
+            shares: VecDeque::new(),
+            last_activity: None,
+        })
+    }
+
+    /// 当前生效的难度，可以通过 diff2target 转换后传给 create_job
+    pub fn difficulty(&self) -> u64 {
+        self.difficulty
+    }
+
+    /// 记录一次提交的时间戳，并丢弃窗口外的记录
+    pub fn record_share(&mut self, ts: u32) {
+        self.shares.push_back(ts);
+        self.last_activity = Some(ts);
+        while let Some(first) = self.shares.front() {
+            if ts.saturating_sub(*first) > self.config.retarget_window {
+                self.shares.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// 根据窗口内的提交间隔计算下一个难度，不修改当前状态
+    pub fn next_difficulty(&self) -> u64 {
+        if self.shares.len() < 2 {
+            return self.difficulty;
+        }
+        let first = *self.shares.front().unwrap();
+        let last = *self.shares.back().unwrap();
+        // 时间戳精度为秒，间隔为 0 时按 1 秒处理
+        let span = last.saturating_sub(first).max(1) as f64;
+        let avg_interval = span / (self.shares.len() - 1) as f64;
+        let target = self.config.target_interval as f64;
+        let variance = self.config.variance;
+        if avg_interval >= target * (1.0 - variance) && avg_interval <= target * (1.0 + variance) {
+            return self.difficulty;
+        }
+
+        let ratio = (target / avg_interval).clamp(1.0 / MAX_RAMP_FACTOR, MAX_RAMP_FACTOR);
+        let next = (self.difficulty as f64 * ratio).round();
+        let next = if next >= u64::MAX as f64 {
+            u64::MAX
+        } else {
+            next as u64
+        };
+        next.clamp(self.config.min_diff, self.config.max_diff)
+    }
+
+    /// 应用 next_difficulty，难度发生变化时返回新难度并清空统计
+    pub fn retarget(&mut self) -> Option<u64> {
+        let next = self.next_difficulty();
+        if next == self.difficulty {
+            return None;
+        }
+        self.difficulty = next;
+        self.shares.clear();
+        Some(next)
+    }
+
+    /// 按当前时间调整难度，应由定时器周期调用
+    /// 距上次提交超过 MAX_RAMP_FACTOR 个期望间隔时按最大幅度降低难度，否则同 retarget
+    pub fn retarget_at(&mut self, now: u32) -> Option<u64> {
+        let last = match self.last_activity {
+            Some(last) => last,
+            None => {
+                // 从第一次调用开始计时
+                self.last_activity = Some(now);
+                return self.retarget();
+            }
+        };
+        let idle = now.saturating_sub(last);
+        let target = self.config.target_interval as f64;
+        if (idle as f64) <= target * MAX_RAMP_FACTOR {
+            return self.retarget();
+        }
+
+        let next = ((self.difficulty as f64 / MAX_RAMP_FACTOR).round() as u64)
+            .clamp(self.config.min_diff, self.config.max_diff);
+        // 重新计时，持续空闲时每个周期继续降低
+        self.last_activity = Some(now);
+        self.shares.clear();
+        if next == self.difficulty {
+            return None;
+        }
+        self.difficulty = next;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> VarDiffConfig {
+        VarDiffConfig {
+            target_interval: 10,
+            retarget_window: 300,
+            min_diff: 16,
+            max_diff: 4096,
+            variance: 0.3,
+        }
+    }
+
+    #[test]
+    fn test_vardiff() {
+        // 提交过快，难度上升
+        let mut vardiff = VarDiff::new(config(), 256).unwrap();
+        for i in 0..20 {
+            vardiff.record_share(1000 + i * 2);
+        }
+        assert!(vardiff.next_difficulty() > 256);
+        assert_eq!(vardiff.retarget(), Some(1024));
+        assert_eq!(vardiff.difficulty(), 1024);
+
+        // 提交过慢，难度下降
+        let mut vardiff = VarDiff::new(config(), 256).unwrap();
+        for i in 0..10 {
+            vardiff.record_share(1000 + i * 30);
+        }
+        assert!(vardiff.next_difficulty() < 256);
+
+        // 偏差范围内不调整
+        let mut vardiff = VarDiff::new(config(), 256).unwrap();
+        for i in 0..10 {
+            vardiff.record_share(1000 + i * 11);
+        }
+        assert_eq!(vardiff.retarget(), None);
+
+        // 不超过上下限
+        let mut vardiff = VarDiff::new(config(), 4000).unwrap();
+        for i in 0..20 {
+            vardiff.record_share(1000 + i);
+        }
+        assert_eq!(vardiff.next_difficulty(), 4096);
+        let mut vardiff = VarDiff::new(config(), 20).unwrap();
+        for i in 0..5 {
+            vardiff.record_share(1000 + i * 60);
+        }
+        assert_eq!(vardiff.next_difficulty(), 16);
+    }
+
+    #[test]
+    fn test_vardiff_config() {
+        assert!(VarDiffConfig::default().validate().is_ok());
+        assert!(config().validate().is_ok());
+
+        let mut bad = config();
+        bad.min_diff = 8192;
+        assert!(bad.validate().is_err());
+        assert!(VarDiff::new(bad, 256).is_err());
+
+        let mut bad = config();
+        bad.min_diff = 0;
+        assert!(bad.validate().is_err());
+
+        let mut bad = config();
+        bad.target_interval = 0;
+        assert!(bad.validate().is_err());
+
+        let mut bad = config();
+        bad.variance = 1.0;
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_vardiff_idle() {
+        let mut vardiff = VarDiff::new(config(), 1024).unwrap();
+        // 首次调用只开始计时
+        assert_eq!(vardiff.retarget_at(1000), None);
+        for i in 0..5 {
+            vardiff.record_share(1000 + i * 10);
+        }
+        assert_eq!(vardiff.retarget_at(1045), None);
+
+        // 停止提交后难度逐步下降直到下限
+        assert_eq!(vardiff.retarget_at(1200), Some(256));
+        assert_eq!(vardiff.retarget_at(1300), Some(64));
+        assert_eq!(vardiff.retarget_at(1400), Some(16));
+        assert_eq!(vardiff.retarget_at(1500), None);
+        assert_eq!(vardiff.difficulty(), 16);
+
+        // 从未提交过的矿机同样会降低
+        let mut vardiff = VarDiff::new(config(), 1024).unwrap();
+        assert_eq!(vardiff.retarget_at(1000), None);
+        assert_eq!(vardiff.retarget_at(1040), None);
+        assert_eq!(vardiff.retarget_at(1041), Some(256));
+    }
+}